@@ -35,8 +35,8 @@ pub fn decode_string<R: Read>(
         .map_err(|_| BencodeError::InvalidStringLength)?;
 
     let mut string_bytes = vec![0; length];
-    for i in 0..length {
-        string_bytes[i] = reader
+    for byte in string_bytes.iter_mut() {
+        *byte = reader
             .next()
             .ok_or(BencodeError::UnexpectedEOI)?
             .map_err(|e| BencodeError::Io(e.kind().into()))?;
@@ -296,3 +296,28 @@ fn decode_next<R: Read>(
         ))),
     }
 }
+
+/// Decodes a single bencode value from a reader.
+///
+/// This is the public entry point of the decoder. It wraps the reader in a peekable
+/// byte iterator and dispatches on the first byte to the matching type decoder.
+///
+/// # Arguments
+/// * `reader` - Any source of bencoded bytes (a file, a byte slice, a socket buffer)
+///
+/// # Returns
+/// * `Result<BencodeValue>` - The decoded value, or an error if the input is malformed
+///
+/// # Example
+/// ```
+/// use rs_torrent_client::bencode::{decoder, BencodeValue};
+///
+/// let value = decoder::decode(&b"i42e"[..])?;
+/// assert_eq!(value, BencodeValue::Integer(42));
+/// # Ok::<(), rs_torrent_client::bencode::BencodeError>(())
+/// ```
+#[instrument(skip(reader), level = "debug")]
+pub fn decode<R: Read>(reader: R) -> BencodeResult<BencodeValue> {
+    let mut bytes = io::BufReader::new(reader).bytes().peekable();
+    decode_next(&mut bytes)
+}
@@ -74,12 +74,11 @@ pub enum BencodeError {
 ///
 /// # Examples
 /// ```rust
-/// use crate::bencode::{BencodeResult, BencodeValue};
+/// use rs_torrent_client::bencode::{BencodeResult, BencodeValue};
 ///
 /// fn decode_something() -> BencodeResult<BencodeValue> {
 ///     // Decoding logic here
 ///     Ok(BencodeValue::Integer(42))
 /// }
-///
-
+/// ```
 pub type BencodeResult<T> = std::result::Result<T, BencodeError>;
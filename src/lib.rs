@@ -1,6 +1,8 @@
 //! Library root for rs-torrent-client.
 //!
-//! Re-exports core modules: bencode, torrent, and tracker for use in binaries and other libraries.
+//! Re-exports core modules: bencode, peer, piece, torrent, and tracker for use in binaries and other libraries.
 pub mod bencode;
+pub mod peer;
+pub mod piece;
 pub mod torrent;
 pub mod tracker;
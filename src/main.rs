@@ -1,6 +1,101 @@
 //! Entry point for the rs-torrent-client application.
 //!
-//! This file contains the main function and is responsible for starting the application.
-fn main() {
-    println!("Rs-Torrent-Client Project has successfully compiled and running");
+//! This file parses the command line and dispatches to the requested subcommand.
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use rs_torrent_client::tracker::server::{DEFAULT_MAX_SWARMS, ServerConfig, TrackerServer};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Parser)]
+#[command(name = "rs-torrent-client", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Runs an embedded BitTorrent tracker until interrupted.
+    Tracker {
+        /// Address to serve HTTP `/announce` and `/scrape` on.
+        #[arg(long, default_value = "0.0.0.0:6969")]
+        http: SocketAddr,
+        /// Address to serve the UDP tracker protocol on; disabled if omitted.
+        #[arg(long)]
+        udp: Option<SocketAddr>,
+        /// Announce interval sent to clients, in seconds.
+        #[arg(long, default_value_t = 1800)]
+        interval: u64,
+        /// Minimum time between periodic announces from one peer, in seconds.
+        #[arg(long, default_value_t = 60)]
+        min_interval: u64,
+        /// File with one hex-encoded info hash per line; only these torrents are tracked.
+        #[arg(long)]
+        whitelist: Option<PathBuf>,
+        /// Maximum number of torrents tracked at once.
+        #[arg(long, default_value_t = DEFAULT_MAX_SWARMS)]
+        max_swarms: usize,
+        /// Answer scrapes that name no torrent with the statistics of every torrent.
+        #[arg(long)]
+        full_scrape: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    match Cli::parse().command {
+        Command::Tracker {
+            http,
+            udp,
+            interval,
+            min_interval,
+            whitelist,
+            max_swarms,
+            full_scrape,
+        } => {
+            let mut config = ServerConfig::default()
+                .with_interval(Duration::from_secs(interval))
+                .with_min_interval(Duration::from_secs(min_interval))
+                .with_max_swarms(max_swarms)
+                .with_full_scrape(full_scrape);
+            if let Some(path) = whitelist {
+                config = config.with_whitelist(read_whitelist(&path)?);
+            }
+
+            let server = TrackerServer::new(config);
+            server.bind_http(http).await?;
+            if let Some(udp) = udp {
+                server.bind_udp(udp).await?;
+            }
+            tokio::signal::ctrl_c().await?;
+            tracing::info!("Shutting down tracker");
+        }
+    }
+    Ok(())
+}
+
+/// Reads a whitelist file: one hex-encoded info hash per line, ignoring blank lines and `#` comments.
+fn read_whitelist(path: &Path) -> Result<Vec<[u8; 20]>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read whitelist {}", path.display()))?;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut info_hash = [0u8; 20];
+            hex::decode_to_slice(line, &mut info_hash)
+                .with_context(|| format!("Invalid info hash in whitelist: {}", line))?;
+            Ok(info_hash)
+        })
+        .collect()
 }
@@ -0,0 +1,351 @@
+//! The BitTorrent peer handshake.
+//!
+//! Every peer connection starts with a fixed 68-byte handshake in each direction:
+//! `<pstrlen=19><pstr="BitTorrent protocol"><reserved: 8 bytes><info_hash: 20 bytes><peer_id: 20 bytes>`.
+//! The reserved bytes advertise protocol extensions (see `Extension`), and the info hash tells
+//! the receiving side which torrent the connection is for.
+use crate::torrent::TorrentError;
+use anyhow::Result;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Protocol identifier sent at the start of every handshake.
+pub const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+/// Length of a handshake on the wire.
+pub const HANDSHAKE_LEN: usize = 68;
+/// How long the remote side may take to complete the handshake, unless configured otherwise.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A protocol extension advertised through a reserved bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Extension {
+    /// Extension protocol (BEP 10): reserved byte 5, bit `0x10`.
+    ExtensionProtocol,
+    /// DHT (BEP 5): reserved byte 7, bit `0x01`; the peer sends a `port` message.
+    Dht,
+    /// Fast extension (BEP 6): reserved byte 7, bit `0x04`.
+    Fast,
+}
+
+impl Extension {
+    /// Returns the reserved byte index and bit mask of the extension.
+    fn bit(self) -> (usize, u8) {
+        match self {
+            Extension::ExtensionProtocol => (5, 0x10),
+            Extension::Dht => (7, 0x01),
+            Extension::Fast => (7, 0x04),
+        }
+    }
+}
+
+/// The eight reserved bytes of a handshake, used as capability flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Reserved(pub [u8; 8]);
+
+impl Reserved {
+    /// Returns the flags with `extension` set.
+    pub fn with(mut self, extension: Extension) -> Self {
+        let (byte, mask) = extension.bit();
+        self.0[byte] |= mask;
+        self
+    }
+
+    /// Returns `true` if `extension` is advertised.
+    pub fn supports(&self, extension: Extension) -> bool {
+        let (byte, mask) = extension.bit();
+        self.0[byte] & mask != 0
+    }
+}
+
+/// A parsed or outgoing handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    pub reserved: Reserved,
+    pub info_hash: [u8; 20],
+    pub peer_id: [u8; 20],
+}
+
+impl Handshake {
+    /// Creates a handshake for `info_hash` with no extensions advertised.
+    pub fn new(info_hash: [u8; 20], peer_id: [u8; 20]) -> Self {
+        Self {
+            reserved: Reserved::default(),
+            info_hash,
+            peer_id,
+        }
+    }
+
+    /// Sets the capability flags.
+    pub fn with_reserved(mut self, reserved: Reserved) -> Self {
+        self.reserved = reserved;
+        self
+    }
+
+    /// Serializes the handshake into its 68-byte wire form.
+    pub fn to_bytes(&self) -> [u8; HANDSHAKE_LEN] {
+        let mut bytes = [0u8; HANDSHAKE_LEN];
+        bytes[0] = PROTOCOL.len() as u8;
+        bytes[1..20].copy_from_slice(PROTOCOL);
+        bytes[20..28].copy_from_slice(&self.reserved.0);
+        bytes[28..48].copy_from_slice(&self.info_hash);
+        bytes[48..68].copy_from_slice(&self.peer_id);
+        bytes
+    }
+
+    /**
+    Parses a handshake from its 68-byte wire form.
+
+    # How it works:
+    1. Checks the protocol string length (19) and the protocol string itself.
+    2. Copies out the reserved bytes, info hash and peer ID.
+    3. Returns `HandshakeInvalidProtocol` if the peer does not speak the BitTorrent protocol.
+
+    # Example
+    ```
+    use rs_torrent_client::peer::handshake::{Extension, Handshake, Reserved};
+
+    let ours = Handshake::new([1; 20], [2; 20]).with_reserved(Reserved::default().with(Extension::Fast));
+    let parsed = Handshake::parse(&ours.to_bytes()).unwrap();
+    assert_eq!(parsed, ours);
+    assert!(parsed.reserved.supports(Extension::Fast));
+    ```
+    */
+    pub fn parse(bytes: &[u8; HANDSHAKE_LEN]) -> Result<Self> {
+        if bytes[0] as usize != PROTOCOL.len() || &bytes[1..20] != PROTOCOL {
+            return Err(TorrentError::HandshakeInvalidProtocol.into());
+        }
+        let mut handshake = Handshake::new([0; 20], [0; 20]);
+        handshake.reserved.0.copy_from_slice(&bytes[20..28]);
+        handshake.info_hash.copy_from_slice(&bytes[28..48]);
+        handshake.peer_id.copy_from_slice(&bytes[48..68]);
+        Ok(handshake)
+    }
+}
+
+/// Reads one handshake from the stream.
+async fn read_handshake<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Handshake> {
+    let mut bytes = [0u8; HANDSHAKE_LEN];
+    // The protocol string comes first, so a non-BitTorrent peer is rejected before we wait for more
+    stream.read_exact(&mut bytes[..20]).await?;
+    if bytes[0] as usize != PROTOCOL.len() || &bytes[1..20] != PROTOCOL {
+        return Err(TorrentError::HandshakeInvalidProtocol.into());
+    }
+    stream.read_exact(&mut bytes[20..]).await?;
+    Handshake::parse(&bytes)
+}
+
+/**
+Performs the handshake as the side that opened the connection.
+
+# How it works:
+1. Sends our handshake.
+2. Reads the remote handshake and checks its protocol string.
+3. Checks that the remote side answered for the same torrent (`HandshakeInfoHashMismatch`).
+4. If `expected_peer_id` is given (e.g. from a non-compact tracker response), checks it too
+   (`HandshakePeerIdMismatch`).
+5. Fails with `HandshakeTimeout` if the whole exchange takes longer than `timeout`.
+
+# Returns
+* `Result<Handshake>` - The remote handshake, carrying its peer ID and capability flags
+*/
+pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    ours: &Handshake,
+    expected_peer_id: Option<[u8; 20]>,
+    timeout: Duration,
+) -> Result<Handshake> {
+    let exchange = async {
+        stream.write_all(&ours.to_bytes()).await?;
+        stream.flush().await?;
+        let theirs = read_handshake(stream).await?;
+        if theirs.info_hash != ours.info_hash {
+            return Err(TorrentError::HandshakeInfoHashMismatch.into());
+        }
+        if expected_peer_id.is_some_and(|peer_id| peer_id != theirs.peer_id) {
+            return Err(TorrentError::HandshakePeerIdMismatch.into());
+        }
+        Ok(theirs)
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| TorrentError::HandshakeTimeout)?
+}
+
+/**
+Performs the handshake as the side that accepted the connection.
+
+# How it works:
+1. Reads the remote handshake first, since it names the torrent the connection is for.
+2. Rejects it with `HandshakeInfoHashMismatch` if `is_known` does not recognise the info hash,
+   without revealing anything about ourselves.
+3. Replies with our handshake for that info hash, using `reserved` and `peer_id`.
+4. Fails with `HandshakeTimeout` if the whole exchange takes longer than `timeout`.
+
+# Returns
+* `Result<Handshake>` - The remote handshake
+*/
+pub async fn accept<S, F>(
+    stream: &mut S,
+    reserved: Reserved,
+    peer_id: [u8; 20],
+    is_known: F,
+    timeout: Duration,
+) -> Result<Handshake>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Fn(&[u8; 20]) -> bool,
+{
+    let exchange = async {
+        let theirs = read_handshake(stream).await?;
+        if !is_known(&theirs.info_hash) {
+            return Err(TorrentError::HandshakeInfoHashMismatch.into());
+        }
+        let ours = Handshake::new(theirs.info_hash, peer_id).with_reserved(reserved);
+        stream.write_all(&ours.to_bytes()).await?;
+        stream.flush().await?;
+        Ok(theirs)
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| TorrentError::HandshakeTimeout)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    const INFO_HASH: [u8; 20] = [1; 20];
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn error(result: Result<Handshake>) -> TorrentError {
+        result.unwrap_err().downcast::<TorrentError>().unwrap()
+    }
+
+    #[test]
+    fn reserved_bits_round_trip() {
+        let reserved = Reserved::default()
+            .with(Extension::ExtensionProtocol)
+            .with(Extension::Dht);
+        assert_eq!(reserved.0, [0, 0, 0, 0, 0, 0x10, 0, 0x01]);
+        assert!(reserved.supports(Extension::Dht));
+        assert!(!reserved.supports(Extension::Fast));
+    }
+
+    #[test]
+    fn parse_rejects_other_protocols() {
+        let mut bytes = Handshake::new(INFO_HASH, [2; 20]).to_bytes();
+        bytes[1] = b'b';
+        assert!(matches!(
+            Handshake::parse(&bytes)
+                .unwrap_err()
+                .downcast::<TorrentError>(),
+            Ok(TorrentError::HandshakeInvalidProtocol)
+        ));
+    }
+
+    #[tokio::test]
+    async fn connect_and_accept_exchange_handshakes() {
+        let (mut ours, mut theirs) = duplex(256);
+        let reserved = Reserved::default().with(Extension::Fast);
+        let local = Handshake::new(INFO_HASH, [2; 20]).with_reserved(reserved);
+        let remote = tokio::spawn(async move {
+            let accepted = accept(
+                &mut theirs,
+                Reserved::default().with(Extension::Dht),
+                [3; 20],
+                |info_hash| *info_hash == INFO_HASH,
+                TIMEOUT,
+            )
+            .await;
+            (accepted, theirs)
+        });
+
+        let answer = connect(&mut ours, &local, Some([3; 20]), TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(answer.peer_id, [3; 20]);
+        assert!(answer.reserved.supports(Extension::Dht));
+        let (accepted, _) = remote.await.unwrap();
+        assert_eq!(accepted.unwrap(), local);
+    }
+
+    #[tokio::test]
+    async fn unknown_info_hash_is_refused_silently() {
+        let (mut ours, mut theirs) = duplex(256);
+        let remote = tokio::spawn(async move {
+            let accepted = accept(
+                &mut theirs,
+                Reserved::default(),
+                [3; 20],
+                |_| false,
+                TIMEOUT,
+            )
+            .await;
+            // Dropping the stream closes the connection
+            error(accepted)
+        });
+
+        let local = Handshake::new(INFO_HASH, [2; 20]);
+        let connected = connect(&mut ours, &local, None, TIMEOUT).await;
+        assert!(matches!(
+            remote.await.unwrap(),
+            TorrentError::HandshakeInfoHashMismatch
+        ));
+        // Nothing was sent back before the connection closed
+        assert!(connected.is_err());
+    }
+
+    /// Connects expecting peer ID `[3; 20]` to a peer answering with `info_hash` and `peer_id`.
+    async fn connect_to(info_hash: [u8; 20], peer_id: [u8; 20]) -> Result<Handshake> {
+        let (mut ours, mut theirs) = duplex(256);
+        let remote = tokio::spawn(async move {
+            let mut request = [0u8; HANDSHAKE_LEN];
+            theirs.read_exact(&mut request).await.unwrap();
+            let answer = Handshake::new(info_hash, peer_id);
+            theirs.write_all(&answer.to_bytes()).await.unwrap();
+            theirs
+        });
+        let local = Handshake::new(INFO_HASH, [2; 20]);
+        let result = connect(&mut ours, &local, Some([3; 20]), TIMEOUT).await;
+        let _ = remote.await;
+        result
+    }
+
+    #[tokio::test]
+    async fn connect_checks_the_answer() {
+        assert!(connect_to(INFO_HASH, [3; 20]).await.is_ok());
+        assert!(matches!(
+            error(connect_to([9; 20], [3; 20]).await),
+            TorrentError::HandshakeInfoHashMismatch
+        ));
+        assert!(matches!(
+            error(connect_to(INFO_HASH, [4; 20]).await),
+            TorrentError::HandshakePeerIdMismatch
+        ));
+    }
+
+    #[tokio::test]
+    async fn non_bittorrent_peers_are_rejected_early() {
+        let (mut ours, mut theirs) = duplex(256);
+        // Only the first 20 bytes are needed to tell
+        theirs.write_all(b"GET / HTTP/1.1\r\nHost").await.unwrap();
+        let result = accept(&mut ours, Reserved::default(), [3; 20], |_| true, TIMEOUT).await;
+        assert!(matches!(
+            error(result),
+            TorrentError::HandshakeInvalidProtocol
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_peers_time_out() {
+        let (mut ours, _theirs) = duplex(256);
+        let local = Handshake::new(INFO_HASH, [2; 20]);
+        let result = connect(&mut ours, &local, None, TIMEOUT).await;
+        assert!(matches!(error(result), TorrentError::HandshakeTimeout));
+
+        let (mut ours, _theirs) = duplex(256);
+        let result = accept(&mut ours, Reserved::default(), [3; 20], |_| true, TIMEOUT).await;
+        assert!(matches!(error(result), TorrentError::HandshakeTimeout));
+    }
+}
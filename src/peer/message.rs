@@ -0,0 +1,462 @@
+//! Peer wire messages and their length-prefixed codec.
+//!
+//! After the handshake, peers exchange messages framed as `<length: u32><id: u8><payload>`,
+//! where a zero length is a keep-alive. `MessageCodec` plugs into `tokio_util::codec::Framed`
+//! so a connection can be driven with typed `Message`s, and rejects frames that are oversized
+//! or that refer to pieces and blocks outside the torrent.
+use crate::piece::bitfield::Bitfield;
+use crate::torrent::TorrentError;
+use crate::torrent::file::{PieceGeometry, TorrentFile};
+use anyhow::Result;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Largest block that may be requested or sent; every current client uses 16 KiB blocks.
+pub const MAX_BLOCK_LENGTH: u32 = 16 * 1024;
+
+const ID_CHOKE: u8 = 0;
+const ID_UNCHOKE: u8 = 1;
+const ID_INTERESTED: u8 = 2;
+const ID_NOT_INTERESTED: u8 = 3;
+const ID_HAVE: u8 = 4;
+const ID_BITFIELD: u8 = 5;
+const ID_REQUEST: u8 = 6;
+const ID_PIECE: u8 = 7;
+const ID_CANCEL: u8 = 8;
+const ID_PORT: u8 = 9;
+
+/// A message of the core peer wire protocol (BEP 3, plus the DHT `port` message of BEP 5).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Sent periodically to keep an idle connection open.
+    KeepAlive,
+    /// The sender will not answer requests.
+    Choke,
+    /// The sender will answer requests.
+    Unchoke,
+    /// The sender wants pieces the receiver has.
+    Interested,
+    /// The sender no longer wants anything from the receiver.
+    NotInterested,
+    /// The sender has completed and verified a piece.
+    Have { index: u32 },
+    /// The pieces the sender has, one bit per piece, most significant bit first.
+    Bitfield(Bytes),
+    /// Asks for `length` bytes of piece `index` starting at `begin`.
+    Request { index: u32, begin: u32, length: u32 },
+    /// A block of data answering a request.
+    Piece {
+        index: u32,
+        begin: u32,
+        block: Bytes,
+    },
+    /// Withdraws an earlier request.
+    Cancel { index: u32, begin: u32, length: u32 },
+    /// The sender's DHT port.
+    Port(u16),
+}
+
+impl Message {
+    /// Returns the message ID, or `None` for keep-alives, which have none.
+    pub fn id(&self) -> Option<u8> {
+        match self {
+            Message::KeepAlive => None,
+            Message::Choke => Some(ID_CHOKE),
+            Message::Unchoke => Some(ID_UNCHOKE),
+            Message::Interested => Some(ID_INTERESTED),
+            Message::NotInterested => Some(ID_NOT_INTERESTED),
+            Message::Have { .. } => Some(ID_HAVE),
+            Message::Bitfield(_) => Some(ID_BITFIELD),
+            Message::Request { .. } => Some(ID_REQUEST),
+            Message::Piece { .. } => Some(ID_PIECE),
+            Message::Cancel { .. } => Some(ID_CANCEL),
+            Message::Port(_) => Some(ID_PORT),
+        }
+    }
+
+    /// Returns the length of the message on the wire, excluding the 4-byte length prefix.
+    fn payload_len(&self) -> usize {
+        match self {
+            Message::KeepAlive => 0,
+            Message::Choke | Message::Unchoke | Message::Interested | Message::NotInterested => 1,
+            Message::Have { .. } => 5,
+            Message::Bitfield(bits) => 1 + bits.len(),
+            Message::Request { .. } | Message::Cancel { .. } => 13,
+            Message::Piece { block, .. } => 9 + block.len(),
+            Message::Port(_) => 3,
+        }
+    }
+}
+
+/// Frames and validates peer wire messages for one torrent.
+#[derive(Debug, Clone)]
+pub struct MessageCodec {
+    num_pieces: u32,
+    piece_length: u32,
+    last_piece_length: u32,
+    max_frame_length: usize,
+}
+
+impl MessageCodec {
+    /// Creates a codec for a torrent with the given piece geometry.
+    ///
+    /// The maximum frame length defaults to whichever is larger: a `piece` message carrying a
+    /// full block, or the torrent's bitfield.
+    ///
+    /// # Returns
+    /// * `Result<Self>` - The codec, or `InvalidFormat` if the geometry is inconsistent
+    pub fn new(num_pieces: u32, piece_length: u32, total_length: u64) -> Result<Self> {
+        Ok(Self::with_geometry(PieceGeometry::new(
+            num_pieces,
+            piece_length,
+            total_length,
+        )?))
+    }
+
+    /// Creates a codec using the piece geometry of `torrent`.
+    pub fn for_torrent(torrent: &TorrentFile) -> Result<Self> {
+        Ok(Self::with_geometry(torrent.piece_geometry()?))
+    }
+
+    fn with_geometry(geometry: PieceGeometry) -> Self {
+        let bitfield_frame = 1 + (geometry.num_pieces as usize).div_ceil(8);
+        Self {
+            num_pieces: geometry.num_pieces,
+            piece_length: geometry.piece_length,
+            last_piece_length: geometry.last_piece_length,
+            max_frame_length: bitfield_frame.max(9 + MAX_BLOCK_LENGTH as usize),
+        }
+    }
+
+    /// Overrides the largest frame accepted (excluding the length prefix).
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// Returns the size of piece `index`, or `None` if the torrent has no such piece.
+    fn piece_size(&self, index: u32) -> Option<u32> {
+        match index {
+            i if i >= self.num_pieces => None,
+            i if i + 1 == self.num_pieces => Some(self.last_piece_length),
+            _ => Some(self.piece_length),
+        }
+    }
+
+    /// Checks that a block lies within its piece and is no longer than `MAX_BLOCK_LENGTH`.
+    fn check_block(&self, index: u32, begin: u32, length: u32) -> Result<(), TorrentError> {
+        let piece_size = self
+            .piece_size(index)
+            .ok_or_else(|| invalid(format!("piece index {} out of range", index)))?;
+        if length == 0 || length > MAX_BLOCK_LENGTH {
+            return Err(invalid(format!("invalid block length {}", length)));
+        }
+        if begin as u64 + length as u64 > piece_size as u64 {
+            return Err(invalid(format!(
+                "block {}+{} exceeds piece {} of {} bytes",
+                begin, length, index, piece_size
+            )));
+        }
+        Ok(())
+    }
+
+    /**
+    Parses the body of one frame (everything after the length prefix).
+
+    # How it works:
+    1. An empty body is a keep-alive.
+    2. The first byte is the message ID; each ID has a fixed payload length, except `bitfield`
+       (checked by `Bitfield::from_bytes` against the torrent's piece count) and `piece`
+       (header plus block).
+    3. Piece indexes and block ranges are checked against the torrent.
+    */
+    fn parse(&self, mut frame: BytesMut) -> Result<Message, TorrentError> {
+        if frame.is_empty() {
+            return Ok(Message::KeepAlive);
+        }
+        let id = frame.get_u8();
+        let expect = |len: usize| {
+            if frame.len() == len {
+                Ok(())
+            } else {
+                Err(invalid(format!(
+                    "message {} has a {}-byte payload, expected {}",
+                    id,
+                    frame.len(),
+                    len
+                )))
+            }
+        };
+        let message = match id {
+            ID_CHOKE => expect(0).map(|_| Message::Choke)?,
+            ID_UNCHOKE => expect(0).map(|_| Message::Unchoke)?,
+            ID_INTERESTED => expect(0).map(|_| Message::Interested)?,
+            ID_NOT_INTERESTED => expect(0).map(|_| Message::NotInterested)?,
+            ID_HAVE => {
+                expect(4)?;
+                let index = frame.get_u32();
+                if index >= self.num_pieces {
+                    return Err(invalid(format!("have for piece {} out of range", index)));
+                }
+                Message::Have { index }
+            }
+            ID_BITFIELD => {
+                // `Bitfield` owns the length and spare-bit rules
+                Bitfield::from_bytes(&frame, self.num_pieces as usize).map_err(|e| {
+                    e.downcast::<TorrentError>()
+                        .unwrap_or_else(|e| invalid(e.to_string()))
+                })?;
+                Message::Bitfield(frame.freeze())
+            }
+            ID_REQUEST | ID_CANCEL => {
+                expect(12)?;
+                let (index, begin, length) = (frame.get_u32(), frame.get_u32(), frame.get_u32());
+                self.check_block(index, begin, length)?;
+                if id == ID_REQUEST {
+                    Message::Request {
+                        index,
+                        begin,
+                        length,
+                    }
+                } else {
+                    Message::Cancel {
+                        index,
+                        begin,
+                        length,
+                    }
+                }
+            }
+            ID_PIECE => {
+                if frame.len() < 8 {
+                    return Err(invalid("truncated piece message".to_string()));
+                }
+                let (index, begin) = (frame.get_u32(), frame.get_u32());
+                self.check_block(index, begin, frame.len() as u32)?;
+                Message::Piece {
+                    index,
+                    begin,
+                    block: frame.freeze(),
+                }
+            }
+            ID_PORT => {
+                expect(2)?;
+                Message::Port(frame.get_u16())
+            }
+            id => return Err(invalid(format!("unknown message id {}", id))),
+        };
+        Ok(message)
+    }
+}
+
+fn invalid(reason: String) -> TorrentError {
+    TorrentError::PeerProtocol(reason)
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = TorrentError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, TorrentError> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if length > self.max_frame_length {
+            return Err(invalid(format!(
+                "frame of {} bytes exceeds the {}-byte limit",
+                length, self.max_frame_length
+            )));
+        }
+        if src.len() < 4 + length {
+            src.reserve(4 + length - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        let frame = src.split_to(length);
+        self.parse(frame).map(Some)
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = TorrentError;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), TorrentError> {
+        let length = message.payload_len();
+        dst.reserve(4 + length);
+        dst.put_u32(length as u32);
+        if let Some(id) = message.id() {
+            dst.put_u8(id);
+        }
+        match message {
+            Message::Have { index } => dst.put_u32(index),
+            Message::Bitfield(bits) => dst.put_slice(&bits),
+            Message::Request {
+                index,
+                begin,
+                length,
+            }
+            | Message::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                dst.put_u32(index);
+                dst.put_u32(begin);
+                dst.put_u32(length);
+            }
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                dst.put_u32(index);
+                dst.put_u32(begin);
+                dst.put_slice(&block);
+            }
+            Message::Port(port) => dst.put_u16(port),
+            Message::KeepAlive
+            | Message::Choke
+            | Message::Unchoke
+            | Message::Interested
+            | Message::NotInterested => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3 pieces of 32 KiB, the last one 1000 bytes.
+    fn codec() -> MessageCodec {
+        MessageCodec::new(3, 2 * MAX_BLOCK_LENGTH, 4 * MAX_BLOCK_LENGTH as u64 + 1000).unwrap()
+    }
+
+    fn frame(id: u8, payload: &[u8]) -> BytesMut {
+        let mut frame = BytesMut::new();
+        frame.put_u32(1 + payload.len() as u32);
+        frame.put_u8(id);
+        frame.put_slice(payload);
+        frame
+    }
+
+    fn decode(bytes: &mut BytesMut) -> Result<Option<Message>, TorrentError> {
+        codec().decode(bytes)
+    }
+
+    fn block_payload(index: u32, begin: u32, length: u32) -> Vec<u8> {
+        [index, begin, length]
+            .iter()
+            .flat_map(|n| n.to_be_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            Message::KeepAlive,
+            Message::Choke,
+            Message::Unchoke,
+            Message::Interested,
+            Message::NotInterested,
+            Message::Have { index: 2 },
+            Message::Bitfield(Bytes::from_static(&[0b1010_0000])),
+            Message::Request {
+                index: 0,
+                begin: MAX_BLOCK_LENGTH,
+                length: MAX_BLOCK_LENGTH,
+            },
+            Message::Piece {
+                index: 2,
+                begin: 0,
+                block: Bytes::from(vec![7; 1000]),
+            },
+            Message::Cancel {
+                index: 1,
+                begin: 0,
+                length: 100,
+            },
+            Message::Port(6881),
+        ];
+        let mut codec = codec();
+        let mut bytes = BytesMut::new();
+        for message in &messages {
+            codec.encode(message.clone(), &mut bytes).unwrap();
+        }
+        for message in messages {
+            assert_eq!(codec.decode(&mut bytes).unwrap(), Some(message));
+        }
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn partial_frames_wait_for_more_data() {
+        let mut codec = codec();
+        let mut bytes = BytesMut::new();
+        codec
+            .encode(Message::Have { index: 1 }, &mut bytes)
+            .unwrap();
+        let mut partial = bytes.split_to(6);
+        assert_eq!(codec.decode(&mut partial).unwrap(), None);
+        partial.unsplit(bytes);
+        assert_eq!(
+            codec.decode(&mut partial).unwrap(),
+            Some(Message::Have { index: 1 })
+        );
+    }
+
+    #[test]
+    fn oversize_frames_are_rejected_before_buffering() {
+        let mut bytes = BytesMut::new();
+        bytes.put_u32(10 + MAX_BLOCK_LENGTH);
+        assert!(decode(&mut bytes).is_err());
+
+        let mut codec = codec().with_max_frame_length(4);
+        assert!(codec.decode(&mut frame(ID_HAVE, &[0; 4])).is_err());
+    }
+
+    #[test]
+    fn bitfields_must_match_the_piece_count() {
+        assert!(
+            decode(&mut frame(ID_BITFIELD, &[0b1110_0000]))
+                .unwrap()
+                .is_some()
+        );
+        assert!(decode(&mut frame(ID_BITFIELD, &[0xe0, 0x00])).is_err());
+        assert!(decode(&mut frame(ID_BITFIELD, &[])).is_err());
+        // Bits after piece 2 are spare and must be zero
+        assert!(decode(&mut frame(ID_BITFIELD, &[0b1111_0000])).is_err());
+        assert!(decode(&mut frame(ID_BITFIELD, &[0b0000_0001])).is_err());
+    }
+
+    #[test]
+    fn out_of_range_pieces_and_blocks_are_rejected() {
+        assert!(decode(&mut frame(ID_HAVE, &3u32.to_be_bytes())).is_err());
+        assert!(decode(&mut frame(ID_REQUEST, &block_payload(3, 0, 1))).is_err());
+        // Past the end of the short last piece
+        assert!(decode(&mut frame(ID_REQUEST, &block_payload(2, 0, 1001))).is_err());
+        assert!(decode(&mut frame(ID_REQUEST, &block_payload(2, 0, 1000))).is_ok());
+        // Over the block size limit, or empty
+        let too_long = MAX_BLOCK_LENGTH + 1;
+        assert!(decode(&mut frame(ID_REQUEST, &block_payload(0, 0, too_long))).is_err());
+        assert!(decode(&mut frame(ID_CANCEL, &block_payload(0, 0, 0))).is_err());
+        // Straddling the end of a full piece
+        let begin = 2 * MAX_BLOCK_LENGTH - 10;
+        assert!(decode(&mut frame(ID_CANCEL, &block_payload(0, begin, 20))).is_err());
+
+        let mut piece = 2u32.to_be_bytes().to_vec();
+        piece.extend_from_slice(&999u32.to_be_bytes());
+        piece.extend_from_slice(&[0, 0]);
+        assert!(decode(&mut frame(ID_PIECE, &piece)).is_err());
+        assert!(decode(&mut frame(ID_PIECE, &[0; 7])).is_err());
+    }
+
+    #[test]
+    fn malformed_frames_are_rejected() {
+        assert!(decode(&mut frame(ID_CHOKE, &[0])).is_err());
+        assert!(decode(&mut frame(ID_HAVE, &[0; 3])).is_err());
+        assert!(decode(&mut frame(ID_PORT, &[0; 3])).is_err());
+        assert!(decode(&mut frame(20, &[])).is_err());
+    }
+}
@@ -0,0 +1,9 @@
+//! Peer wire protocol.
+//!
+//! This module implements the TCP protocol spoken between peers, including:
+//! - The 68-byte handshake with reserved-bit capability flags
+//! - Typed core messages and the length-prefixed codec that frames them
+//!
+//! Used by the client to exchange pieces with the peers discovered through trackers.
+pub mod handshake;
+pub mod message;
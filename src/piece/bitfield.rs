@@ -0,0 +1,322 @@
+//! Piece bitfields and swarm-wide availability.
+//!
+//! A `Bitfield` records which pieces one party has, in the wire layout of the `bitfield` message:
+//! one bit per piece, most significant bit of the first byte first. `Availability` counts how many
+//! connected peers have each piece, so the picker can prefer the rarest ones.
+use crate::torrent::TorrentError;
+use crate::torrent::file::TorrentFile;
+use anyhow::Result;
+use bytes::Bytes;
+
+/// The set of pieces held by us or by a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitfield {
+    bits: Vec<u8>,
+    num_pieces: usize,
+}
+
+impl Bitfield {
+    /// Creates an empty bitfield for `num_pieces` pieces.
+    pub fn new(num_pieces: usize) -> Self {
+        Self {
+            bits: vec![0; num_pieces.div_ceil(8)],
+            num_pieces,
+        }
+    }
+
+    /// Creates an empty bitfield sized for `torrent`.
+    pub fn for_torrent(torrent: &TorrentFile) -> Self {
+        Self::new(torrent.num_pieces())
+    }
+
+    /// Creates a bitfield with every piece set, as held by a seed.
+    pub fn full(num_pieces: usize) -> Self {
+        let mut bitfield = Self::new(num_pieces);
+        bitfield.bits.fill(0xff);
+        bitfield.clear_spare_bits();
+        bitfield
+    }
+
+    /**
+    Parses the payload of a `bitfield` message.
+
+    # How it works:
+    1. Checks that the payload is exactly `ceil(num_pieces / 8)` bytes long.
+    2. Checks that the spare bits after the last piece are zero, as BEP 3 requires.
+    3. Returns `PeerProtocol` if either check fails.
+
+    # Example
+    ```
+    use rs_torrent_client::piece::bitfield::Bitfield;
+
+    let bitfield = Bitfield::from_bytes(&[0b1010_0000, 0b1000_0000], 9).unwrap();
+    assert_eq!(bitfield.count(), 3);
+    assert!(bitfield.has(8));
+    assert!(Bitfield::from_bytes(&[0xff, 0xc0], 9).is_err());
+    ```
+    */
+    pub fn from_bytes(bytes: &[u8], num_pieces: usize) -> Result<Self> {
+        if bytes.len() != num_pieces.div_ceil(8) {
+            return Err(TorrentError::PeerProtocol(format!(
+                "bitfield of {} bytes for {} pieces",
+                bytes.len(),
+                num_pieces
+            ))
+            .into());
+        }
+        let bitfield = Self {
+            bits: bytes.to_vec(),
+            num_pieces,
+        };
+        if bitfield.spare_bits() != 0 {
+            return Err(
+                TorrentError::PeerProtocol("bitfield has spare bits set".to_string()).into(),
+            );
+        }
+        Ok(bitfield)
+    }
+
+    /// Returns the wire form, ready to be sent as a `bitfield` message.
+    pub fn to_bytes(&self) -> Bytes {
+        Bytes::copy_from_slice(&self.bits)
+    }
+
+    /// Returns the number of pieces the bitfield covers.
+    pub fn len(&self) -> usize {
+        self.num_pieces
+    }
+
+    /// Returns `true` if the torrent has no pieces.
+    pub fn is_empty(&self) -> bool {
+        self.num_pieces == 0
+    }
+
+    /// Returns `true` if piece `index` is set. Out-of-range indexes are never set.
+    pub fn has(&self, index: usize) -> bool {
+        index < self.num_pieces && self.bits[index / 8] & mask(index) != 0
+    }
+
+    /// Sets piece `index`.
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the piece was not set before; `false` if it was, or is out of range
+    pub fn set(&mut self, index: usize) -> bool {
+        if index >= self.num_pieces || self.has(index) {
+            return false;
+        }
+        self.bits[index / 8] |= mask(index);
+        true
+    }
+
+    /// Clears piece `index`, e.g. after it failed verification.
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the piece was set before
+    pub fn clear(&mut self, index: usize) -> bool {
+        if !self.has(index) {
+            return false;
+        }
+        self.bits[index / 8] &= !mask(index);
+        true
+    }
+
+    /// Returns the number of pieces set.
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Returns `true` if every piece is set.
+    pub fn is_complete(&self) -> bool {
+        self.count() == self.num_pieces
+    }
+
+    /// Iterates over the indexes of the pieces that are set, in ascending order.
+    pub fn pieces(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.num_pieces).filter(|&index| self.has(index))
+    }
+
+    /// Iterates over the indexes of the pieces that are not set, in ascending order.
+    pub fn missing(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.num_pieces).filter(|&index| !self.has(index))
+    }
+
+    /// Returns `true` if `other` has a piece that we do not, i.e. we should be interested in it.
+    pub fn wants_from(&self, other: &Bitfield) -> bool {
+        self.bits
+            .iter()
+            .zip(&other.bits)
+            .any(|(ours, theirs)| theirs & !ours != 0)
+    }
+
+    /// Returns the bits of the last byte that lie beyond the last piece.
+    fn spare_bits(&self) -> u8 {
+        match (self.num_pieces % 8, self.bits.last()) {
+            (0, _) | (_, None) => 0,
+            (used, Some(last)) => last & (0xff >> used),
+        }
+    }
+
+    fn clear_spare_bits(&mut self) {
+        let spare = self.spare_bits();
+        if let Some(last) = self.bits.last_mut() {
+            *last &= !spare;
+        }
+    }
+}
+
+/// Returns the bit mask of piece `index` within its byte.
+fn mask(index: usize) -> u8 {
+    0x80 >> (index % 8)
+}
+
+/**
+How many connected peers have each piece.
+
+# How it works:
+1. When a peer's `bitfield` arrives, `add_peer` increments the count of every piece in it.
+2. Each `have` goes through `have`, which updates the peer's bitfield and only counts pieces
+   the peer did not already announce.
+3. When the peer disconnects, `remove_peer` subtracts its final bitfield again.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Availability {
+    counts: Vec<u32>,
+}
+
+impl Availability {
+    /// Creates counters for `num_pieces` pieces, all zero.
+    pub fn new(num_pieces: usize) -> Self {
+        Self {
+            counts: vec![0; num_pieces],
+        }
+    }
+
+    /// Counts the pieces of a newly received peer bitfield.
+    pub fn add_peer(&mut self, bitfield: &Bitfield) {
+        for index in bitfield.pieces() {
+            if let Some(count) = self.counts.get_mut(index) {
+                *count += 1;
+            }
+        }
+    }
+
+    /// Stops counting the pieces of a disconnected peer.
+    pub fn remove_peer(&mut self, bitfield: &Bitfield) {
+        for index in bitfield.pieces() {
+            if let Some(count) = self.counts.get_mut(index) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Records a `have` message from a peer, setting the piece in the peer's bitfield.
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the peer did not have the piece before
+    pub fn have(&mut self, peer: &mut Bitfield, index: usize) -> bool {
+        if !peer.set(index) {
+            return false;
+        }
+        if let Some(count) = self.counts.get_mut(index) {
+            *count += 1;
+        }
+        true
+    }
+
+    /// Returns how many connected peers have piece `index`.
+    pub fn count(&self, index: usize) -> u32 {
+        self.counts.get(index).copied().unwrap_or(0)
+    }
+
+    /// Returns the number of pieces tracked.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Returns `true` if no pieces are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitfield(num_pieces: usize, pieces: &[usize]) -> Bitfield {
+        let mut bitfield = Bitfield::new(num_pieces);
+        for &index in pieces {
+            bitfield.set(index);
+        }
+        bitfield
+    }
+
+    #[test]
+    fn set_and_clear_report_changes() {
+        let mut bits = Bitfield::new(10);
+        assert!(bits.set(0));
+        assert!(!bits.set(0));
+        assert!(bits.set(9));
+        assert!(!bits.set(10), "out of range");
+        assert_eq!(bits.to_bytes().as_ref(), [0b1000_0000, 0b0100_0000]);
+        assert!(bits.clear(0));
+        assert!(!bits.clear(0));
+        assert_eq!(bits.pieces().collect::<Vec<_>>(), [9]);
+        assert_eq!(bits.missing().count(), 9);
+    }
+
+    #[test]
+    fn full_bitfield_leaves_spare_bits_clear() {
+        let bits = Bitfield::full(10);
+        assert!(bits.is_complete());
+        assert_eq!(bits.to_bytes().as_ref(), [0xff, 0xc0]);
+        assert_eq!(Bitfield::from_bytes(&bits.to_bytes(), 10).unwrap(), bits);
+        assert!(Bitfield::full(0).is_complete());
+    }
+
+    #[test]
+    fn from_bytes_checks_length_and_spare_bits() {
+        assert!(Bitfield::from_bytes(&[0xff], 10).is_err());
+        assert!(Bitfield::from_bytes(&[0xff, 0xc0, 0x00], 10).is_err());
+        assert!(Bitfield::from_bytes(&[0xff, 0xe0], 10).is_err());
+        assert!(Bitfield::from_bytes(&[0xff], 8).is_ok());
+        assert!(Bitfield::from_bytes(&[], 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn interest_follows_missing_pieces() {
+        let ours = bitfield(10, &[0, 1]);
+        assert!(ours.wants_from(&bitfield(10, &[9])));
+        assert!(!ours.wants_from(&bitfield(10, &[1])));
+        assert!(!Bitfield::full(10).wants_from(&Bitfield::full(10)));
+    }
+
+    #[test]
+    fn availability_counts_peers_per_piece() {
+        let mut availability = Availability::new(4);
+        let mut first = bitfield(4, &[0, 1]);
+        let second = bitfield(4, &[1, 3]);
+        availability.add_peer(&first);
+        availability.add_peer(&second);
+        assert_eq!(
+            (0..4).map(|i| availability.count(i)).collect::<Vec<_>>(),
+            [1, 2, 0, 1]
+        );
+
+        // Only new pieces are counted
+        assert!(availability.have(&mut first, 2));
+        assert!(!availability.have(&mut first, 1));
+        assert!(!availability.have(&mut first, 7));
+        assert_eq!(availability.count(2), 1);
+
+        // A leaving peer takes its `have`s with it
+        availability.remove_peer(&first);
+        assert_eq!(
+            (0..4).map(|i| availability.count(i)).collect::<Vec<_>>(),
+            [0, 1, 0, 1]
+        );
+        availability.remove_peer(&first);
+        assert_eq!(availability.count(0), 0, "counts do not underflow");
+        assert_eq!(availability.count(99), 0);
+    }
+}
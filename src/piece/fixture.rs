@@ -0,0 +1,54 @@
+//! Torrents with known content, shared by the piece tests.
+use super::verify::piece_hash;
+use crate::bencode::BencodeValue;
+use crate::torrent::file::TorrentFile;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Deterministic content of `length` bytes.
+pub(crate) fn content(length: usize) -> Vec<u8> {
+    (0..length).map(|i| (i % 251) as u8).collect()
+}
+
+/**
+Builds a torrent whose data is `content` of the files' total length.
+
+A single entry in `files` makes a single-file torrent; more make a directory with files named
+`0`, `1`, ... in that order.
+*/
+pub(crate) fn torrent(piece_length: usize, files: &[usize]) -> TorrentFile {
+    let data = content(files.iter().sum());
+    let pieces = data.chunks(piece_length).flat_map(piece_hash).collect();
+    let mut info = HashMap::from([
+        (b"name".to_vec(), BencodeValue::String(b"fixture".to_vec())),
+        (
+            b"piece length".to_vec(),
+            BencodeValue::Integer(piece_length as i64),
+        ),
+        (b"pieces".to_vec(), BencodeValue::String(pieces)),
+    ]);
+    if let [length] = files {
+        info.insert(b"length".to_vec(), BencodeValue::Integer(*length as i64));
+    } else {
+        let files = files
+            .iter()
+            .enumerate()
+            .map(|(n, &length)| {
+                BencodeValue::Dict(HashMap::from([
+                    (b"length".to_vec(), BencodeValue::Integer(length as i64)),
+                    (
+                        b"path".to_vec(),
+                        BencodeValue::List(vec![BencodeValue::String(n.to_string().into_bytes())]),
+                    ),
+                ]))
+            })
+            .collect();
+        info.insert(b"files".to_vec(), BencodeValue::List(files));
+    }
+    TorrentFile::from_info(BencodeValue::Dict(info), Vec::new()).unwrap()
+}
+
+/// A peer address, distinct for every `n`.
+pub(crate) fn peer(n: u8) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, n], 6881))
+}
@@ -0,0 +1,18 @@
+//! Piece bookkeeping for downloads.
+//!
+//! This module tracks which pieces we and our peers have, including:
+//! - Compact bitfields and swarm-wide piece availability
+//! - Rarest-first piece selection with file and piece priorities
+//! - Adaptive per-peer request pipelining and assembly of blocks into pieces
+//! - Endgame mode, which requests the last blocks from every peer that has them
+//! - SHA-1 verification of completed pieces off the async runtime
+//! - Smart ban of peers whose blocks turn out to be corrupt
+//!
+//! Used by the peer connections to decide what to request and to record what has been downloaded.
+pub mod bitfield;
+#[cfg(test)]
+mod fixture;
+pub mod picker;
+pub mod pipeline;
+pub mod smart_ban;
+pub mod verify;
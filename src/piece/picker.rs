@@ -0,0 +1,386 @@
+//! Piece selection.
+//!
+//! Decides which piece to request next from a given peer. The order of preference is:
+//! 1. Pieces we already started, so partial pieces are completed and can be shared quickly
+//! 2. Higher priority pieces before lower ones; `Skip` pieces are never picked
+//! 3. Until the first few pieces are complete, a random piece, since any piece lets us start trading
+//! 4. Afterwards, the rarest piece in the swarm, with ties broken randomly so peers spread out
+use super::bitfield::{Availability, Bitfield};
+use crate::torrent::TorrentError;
+use crate::torrent::file::TorrentFile;
+use anyhow::Result;
+use rand::Rng;
+use rand::seq::IndexedRandom;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// How many pieces are picked at random before switching to rarest-first, unless configured otherwise.
+pub const DEFAULT_RANDOM_FIRST: usize = 4;
+
+/// Download priority of a file or piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Do not download.
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Chooses the next piece to request, based on priorities and swarm availability.
+#[derive(Debug, Clone)]
+pub struct PiecePicker {
+    /// The pieces each file spans.
+    files: Vec<Range<usize>>,
+    file_priorities: Vec<Priority>,
+    piece_priorities: HashMap<usize, Priority>,
+    /// Effective priority of every piece, derived from the two above.
+    priorities: Vec<Priority>,
+    /// Pieces with some blocks requested or received, but others still to request.
+    partial: HashSet<usize>,
+    /// Pieces with every block requested.
+    requested: HashSet<usize>,
+    random_first: usize,
+}
+
+impl PiecePicker {
+    /// Creates a picker for `torrent` with every file at `Normal` priority.
+    pub fn new(torrent: &TorrentFile) -> Self {
+        let num_pieces = torrent.num_pieces();
+        let piece_length = torrent.info.piece_length.max(1);
+        let lengths: Vec<i64> = if torrent.info.is_directory {
+            torrent.info.files.iter().map(|file| file.length).collect()
+        } else {
+            vec![torrent.total_length()]
+        };
+        let mut offset = 0;
+        let files = lengths
+            .into_iter()
+            .map(|length| {
+                let start = offset;
+                offset += length;
+                if length <= 0 {
+                    return 0..0;
+                }
+                let first = (start / piece_length) as usize;
+                let last = ((offset - 1) / piece_length) as usize;
+                first.min(num_pieces)..(last + 1).min(num_pieces)
+            })
+            .collect::<Vec<_>>();
+        Self {
+            file_priorities: vec![Priority::Normal; files.len()],
+            files,
+            piece_priorities: HashMap::new(),
+            priorities: vec![Priority::Normal; num_pieces],
+            partial: HashSet::new(),
+            requested: HashSet::new(),
+            random_first: DEFAULT_RANDOM_FIRST,
+        }
+    }
+
+    /// Sets how many pieces we must have before switching from random to rarest-first.
+    pub fn with_random_first(mut self, pieces: usize) -> Self {
+        self.random_first = pieces;
+        self
+    }
+
+    /// Sets the priority of every piece of file `index` (in the order of the torrent's file list).
+    ///
+    /// A piece shared by several files gets the highest priority among them, so skipping a file
+    /// never skips data belonging to a wanted neighbour.
+    pub fn set_file_priority(&mut self, index: usize, priority: Priority) -> Result<()> {
+        let Some(slot) = self.file_priorities.get_mut(index) else {
+            return Err(
+                TorrentError::InvalidFormat(format!("File index {} out of range", index)).into(),
+            );
+        };
+        *slot = priority;
+        self.recompute_priorities();
+        Ok(())
+    }
+
+    /// Overrides the priority of a single piece, taking precedence over its files' priorities.
+    pub fn set_piece_priority(&mut self, index: usize, priority: Priority) -> Result<()> {
+        if index >= self.priorities.len() {
+            return Err(
+                TorrentError::InvalidFormat(format!("Piece index {} out of range", index)).into(),
+            );
+        }
+        self.piece_priorities.insert(index, priority);
+        self.priorities[index] = priority;
+        Ok(())
+    }
+
+    /// Returns the effective priority of piece `index`.
+    pub fn priority(&self, index: usize) -> Priority {
+        self.priorities
+            .get(index)
+            .copied()
+            .unwrap_or(Priority::Skip)
+    }
+
+    /// Returns `true` if at least one block of piece `index` has been requested and not all have.
+    pub fn is_partial(&self, index: usize) -> bool {
+        self.partial.contains(&index)
+    }
+
+    /// Returns `true` if every block of piece `index` has been requested.
+    pub fn is_requested(&self, index: usize) -> bool {
+        self.requested.contains(&index)
+    }
+
+    /// Records that some, but not all, blocks of piece `index` have been requested.
+    pub fn mark_partial(&mut self, index: usize) {
+        self.requested.remove(&index);
+        self.partial.insert(index);
+    }
+
+    /// Records that every block of piece `index` has been requested.
+    pub fn mark_requested(&mut self, index: usize) {
+        self.partial.remove(&index);
+        self.requested.insert(index);
+    }
+
+    /// Forgets any progress on piece `index`, once it is complete or has to be downloaded again.
+    pub fn reset(&mut self, index: usize) {
+        self.partial.remove(&index);
+        self.requested.remove(&index);
+    }
+
+    /// Returns `true` if every wanted piece we lack has been fully requested, i.e. nothing is
+    /// left to pick from any peer.
+    pub fn all_requested(&self, ours: &Bitfield) -> bool {
+        ours.missing()
+            .all(|index| self.priority(index) == Priority::Skip || self.requested.contains(&index))
+    }
+
+    /**
+    Picks the next piece to request from a peer.
+
+    # How it works:
+    1. Candidates are pieces the peer has, we do not, that are not `Skip` and not fully requested.
+    2. Partial pieces are preferred over fresh ones, then higher priority over lower.
+    3. Among fresh pieces, while we have fewer than `random_first` pieces any candidate is equally
+       good; afterwards the one held by the fewest peers wins.
+    4. Remaining ties are broken randomly.
+
+    # Arguments
+    * `ours` - The pieces we have
+    * `peer` - The pieces the peer has
+    * `availability` - How many peers have each piece
+
+    # Returns
+    * `Option<usize>` - The piece to request, or `None` if the peer has nothing we want
+    */
+    pub fn pick(
+        &self,
+        ours: &Bitfield,
+        peer: &Bitfield,
+        availability: &Availability,
+    ) -> Option<usize> {
+        self.pick_with_rng(ours, peer, availability, &mut rand::rng())
+    }
+
+    /// Like `pick`, but breaks ties with `rng`, e.g. a seeded one for reproducible choices.
+    pub fn pick_with_rng<R: Rng + ?Sized>(
+        &self,
+        ours: &Bitfield,
+        peer: &Bitfield,
+        availability: &Availability,
+        rng: &mut R,
+    ) -> Option<usize> {
+        let random = ours.count() < self.random_first;
+        let key = |index: usize| {
+            let partial = self.partial.contains(&index);
+            // Larger is better: partial first, then priority, then rarity
+            let rarity = if partial || !random {
+                u32::MAX - availability.count(index)
+            } else {
+                0
+            };
+            (partial, self.priority(index), rarity)
+        };
+
+        let mut best = None;
+        let mut ties = Vec::new();
+        for index in peer.pieces() {
+            if ours.has(index)
+                || self.requested.contains(&index)
+                || self.priority(index) == Priority::Skip
+            {
+                continue;
+            }
+            let candidate = key(index);
+            if best.is_none_or(|best| candidate > best) {
+                best = Some(candidate);
+                ties.clear();
+            }
+            if best == Some(candidate) {
+                ties.push(index);
+            }
+        }
+        ties.choose(rng).copied()
+    }
+
+    /// Recomputes the effective priority of every piece after a file priority change.
+    fn recompute_priorities(&mut self) {
+        self.priorities.fill(Priority::Skip);
+        for (range, &priority) in self.files.iter().zip(&self.file_priorities) {
+            for index in range.clone() {
+                self.priorities[index] = self.priorities[index].max(priority);
+            }
+        }
+        for (&index, &priority) in &self.piece_priorities {
+            self.priorities[index] = priority;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::fixture;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    /// A picker for four pieces of one file, past the random-first phase.
+    fn picker() -> PiecePicker {
+        PiecePicker::new(&fixture::torrent(16, &[64])).with_random_first(0)
+    }
+
+    /// Availability with `counts[i]` peers having piece `i`.
+    fn availability(counts: &[u32]) -> Availability {
+        let mut availability = Availability::new(counts.len());
+        for (index, &count) in counts.iter().enumerate() {
+            for _ in 0..count {
+                let mut peer = Bitfield::new(counts.len());
+                availability.have(&mut peer, index);
+            }
+        }
+        availability
+    }
+
+    fn pick(picker: &PiecePicker, ours: &Bitfield, availability: &Availability) -> Option<usize> {
+        picker.pick(ours, &Bitfield::full(4), availability)
+    }
+
+    #[test]
+    fn rarest_piece_is_picked() {
+        let picker = picker();
+        let ours = Bitfield::new(4);
+        assert_eq!(pick(&picker, &ours, &availability(&[3, 2, 1, 2])), Some(2));
+
+        // Pieces we have or have fully requested are not candidates
+        let mut ours = Bitfield::new(4);
+        ours.set(2);
+        assert_eq!(pick(&picker, &ours, &availability(&[3, 2, 1, 1])), Some(3));
+        let mut picker = picker;
+        picker.mark_requested(3);
+        assert_eq!(pick(&picker, &ours, &availability(&[3, 1, 1, 1])), Some(1));
+        assert_eq!(
+            picker.pick(&ours, &Bitfield::new(4), &availability(&[1; 4])),
+            None
+        );
+    }
+
+    #[test]
+    fn partial_pieces_come_before_fresh_ones() {
+        let mut picker = picker();
+        picker.mark_partial(0);
+        picker.set_piece_priority(3, Priority::High).unwrap();
+        picker.set_piece_priority(0, Priority::Low).unwrap();
+        let ours = Bitfield::new(4);
+        assert_eq!(pick(&picker, &ours, &availability(&[5, 1, 1, 1])), Some(0));
+
+        // Among partial pieces, priority and then rarity still apply
+        picker.mark_partial(1);
+        picker.mark_partial(2);
+        assert_eq!(pick(&picker, &ours, &availability(&[5, 3, 2, 1])), Some(2));
+    }
+
+    #[test]
+    fn priority_comes_before_rarity() {
+        let mut picker = picker();
+        picker.set_piece_priority(1, Priority::High).unwrap();
+        picker.set_piece_priority(0, Priority::Low).unwrap();
+        let ours = Bitfield::new(4);
+        assert_eq!(pick(&picker, &ours, &availability(&[1, 9, 2, 2])), Some(1));
+
+        let mut ours = Bitfield::new(4);
+        ours.set(1);
+        assert_eq!(pick(&picker, &ours, &availability(&[1, 9, 3, 2])), Some(3));
+    }
+
+    #[test]
+    fn random_first_ignores_rarity() {
+        let picker = PiecePicker::new(&fixture::torrent(16, &[64])).with_random_first(2);
+        let availability = availability(&[4, 3, 2, 1]);
+        let picks = |ours: &Bitfield| {
+            let mut picks: Vec<usize> = (0..32)
+                .filter_map(|seed| {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    picker.pick_with_rng(ours, &Bitfield::full(4), &availability, &mut rng)
+                })
+                .collect();
+            picks.sort_unstable();
+            picks.dedup();
+            picks
+        };
+        assert_eq!(picks(&Bitfield::new(4)), [0, 1, 2, 3]);
+
+        // Once we have `random_first` pieces, the rarest one wins
+        let mut ours = Bitfield::new(4);
+        ours.set(0);
+        ours.set(1);
+        assert_eq!(picks(&ours), [3]);
+    }
+
+    #[test]
+    fn skipped_pieces_are_never_picked() {
+        let mut picker = picker();
+        for index in 0..4 {
+            picker.set_piece_priority(index, Priority::Skip).unwrap();
+        }
+        picker.set_piece_priority(2, Priority::Low).unwrap();
+        // Not even a partial piece, once skipped
+        picker.mark_partial(0);
+        let ours = Bitfield::new(4);
+        assert_eq!(pick(&picker, &ours, &availability(&[1, 1, 9, 1])), Some(2));
+        assert!(!picker.all_requested(&ours));
+
+        picker.mark_requested(2);
+        assert_eq!(pick(&picker, &ours, &availability(&[1, 1, 9, 1])), None);
+        assert!(picker.all_requested(&ours));
+    }
+
+    #[test]
+    fn shared_pieces_take_the_highest_file_priority() {
+        // Piece 1 holds the end of file 0 and the start of file 1
+        let mut picker = PiecePicker::new(&fixture::torrent(16, &[24, 24]));
+        picker.set_file_priority(0, Priority::Skip).unwrap();
+        let priorities =
+            |picker: &PiecePicker| (0..3).map(|i| picker.priority(i)).collect::<Vec<_>>();
+        assert_eq!(
+            priorities(&picker),
+            [Priority::Skip, Priority::Normal, Priority::Normal]
+        );
+
+        picker.set_file_priority(1, Priority::Skip).unwrap();
+        assert_eq!(priorities(&picker), [Priority::Skip; 3]);
+
+        picker.set_file_priority(0, Priority::High).unwrap();
+        picker.set_file_priority(1, Priority::Low).unwrap();
+        assert_eq!(
+            priorities(&picker),
+            [Priority::High, Priority::High, Priority::Low]
+        );
+
+        // Piece overrides survive file priority changes
+        picker.set_piece_priority(1, Priority::Skip).unwrap();
+        picker.set_file_priority(1, Priority::Normal).unwrap();
+        assert_eq!(picker.priority(1), Priority::Skip);
+        assert!(picker.set_file_priority(2, Priority::High).is_err());
+        assert!(picker.set_piece_priority(3, Priority::High).is_err());
+    }
+}
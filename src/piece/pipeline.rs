@@ -0,0 +1,922 @@
+//! Block requests and piece assembly.
+//!
+//! Pieces are downloaded as blocks of up to 16 KiB, each fetched with a `request` message. Two
+//! pieces of state cooperate:
+//! - `RequestQueue`, one per peer, holds the requests outstanding on that connection. Its depth
+//!   adapts to the peer's measured throughput and latency, and it reports requests that got stuck.
+//! - `PieceDownloads`, one per torrent, knows which blocks of each piece in progress are missing,
+//!   requested or received, hands out blocks to request, and assembles the received data. Near
+//!   the end of a download it switches to endgame mode and requests the last blocks from every
+//!   peer that has them.
+use super::bitfield::{Availability, Bitfield};
+use super::picker::PiecePicker;
+use super::verify::Verification;
+use crate::peer::message::MAX_BLOCK_LENGTH;
+use crate::torrent::TorrentError;
+use crate::torrent::file::TorrentFile;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Size of every block except possibly the last block of each piece.
+pub const BLOCK_SIZE: u32 = MAX_BLOCK_LENGTH;
+/// Fewest requests kept outstanding on a connection.
+pub const MIN_QUEUE_DEPTH: usize = 2;
+/// Most requests kept outstanding on a connection; many clients drop requests beyond 250.
+pub const MAX_QUEUE_DEPTH: usize = 250;
+/// Requests kept outstanding before any throughput has been measured.
+pub const INITIAL_QUEUE_DEPTH: usize = 4;
+/// How much data, in seconds of transfer at the measured rate, is kept requested beyond the link's latency.
+pub const QUEUE_TIME: Duration = Duration::from_secs(3);
+/// How long a request may stay unanswered before any latency has been measured.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+/// Lower and upper bounds of the adaptive request timeout.
+pub const MIN_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+pub const MAX_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the throughput estimate is refreshed.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// A block of a piece, as named by `request`, `piece` and `cancel` messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Block {
+    pub index: u32,
+    pub begin: u32,
+    pub length: u32,
+}
+
+/// Splits a piece of `piece_size` bytes into blocks; only the last one may be shorter than `BLOCK_SIZE`.
+pub fn blocks_for_piece(index: u32, piece_size: u32) -> Vec<Block> {
+    (0..piece_size.div_ceil(BLOCK_SIZE))
+        .map(|n| {
+            let begin = n * BLOCK_SIZE;
+            Block {
+                index,
+                begin,
+                length: BLOCK_SIZE.min(piece_size - begin),
+            }
+        })
+        .collect()
+}
+
+/**
+The requests outstanding on one peer connection.
+
+# How it works:
+1. Received bytes are counted over one-second windows into a smoothed throughput estimate.
+2. Each answered request yields a latency sample; the smallest one approximates the link's
+   round trip, and a smoothed average (which includes queueing at the peer) drives timeouts.
+3. The queue depth is the number of blocks the peer can deliver in one round trip plus
+   `QUEUE_TIME`, clamped to `MIN_QUEUE_DEPTH..=MAX_QUEUE_DEPTH`.
+4. A request unanswered for twice the smoothed latency is considered stuck; the depth is
+   halved, since the peer is evidently slower than estimated.
+*/
+#[derive(Debug, Clone)]
+pub struct RequestQueue {
+    in_flight: Vec<(Block, Instant)>,
+    depth: usize,
+    rate: Option<f64>,
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    min_rtt: Option<Duration>,
+    srtt: Option<Duration>,
+}
+
+impl Default for RequestQueue {
+    fn default() -> Self {
+        Self {
+            in_flight: Vec::new(),
+            depth: INITIAL_QUEUE_DEPTH,
+            rate: None,
+            window_start: None,
+            window_bytes: 0,
+            min_rtt: None,
+            srtt: None,
+        }
+    }
+}
+
+impl RequestQueue {
+    /// Creates an empty queue with the initial depth.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how many requests should be outstanding.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns how many more requests can be sent now.
+    pub fn slots(&self) -> usize {
+        self.depth.saturating_sub(self.in_flight.len())
+    }
+
+    /// Returns the smoothed download rate from this peer in bytes per second, once measured.
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Returns how long a request may go unanswered before it is considered stuck.
+    pub fn timeout(&self) -> Duration {
+        self.srtt
+            .map(|srtt| (srtt * 2).clamp(MIN_REQUEST_TIMEOUT, MAX_REQUEST_TIMEOUT))
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    /// Returns the outstanding requests, oldest first.
+    pub fn in_flight(&self) -> impl Iterator<Item = &Block> {
+        self.in_flight.iter().map(|(block, _)| block)
+    }
+
+    /// Returns `true` if `block` is outstanding on this connection.
+    pub fn contains(&self, block: &Block) -> bool {
+        self.in_flight.iter().any(|(pending, _)| pending == block)
+    }
+
+    /// Records that a `request` for `block` was sent.
+    pub fn on_request(&mut self, block: Block, now: Instant) {
+        self.in_flight.push((block, now));
+    }
+
+    /// Records a received block, updating the throughput and latency estimates.
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the block had been requested on this connection
+    pub fn on_block(&mut self, block: &Block, now: Instant) -> bool {
+        let Some(position) = self
+            .in_flight
+            .iter()
+            .position(|(pending, _)| pending == block)
+        else {
+            return false;
+        };
+        let (_, sent) = self.in_flight.remove(position);
+        let rtt = now.saturating_duration_since(sent);
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min| min.min(rtt)));
+        self.srtt = Some(self.srtt.map_or(rtt, |srtt| (srtt * 7 + rtt) / 8));
+
+        let window_start = *self.window_start.get_or_insert(sent);
+        self.window_bytes += block.length as u64;
+        let elapsed = now.saturating_duration_since(window_start);
+        if elapsed >= RATE_WINDOW {
+            let sample = self.window_bytes as f64 / elapsed.as_secs_f64();
+            self.rate = Some(self.rate.map_or(sample, |rate| rate * 0.7 + sample * 0.3));
+            self.window_start = Some(now);
+            self.window_bytes = 0;
+            self.update_depth();
+        }
+        true
+    }
+
+    /// Withdraws the request for `block`, e.g. before sending a `cancel` for it.
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the block was outstanding, i.e. a `cancel` should be sent
+    pub fn on_cancel(&mut self, block: &Block) -> bool {
+        let before = self.in_flight.len();
+        self.in_flight.retain(|(pending, _)| pending != block);
+        self.in_flight.len() != before
+    }
+
+    /// Removes and returns the requests that have been outstanding longer than `timeout`.
+    ///
+    /// The caller should release them so they can be requested again, from this or another peer.
+    pub fn timed_out(&mut self, now: Instant) -> Vec<Block> {
+        let timeout = self.timeout();
+        let (stuck, pending): (Vec<_>, Vec<_>) = self
+            .in_flight
+            .drain(..)
+            .partition(|(_, sent)| now.saturating_duration_since(*sent) > timeout);
+        self.in_flight = pending;
+        if !stuck.is_empty() {
+            self.depth = (self.depth / 2).max(MIN_QUEUE_DEPTH);
+        }
+        stuck.into_iter().map(|(block, _)| block).collect()
+    }
+
+    /// Removes and returns every outstanding request, e.g. when the peer chokes us or disconnects.
+    pub fn clear(&mut self) -> Vec<Block> {
+        self.in_flight.drain(..).map(|(block, _)| block).collect()
+    }
+
+    fn update_depth(&mut self) {
+        let (Some(rate), Some(min_rtt)) = (self.rate, self.min_rtt) else {
+            return;
+        };
+        let bytes = rate * (min_rtt + QUEUE_TIME).as_secs_f64();
+        self.depth =
+            ((bytes / BLOCK_SIZE as f64).ceil() as usize).clamp(MIN_QUEUE_DEPTH, MAX_QUEUE_DEPTH);
+    }
+}
+
+/// Download state of one block of a piece in progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BlockState {
+    Missing,
+    /// Requested from these peers; more than one only in endgame mode.
+    Requested(Vec<SocketAddr>),
+    /// Received from this peer.
+    Received(SocketAddr),
+}
+
+/// A piece being downloaded.
+#[derive(Debug, Clone)]
+struct PartialPiece {
+    data: Vec<u8>,
+    blocks: Vec<BlockState>,
+    received: usize,
+}
+
+impl PartialPiece {
+    fn new(size: u32) -> Self {
+        Self {
+            data: vec![0; size as usize],
+            blocks: vec![BlockState::Missing; size.div_ceil(BLOCK_SIZE) as usize],
+            received: 0,
+        }
+    }
+
+    fn has_missing(&self) -> bool {
+        self.blocks.contains(&BlockState::Missing)
+    }
+
+    /// Returns the `n`th block of the piece.
+    fn block(&self, index: u32, n: usize) -> Block {
+        let begin = n as u32 * BLOCK_SIZE;
+        Block {
+            index,
+            begin,
+            length: BLOCK_SIZE.min(self.data.len() as u32 - begin),
+        }
+    }
+}
+
+/// A fully downloaded, not yet verified piece.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletedPiece {
+    pub index: u32,
+    pub data: Vec<u8>,
+    /// The peer each block came from, in block order.
+    pub contributors: Vec<SocketAddr>,
+}
+
+impl CompletedPiece {
+    /// Returns the distinct peers that sent blocks of this piece.
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        let mut peers = self.contributors.clone();
+        peers.sort_unstable();
+        peers.dedup();
+        peers
+    }
+}
+
+/// What to do after a block was received.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockReceived {
+    /// Other peers the block was also requested from (in endgame mode); send them a `cancel`.
+    pub cancels: Vec<SocketAddr>,
+    /// The assembled piece, once every block has arrived.
+    pub piece: Option<CompletedPiece>,
+}
+
+/// A snapshot of download progress, reported in torrent statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadStats {
+    /// Pieces with at least one block requested or received.
+    pub pieces_in_progress: usize,
+    /// Completed pieces waiting for their hash check.
+    pub pieces_verifying: usize,
+    /// Blocks with at least one outstanding request.
+    pub blocks_requested: usize,
+    /// Outstanding requests beyond the first for the same block, made in endgame mode.
+    pub duplicate_requests: usize,
+    /// Blocks received for pieces still in progress.
+    pub blocks_received: usize,
+    /// Whether every remaining block has been requested and requests are being duplicated.
+    pub endgame: bool,
+}
+
+/**
+The pieces being downloaded for one torrent, at block granularity.
+
+# Endgame mode
+Normally each block is requested from one peer only. Once every block we still need has been
+requested, the download can only finish as fast as the slowest of those peers, so the
+remaining blocks are also requested from every other peer that has them. The first copy to
+arrive wins and the other requests are cancelled.
+*/
+#[derive(Debug, Clone)]
+pub struct PieceDownloads {
+    picker: PiecePicker,
+    pieces: HashMap<u32, PartialPiece>,
+    /// Completed pieces handed out for verification, whose result has not been reported yet.
+    verifying: HashSet<u32>,
+    num_pieces: u32,
+    piece_length: u32,
+    last_piece_length: u32,
+    endgame: bool,
+}
+
+impl PieceDownloads {
+    /// Creates the download state for `torrent`, choosing pieces with `picker`.
+    ///
+    /// Fails with `InvalidFormat` if the torrent's piece geometry is inconsistent.
+    pub fn new(torrent: &TorrentFile, picker: PiecePicker) -> Result<Self> {
+        let geometry = torrent.piece_geometry()?;
+        Ok(Self {
+            picker,
+            pieces: HashMap::new(),
+            verifying: HashSet::new(),
+            num_pieces: geometry.num_pieces,
+            piece_length: geometry.piece_length,
+            last_piece_length: geometry.last_piece_length,
+            endgame: false,
+        })
+    }
+
+    /// Returns the piece picker, e.g. to change priorities.
+    pub fn picker(&self) -> &PiecePicker {
+        &self.picker
+    }
+
+    /// Returns the piece picker for modification.
+    pub fn picker_mut(&mut self) -> &mut PiecePicker {
+        &mut self.picker
+    }
+
+    /// Returns the size of piece `index`; the last piece may be shorter than the others.
+    pub fn piece_size(&self, index: u32) -> Option<u32> {
+        match index {
+            i if i >= self.num_pieces => None,
+            i if i + 1 == self.num_pieces => Some(self.last_piece_length),
+            _ => Some(self.piece_length),
+        }
+    }
+
+    /// Returns the indexes of the pieces in progress.
+    pub fn in_progress(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pieces.keys().copied()
+    }
+
+    /// Returns `true` if the download is in endgame mode.
+    pub fn is_endgame(&self) -> bool {
+        self.endgame
+    }
+
+    /// Returns a snapshot of the download progress.
+    pub fn stats(&self) -> DownloadStats {
+        let mut stats = DownloadStats {
+            pieces_in_progress: self.pieces.len(),
+            pieces_verifying: self.verifying.len(),
+            endgame: self.endgame,
+            ..Default::default()
+        };
+        for state in self.pieces.values().flat_map(|piece| &piece.blocks) {
+            match state {
+                BlockState::Missing => {}
+                BlockState::Requested(peers) => {
+                    stats.blocks_requested += 1;
+                    stats.duplicate_requests += peers.len().saturating_sub(1);
+                }
+                BlockState::Received(_) => stats.blocks_received += 1,
+            }
+        }
+        stats
+    }
+
+    /**
+    Chooses the blocks to request next from a peer, filling its queue.
+
+    # How it works:
+    1. Asks the picker for a piece the peer has (partial pieces come first).
+    2. Takes the missing blocks of that piece in order, up to the queue's free slots, and marks
+       them requested from `addr`.
+    3. Tells the picker whether the piece is now partial or fully requested, and repeats until
+       the queue is full or the peer has nothing more we want.
+    4. If the picker ran out because every wanted block is requested, enters endgame mode and
+       fills the remaining slots with blocks requested from other peers, those with the fewest
+       requests first.
+
+    # Returns
+    * `Vec<Block>` - The blocks to send `request` messages for; they are already in `queue`
+    */
+    pub fn request_blocks(
+        &mut self,
+        addr: SocketAddr,
+        ours: &Bitfield,
+        peer: &Bitfield,
+        availability: &Availability,
+        queue: &mut RequestQueue,
+        now: Instant,
+    ) -> Vec<Block> {
+        let mut requests = Vec::new();
+        while queue.slots() > 0 {
+            let Some(index) = self.picker.pick(ours, peer, availability) else {
+                self.endgame = !self.pieces.is_empty() && self.picker.all_requested(ours);
+                break;
+            };
+            let index = index as u32;
+            let Some(size) = self.piece_size(index) else {
+                break;
+            };
+            let piece = self
+                .pieces
+                .entry(index)
+                .or_insert_with(|| PartialPiece::new(size));
+            for n in 0..piece.blocks.len() {
+                if queue.slots() == 0 {
+                    break;
+                }
+                if piece.blocks[n] != BlockState::Missing {
+                    continue;
+                }
+                piece.blocks[n] = BlockState::Requested(vec![addr]);
+                let block = piece.block(index, n);
+                queue.on_request(block, now);
+                requests.push(block);
+            }
+            if piece.has_missing() {
+                self.picker.mark_partial(index as usize);
+            } else {
+                self.picker.mark_requested(index as usize);
+            }
+        }
+
+        if self.endgame && queue.slots() > 0 {
+            let mut candidates: Vec<(usize, u32, usize)> = Vec::new();
+            for (&index, piece) in &self.pieces {
+                if !peer.has(index as usize) {
+                    continue;
+                }
+                for (n, state) in piece.blocks.iter().enumerate() {
+                    if let BlockState::Requested(peers) = state
+                        && !peers.contains(&addr)
+                    {
+                        candidates.push((peers.len(), index, n));
+                    }
+                }
+            }
+            candidates.sort_unstable();
+            for (_, index, n) in candidates.into_iter().take(queue.slots()) {
+                let Some(piece) = self.pieces.get_mut(&index) else {
+                    continue;
+                };
+                if let BlockState::Requested(peers) = &mut piece.blocks[n] {
+                    peers.push(addr);
+                }
+                let block = piece.block(index, n);
+                queue.on_request(block, now);
+                requests.push(block);
+            }
+        }
+        requests
+    }
+
+    /**
+    Stores a block received from `addr`.
+
+    # How it works:
+    1. Blocks for pieces not in progress (e.g. arriving after a cancel) and duplicates are ignored.
+    2. The block must start on a block boundary and have exactly the expected length, which is
+       shorter for the last block of a piece; otherwise `PeerProtocol` is returned.
+    3. Any other peers the block was requested from are returned so their requests can be cancelled.
+    4. When the last block arrives the piece leaves the in-progress set and is returned for
+       verification. It stays marked as requested in the picker, so it is not picked again (and
+       endgame is not left) while its hash is checked; report the result with `verified`.
+    */
+    pub fn block_received(
+        &mut self,
+        addr: SocketAddr,
+        block: &Block,
+        data: &[u8],
+    ) -> Result<BlockReceived> {
+        let Some(piece) = self.pieces.get_mut(&block.index) else {
+            return Ok(BlockReceived::default());
+        };
+        let size = piece.data.len() as u32;
+        let expected = size.saturating_sub(block.begin).min(BLOCK_SIZE);
+        if !block.begin.is_multiple_of(BLOCK_SIZE)
+            || block.begin >= size
+            || data.len() as u32 != expected
+        {
+            return Err(TorrentError::PeerProtocol(format!(
+                "unexpected block {}+{} for piece {}",
+                block.begin,
+                data.len(),
+                block.index
+            ))
+            .into());
+        }
+        let n = (block.begin / BLOCK_SIZE) as usize;
+        let cancels = match &piece.blocks[n] {
+            BlockState::Received(_) => return Ok(BlockReceived::default()),
+            BlockState::Requested(peers) => {
+                peers.iter().copied().filter(|peer| *peer != addr).collect()
+            }
+            BlockState::Missing => Vec::new(),
+        };
+        let begin = block.begin as usize;
+        piece.data[begin..begin + data.len()].copy_from_slice(data);
+        piece.blocks[n] = BlockState::Received(addr);
+        piece.received += 1;
+        if piece.received < piece.blocks.len() {
+            return Ok(BlockReceived {
+                cancels,
+                piece: None,
+            });
+        }
+
+        let piece = self
+            .pieces
+            .remove(&block.index)
+            .map(|piece| CompletedPiece {
+                index: block.index,
+                contributors: piece
+                    .blocks
+                    .iter()
+                    .map(|state| match state {
+                        BlockState::Received(peer) => *peer,
+                        _ => addr,
+                    })
+                    .collect(),
+                data: piece.data,
+            });
+        self.verifying.insert(block.index);
+        if self.pieces.is_empty() {
+            self.endgame = false;
+        }
+        Ok(BlockReceived { cancels, piece })
+    }
+
+    /// Withdraws the request for a block from `addr`, after it timed out, was cancelled, or the
+    /// peer choked us or disconnected. Once no peer has it requested, the block can be requested again.
+    pub fn release(&mut self, addr: SocketAddr, block: &Block) {
+        let Some(piece) = self.pieces.get_mut(&block.index) else {
+            return;
+        };
+        let n = (block.begin / BLOCK_SIZE) as usize;
+        let Some(BlockState::Requested(peers)) = piece.blocks.get_mut(n) else {
+            return;
+        };
+        peers.retain(|peer| *peer != addr);
+        if peers.is_empty() {
+            piece.blocks[n] = BlockState::Missing;
+            self.picker.mark_partial(block.index as usize);
+            self.endgame = false;
+        }
+    }
+
+    /// Returns `true` if piece `index` is complete and waiting for its hash check.
+    pub fn is_verifying(&self, index: u32) -> bool {
+        self.verifying.contains(&index)
+    }
+
+    /// Records the hash check result of a piece returned by `block_received`.
+    ///
+    /// A piece that passed is done; it is in our bitfield by now, so the picker skips it. A piece
+    /// that failed is reset in the picker so it is downloaded again.
+    pub fn verified(&mut self, verification: &Verification) {
+        let index = verification.piece.index;
+        if !self.verifying.remove(&index) {
+            return;
+        }
+        if !verification.passed {
+            self.picker.reset(index as usize);
+            self.endgame = false;
+        }
+    }
+
+    /// Discards a piece in progress and everything received for it, so it can be picked and
+    /// downloaded again.
+    pub fn abandon(&mut self, index: u32) {
+        self.pieces.remove(&index);
+        self.picker.reset(index as usize);
+        self.endgame = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::fixture::{self, peer};
+    use crate::piece::picker::Priority;
+
+    /// Two pieces of two blocks, then a last piece of one full block and 100 bytes.
+    fn downloads() -> PieceDownloads {
+        let torrent = fixture::torrent(2 * BLOCK_SIZE as usize, &[5 * BLOCK_SIZE as usize + 100]);
+        let picker = PiecePicker::new(&torrent).with_random_first(0);
+        PieceDownloads::new(&torrent, picker).unwrap()
+    }
+
+    /// Availability making the last piece the rarest, then piece 0, then piece 1.
+    fn availability() -> Availability {
+        let mut availability = Availability::new(3);
+        for pieces in [&[0, 1][..], &[1]] {
+            let mut bitfield = Bitfield::new(3);
+            for &index in pieces {
+                bitfield.set(index);
+            }
+            availability.add_peer(&bitfield);
+        }
+        availability
+    }
+
+    fn request(
+        downloads: &mut PieceDownloads,
+        addr: SocketAddr,
+        queue: &mut RequestQueue,
+    ) -> Vec<Block> {
+        downloads.request_blocks(
+            addr,
+            &Bitfield::new(3),
+            &Bitfield::full(3),
+            &availability(),
+            queue,
+            Instant::now(),
+        )
+    }
+
+    fn block(index: u32, n: u32) -> Block {
+        let length = if index == 2 && n == 1 {
+            100
+        } else {
+            BLOCK_SIZE
+        };
+        Block {
+            index,
+            begin: n * BLOCK_SIZE,
+            length,
+        }
+    }
+
+    fn receive(downloads: &mut PieceDownloads, addr: SocketAddr, block: &Block) -> BlockReceived {
+        downloads
+            .block_received(addr, block, &vec![1; block.length as usize])
+            .unwrap()
+    }
+
+    #[test]
+    fn pieces_split_into_blocks() {
+        assert_eq!(
+            blocks_for_piece(2, BLOCK_SIZE + 100),
+            [block(2, 0), block(2, 1)]
+        );
+        assert_eq!(
+            blocks_for_piece(0, 2 * BLOCK_SIZE),
+            [block(0, 0), block(0, 1)]
+        );
+        assert_eq!(blocks_for_piece(0, 0), []);
+
+        let downloads = downloads();
+        assert_eq!(downloads.piece_size(1), Some(2 * BLOCK_SIZE));
+        assert_eq!(downloads.piece_size(2), Some(BLOCK_SIZE + 100));
+        assert_eq!(downloads.piece_size(3), None);
+    }
+
+    #[test]
+    fn requests_fill_the_queue_in_pick_order() {
+        let mut downloads = downloads();
+        let mut queue = RequestQueue::new();
+        let requests = request(&mut downloads, peer(1), &mut queue);
+        assert_eq!(
+            requests,
+            [block(2, 0), block(2, 1), block(0, 0), block(0, 1)]
+        );
+        assert_eq!(queue.in_flight().copied().collect::<Vec<_>>(), requests);
+        assert_eq!(queue.slots(), 0);
+        assert!(downloads.picker().is_requested(2));
+        assert!(downloads.picker().is_requested(0));
+        assert_eq!(downloads.stats().blocks_requested, 4);
+
+        // A short queue leaves the next piece partial, to be finished first
+        let mut queue = RequestQueue::new();
+        queue.on_request(block(9, 0), Instant::now());
+        queue.on_request(block(9, 1), Instant::now());
+        queue.on_request(block(9, 2), Instant::now());
+        assert_eq!(request(&mut downloads, peer(2), &mut queue), [block(1, 0)]);
+        assert!(downloads.picker().is_partial(1));
+    }
+
+    #[test]
+    fn misaligned_or_mis_sized_blocks_are_rejected() {
+        let mut downloads = downloads();
+        request(&mut downloads, peer(1), &mut RequestQueue::new());
+        for (begin, length) in [
+            (1, BLOCK_SIZE),
+            (0, 100),
+            (BLOCK_SIZE, BLOCK_SIZE),
+            (2 * BLOCK_SIZE, 100),
+        ] {
+            let block = Block {
+                index: 2,
+                begin,
+                length,
+            };
+            let error = downloads
+                .block_received(peer(1), &block, &vec![0; length as usize])
+                .unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<TorrentError>(),
+                Some(TorrentError::PeerProtocol(_))
+            ));
+        }
+        // Blocks of pieces not in progress are dropped quietly
+        assert_eq!(
+            receive(&mut downloads, peer(1), &block(1, 0)),
+            BlockReceived::default()
+        );
+    }
+
+    #[test]
+    fn completed_pieces_are_assembled_once() {
+        let mut downloads = downloads();
+        request(&mut downloads, peer(1), &mut RequestQueue::new());
+        assert_eq!(
+            receive(&mut downloads, peer(1), &block(2, 0)),
+            BlockReceived::default()
+        );
+        // A duplicate changes nothing
+        let duplicate = downloads
+            .block_received(peer(2), &block(2, 0), &vec![2; BLOCK_SIZE as usize])
+            .unwrap();
+        assert_eq!(duplicate, BlockReceived::default());
+        assert_eq!(downloads.stats().blocks_received, 1);
+
+        let piece = receive(&mut downloads, peer(3), &block(2, 1))
+            .piece
+            .unwrap();
+        assert_eq!(piece.index, 2);
+        assert_eq!(piece.data, vec![1; BLOCK_SIZE as usize + 100]);
+        assert_eq!(piece.contributors, [peer(1), peer(3)]);
+        assert_eq!(piece.peers(), [peer(1), peer(3)]);
+        assert!(downloads.is_verifying(2));
+        assert!(!downloads.in_progress().any(|index| index == 2));
+        // Late copies of a completed piece are ignored
+        assert_eq!(
+            receive(&mut downloads, peer(2), &block(2, 1)),
+            BlockReceived::default()
+        );
+    }
+
+    #[test]
+    fn released_blocks_can_be_requested_again() {
+        let mut downloads = downloads();
+        let mut queue = RequestQueue::new();
+        request(&mut downloads, peer(1), &mut queue);
+        for block in queue.clear() {
+            if block.index == 0 {
+                downloads.release(peer(1), &block);
+            }
+        }
+        assert!(downloads.picker().is_partial(0));
+        assert_eq!(downloads.stats().blocks_requested, 2);
+
+        let requests = request(&mut downloads, peer(2), &mut RequestQueue::new());
+        assert_eq!(requests[..2], [block(0, 0), block(0, 1)]);
+        assert!(downloads.picker().is_requested(0));
+    }
+
+    #[test]
+    fn failed_pieces_are_downloaded_again() {
+        let mut downloads = downloads();
+        request(&mut downloads, peer(1), &mut RequestQueue::new());
+        receive(&mut downloads, peer(1), &block(2, 0));
+        let piece = receive(&mut downloads, peer(1), &block(2, 1))
+            .piece
+            .unwrap();
+        // Still requested in the picker while it is checked
+        assert!(downloads.picker().is_requested(2));
+
+        downloads.verified(&Verification {
+            piece: piece.clone(),
+            passed: false,
+            banned: Vec::new(),
+        });
+        assert!(!downloads.is_verifying(2));
+        assert!(!downloads.picker().is_requested(2));
+        let requests = request(&mut downloads, peer(2), &mut RequestQueue::new());
+        assert_eq!(requests[..2], [block(2, 0), block(2, 1)]);
+
+        receive(&mut downloads, peer(2), &block(2, 0));
+        let piece = receive(&mut downloads, peer(2), &block(2, 1))
+            .piece
+            .unwrap();
+        downloads.verified(&Verification {
+            piece,
+            passed: true,
+            banned: Vec::new(),
+        });
+        assert!(!downloads.is_verifying(2));
+        assert!(downloads.picker().is_requested(2));
+    }
+
+    /// A queue with room for a single request.
+    fn one_slot() -> RequestQueue {
+        let mut queue = RequestQueue::new();
+        for n in 0..INITIAL_QUEUE_DEPTH as u32 - 1 {
+            queue.on_request(block(9, n), Instant::now());
+        }
+        queue
+    }
+
+    #[test]
+    fn endgame_duplicates_the_last_requests() {
+        // Only the last piece is wanted, so its two blocks are all that is left
+        let mut downloads = downloads();
+        for index in 0..2 {
+            downloads
+                .picker_mut()
+                .set_piece_priority(index, Priority::Skip)
+                .unwrap();
+        }
+        assert_eq!(
+            request(&mut downloads, peer(1), &mut one_slot()),
+            [block(2, 0)]
+        );
+        assert!(!downloads.is_endgame());
+
+        let requests = request(&mut downloads, peer(2), &mut RequestQueue::new());
+        assert_eq!(requests, [block(2, 1), block(2, 0)]);
+        assert!(downloads.is_endgame());
+        let stats = downloads.stats();
+        assert_eq!((stats.blocks_requested, stats.duplicate_requests), (2, 1));
+
+        // The block requested from fewer peers goes first
+        assert_eq!(
+            request(&mut downloads, peer(3), &mut one_slot()),
+            [block(2, 1)]
+        );
+        // Nothing is requested twice from the same peer
+        assert_eq!(
+            request(&mut downloads, peer(2), &mut RequestQueue::new()),
+            []
+        );
+
+        // Releasing one of several requests keeps the block requested, and endgame on
+        downloads.release(peer(2), &block(2, 1));
+        assert!(downloads.is_endgame());
+        let stats = downloads.stats();
+        assert_eq!((stats.blocks_requested, stats.duplicate_requests), (2, 1));
+    }
+
+    #[test]
+    fn first_copy_cancels_the_other_requests() {
+        let mut downloads = downloads();
+        for index in 0..2 {
+            downloads
+                .picker_mut()
+                .set_piece_priority(index, Priority::Skip)
+                .unwrap();
+        }
+        request(&mut downloads, peer(1), &mut one_slot());
+        request(&mut downloads, peer(2), &mut RequestQueue::new());
+        request(&mut downloads, peer(3), &mut one_slot());
+
+        let received = receive(&mut downloads, peer(3), &block(2, 1));
+        assert_eq!(received.cancels, [peer(2)]);
+        assert_eq!(received.piece, None);
+        assert_eq!(
+            receive(&mut downloads, peer(2), &block(2, 1)),
+            BlockReceived::default()
+        );
+
+        let received = receive(&mut downloads, peer(2), &block(2, 0));
+        assert_eq!(received.cancels, [peer(1)]);
+        assert_eq!(received.piece.unwrap().contributors, [peer(2), peer(3)]);
+        assert!(!downloads.is_endgame());
+    }
+
+    #[test]
+    fn request_queue_adapts_to_throughput_and_timeouts() {
+        let start = Instant::now();
+        let mut queue = RequestQueue::new();
+        assert_eq!(queue.slots(), INITIAL_QUEUE_DEPTH);
+        assert_eq!(queue.timeout(), DEFAULT_REQUEST_TIMEOUT);
+        for n in 0..4 {
+            queue.on_request(block(0, n), start);
+        }
+        assert!(!queue.on_block(&block(1, 0), start));
+
+        // 64 KiB in one second, the first block after 250 ms: 3.25 seconds' worth of blocks
+        for n in 0..4 {
+            let at = start + Duration::from_millis(250) * (n + 1);
+            assert!(queue.on_block(&block(0, n), at));
+        }
+        assert_eq!(queue.rate(), Some(4.0 * BLOCK_SIZE as f64));
+        assert_eq!(queue.depth(), 13);
+        assert_eq!(queue.timeout(), MIN_REQUEST_TIMEOUT);
+
+        let later = start + Duration::from_secs(1);
+        queue.on_request(block(1, 0), later);
+        queue.on_request(block(1, 1), later + Duration::from_secs(4));
+        assert!(queue.on_cancel(&block(1, 1)));
+        assert!(!queue.on_cancel(&block(1, 1)));
+        assert_eq!(queue.timed_out(later + Duration::from_secs(5)), []);
+        assert_eq!(
+            queue.timed_out(later + Duration::from_secs(6)),
+            [block(1, 0)]
+        );
+        assert_eq!(queue.depth(), 6);
+    }
+}
@@ -0,0 +1,217 @@
+//! Smart ban of peers that send corrupt data.
+//!
+//! A failed hash check only says that some block of the piece was bad, not which peer sent it.
+//! Banning every contributor would punish honest peers, so instead the hash of each block of the
+//! failed piece is remembered together with the peer that sent it. Once the piece is downloaded
+//! again and passes, every block can be checked against the good copy: the peers whose blocks
+//! differed sent corrupt data, and their IP addresses are banned for the rest of the session.
+//! The bans go into a `BannedPeers` set, which the tracker client's peer filter can share.
+use super::pipeline::{BLOCK_SIZE, CompletedPiece};
+use super::verify::piece_hash;
+use crate::tracker::filter::BannedPeers;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+/// The hash of one block and the peer that sent it.
+type BlockRecord = (SocketAddr, [u8; 20]);
+
+#[derive(Debug, Default)]
+struct State {
+    /// For each failed piece, the block copies received so far, by block number.
+    failed: HashMap<u32, Vec<HashSet<BlockRecord>>>,
+}
+
+/// Tracks failed pieces and bans the peers that sent corrupt blocks.
+///
+/// Cloning is cheap: clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct SmartBan {
+    inner: Arc<Mutex<State>>,
+    banned: BannedPeers,
+}
+
+impl SmartBan {
+    /// Creates a smart ban with nothing recorded and nobody banned.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records bans in `banned` instead of a set of its own, e.g. the one given to the tracker
+    /// client so banned peers are no longer handed out.
+    pub fn with_banned_peers(mut self, banned: BannedPeers) -> Self {
+        self.banned = banned;
+        self
+    }
+
+    /// Returns the set the bans are recorded in.
+    pub fn banned_peers(&self) -> BannedPeers {
+        self.banned.clone()
+    }
+
+    /// Remembers the block hashes and senders of a piece that failed its hash check.
+    ///
+    /// A piece may fail several times before it passes; the copies from every attempt are kept.
+    pub fn on_failed(&self, piece: &CompletedPiece) {
+        let records = block_records(piece);
+        let mut state = self.lock();
+        let blocks = state.failed.entry(piece.index).or_default();
+        if blocks.len() < records.len() {
+            blocks.resize_with(records.len(), HashSet::new);
+        }
+        for (copies, record) in blocks.iter_mut().zip(records) {
+            copies.insert(record);
+        }
+    }
+
+    /**
+    Identifies the peers that corrupted a piece, now that it has passed its hash check.
+
+    # How it works:
+    1. Does nothing unless the piece failed before.
+    2. Hashes each block of the good piece and compares it with every copy remembered from the
+       failed attempts.
+    3. Bans the IP address of each peer whose copy differs, and forgets the failed piece.
+
+    # Returns
+    * `Vec<IpAddr>` - The addresses banned because of this piece
+    */
+    pub fn on_passed(&self, piece: &CompletedPiece) -> Vec<IpAddr> {
+        if !self.lock().failed.contains_key(&piece.index) {
+            return Vec::new();
+        }
+        let good: Vec<[u8; 20]> = block_records(piece)
+            .into_iter()
+            .map(|(_, hash)| hash)
+            .collect();
+
+        let mut state = self.lock();
+        let Some(blocks) = state.failed.remove(&piece.index) else {
+            return Vec::new();
+        };
+        drop(state);
+        let mut newly_banned = Vec::new();
+        for (copies, good) in blocks.iter().zip(&good) {
+            for (addr, hash) in copies {
+                let ip = addr.ip().to_canonical();
+                if hash != good && self.banned.ban(ip) {
+                    tracing::warn!(%ip, index = piece.index, "Banned peer for sending corrupt data");
+                    newly_banned.push(ip);
+                }
+            }
+        }
+        newly_banned
+    }
+
+    /// Bans `ip` for the rest of the session.
+    pub fn ban(&self, ip: IpAddr) {
+        self.banned.ban(ip);
+    }
+
+    /// Returns `true` if `ip` is banned.
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.banned.is_banned(ip)
+    }
+
+    /// Returns the banned addresses.
+    pub fn banned(&self) -> Vec<IpAddr> {
+        self.banned.banned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Hashes each block of a piece and pairs it with the peer that sent it.
+fn block_records(piece: &CompletedPiece) -> Vec<BlockRecord> {
+    piece
+        .data
+        .chunks(BLOCK_SIZE as usize)
+        .zip(&piece.contributors)
+        .map(|(block, addr)| (*addr, piece_hash(block)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::bitfield::Bitfield;
+    use crate::piece::fixture::{self, peer};
+    use crate::piece::verify::PieceVerifier;
+
+    const PIECE_LENGTH: usize = 2 * BLOCK_SIZE as usize;
+
+    /// The only piece of a two-block torrent, with the second block corrupted if `corrupt`.
+    fn piece(contributors: [SocketAddr; 2], corrupt: bool) -> CompletedPiece {
+        let mut data = fixture::content(PIECE_LENGTH);
+        if corrupt {
+            data[PIECE_LENGTH - 1] ^= 0xff;
+        }
+        CompletedPiece {
+            index: 0,
+            data,
+            contributors: contributors.to_vec(),
+        }
+    }
+
+    #[test]
+    fn only_senders_of_corrupt_blocks_are_banned() {
+        let banned = BannedPeers::new();
+        let smart_ban = SmartBan::new().with_banned_peers(banned.clone());
+        assert!(
+            smart_ban
+                .on_passed(&piece([peer(1), peer(2)], false))
+                .is_empty()
+        );
+
+        smart_ban.on_failed(&piece([peer(1), peer(2)], true));
+        assert!(banned.banned().is_empty());
+        assert_eq!(
+            smart_ban.on_passed(&piece([peer(3), peer(1)], false)),
+            [peer(2).ip()]
+        );
+        assert_eq!(banned.banned(), [peer(2).ip()]);
+        assert!(smart_ban.is_banned(&peer(2).ip()));
+        assert!(!smart_ban.is_banned(&peer(1).ip()));
+
+        // The failed piece is forgotten once resolved
+        assert!(
+            smart_ban
+                .on_passed(&piece([peer(3), peer(1)], false))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn every_failed_copy_is_checked() {
+        let smart_ban = SmartBan::new();
+        smart_ban.on_failed(&piece([peer(1), peer(2)], true));
+        smart_ban.on_failed(&piece([peer(2), peer(3)], true));
+        let mut banned = smart_ban.on_passed(&piece([peer(4), peer(4)], false));
+        banned.sort_unstable();
+        assert_eq!(banned, [peer(2).ip(), peer(3).ip()]);
+    }
+
+    #[tokio::test]
+    async fn verifier_reports_the_bans() {
+        let torrent = fixture::torrent(PIECE_LENGTH, &[PIECE_LENGTH]);
+        let banned = BannedPeers::new();
+        let (handle, mut results) =
+            PieceVerifier::new(&torrent, Arc::new(Mutex::new(Bitfield::new(1))))
+                .with_smart_ban(SmartBan::new().with_banned_peers(banned.clone()))
+                .spawn();
+
+        handle.submit(piece([peer(1), peer(2)], true)).await;
+        let failed = results.recv().await.unwrap();
+        assert!(!failed.passed);
+        assert!(failed.banned.is_empty());
+
+        handle.submit(piece([peer(1), peer(3)], false)).await;
+        let passed = results.recv().await.unwrap();
+        assert!(passed.passed);
+        assert_eq!(passed.banned, [peer(2).ip()]);
+        assert_eq!(banned.banned(), [peer(2).ip()]);
+        handle.shutdown().await;
+    }
+}
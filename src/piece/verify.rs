@@ -0,0 +1,308 @@
+//! Piece hash verification.
+//!
+//! Every completed piece is SHA-1 hashed and compared with its entry in the torrent's `pieces`
+//! list before it counts as downloaded. Hashing a multi-megabyte piece takes long enough to stall
+//! the async runtime, so the verifier runs as a background task that hands the work to tokio's
+//! blocking thread pool, a bounded number of pieces at a time.
+use super::bitfield::Bitfield;
+use super::pipeline::CompletedPiece;
+use super::smart_ban::SmartBan;
+use crate::torrent::file::TorrentFile;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::{Id, JoinError, JoinHandle, JoinSet};
+
+/// Pieces waiting to be hashed before `submit` waits for room.
+const QUEUE_LENGTH: usize = 64;
+
+/// Computes the SHA-1 hash of a piece.
+///
+/// # Example
+/// ```
+/// use rs_torrent_client::piece::verify::piece_hash;
+///
+/// assert_eq!(hex::encode(piece_hash(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+/// ```
+pub fn piece_hash(data: &[u8]) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
+
+/// The outcome of verifying one piece.
+///
+/// Pass it to `PieceDownloads::verified` so a failed piece is downloaded again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// The piece, including its data and the peers that sent each block. The data is empty if
+    /// the hashing task panicked.
+    pub piece: CompletedPiece,
+    /// `true` if the piece matched its hash.
+    pub passed: bool,
+    /// Addresses banned because this piece exposed them as senders of corrupt blocks.
+    pub banned: Vec<IpAddr>,
+}
+
+/// Handle to a running verifier task.
+#[derive(Debug)]
+pub struct VerifierHandle {
+    pieces: mpsc::Sender<CompletedPiece>,
+    have: broadcast::Sender<u32>,
+    task: JoinHandle<()>,
+}
+
+impl VerifierHandle {
+    /// Queues a completed piece for verification.
+    pub async fn submit(&self, piece: CompletedPiece) {
+        let _ = self.pieces.send(piece).await;
+    }
+
+    /// Subscribes to the indexes of newly verified pieces, to be announced with `have` messages.
+    pub fn subscribe(&self) -> broadcast::Receiver<u32> {
+        self.have.subscribe()
+    }
+
+    /// Finishes verifying the queued pieces and waits for the task to end.
+    pub async fn shutdown(self) {
+        drop(self.pieces);
+        let _ = self.task.await;
+    }
+}
+
+/// Verifies completed pieces against the torrent's piece hashes.
+pub struct PieceVerifier {
+    pieces_hash: Arc<Vec<[u8; 20]>>,
+    bitfield: Arc<Mutex<Bitfield>>,
+    concurrency: usize,
+    smart_ban: Option<SmartBan>,
+}
+
+impl PieceVerifier {
+    /// Creates a verifier for `torrent` that records verified pieces in `bitfield`.
+    ///
+    /// By default as many pieces are hashed at once as the machine has CPU cores.
+    pub fn new(torrent: &TorrentFile, bitfield: Arc<Mutex<Bitfield>>) -> Self {
+        Self {
+            pieces_hash: Arc::new(torrent.pieces_hash.clone()),
+            bitfield,
+            concurrency: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            smart_ban: None,
+        }
+    }
+
+    /// Sets how many pieces may be hashed at the same time.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Feeds every verified piece to `smart_ban`, so peers that sent corrupt blocks get banned.
+    pub fn with_smart_ban(mut self, smart_ban: SmartBan) -> Self {
+        self.smart_ban = Some(smart_ban);
+        self
+    }
+
+    /// Returns `true` if `data` matches the hash of piece `index`.
+    pub fn verify(&self, index: u32, data: &[u8]) -> bool {
+        self.pieces_hash
+            .get(index as usize)
+            .is_some_and(|expected| *expected == piece_hash(data))
+    }
+
+    /// Starts the verifier on the tokio runtime.
+    ///
+    /// # Returns
+    /// * `(VerifierHandle, mpsc::Receiver<Verification>)` - A handle to submit pieces, and a
+    ///   channel that receives the outcome for every submitted piece
+    pub fn spawn(self) -> (VerifierHandle, mpsc::Receiver<Verification>) {
+        let (piece_tx, piece_rx) = mpsc::channel(QUEUE_LENGTH);
+        let (result_tx, result_rx) = mpsc::channel(QUEUE_LENGTH);
+        let (have_tx, _) = broadcast::channel(QUEUE_LENGTH);
+        let task = tokio::spawn(self.run(piece_rx, result_tx, have_tx.clone()));
+        (
+            VerifierHandle {
+                pieces: piece_tx,
+                have: have_tx,
+                task,
+            },
+            result_rx,
+        )
+    }
+
+    /**
+    The verifier loop.
+
+    # How it works:
+    1. Takes submitted pieces while fewer than `concurrency` are being hashed, and hashes each
+       on the blocking thread pool.
+    2. A piece matching its hash is set in our bitfield and its index is broadcast so every
+       connection can send `have`.
+    3. A piece that does not match is logged with the peers that sent its blocks; it stays
+       missing from our bitfield, so the picker will download it again.
+    4. With a smart ban configured, failed pieces are remembered block by block, and a piece that
+       passes after failing bans the peers whose blocks differed (hashed on the blocking pool too).
+    5. A hashing task that panics counts as a failed check, since its piece cannot be trusted.
+    6. Either way the outcome is sent on the results channel. When every handle is dropped, the
+       queued pieces are finished and the task ends.
+    */
+    async fn run(
+        self,
+        mut pieces: mpsc::Receiver<CompletedPiece>,
+        results: mpsc::Sender<Verification>,
+        have: broadcast::Sender<u32>,
+    ) {
+        let verifier = Arc::new(self);
+        let mut hashing = JoinSet::new();
+        // The piece index and contributors of each hashing task, in case the task fails
+        let mut submitted = HashMap::new();
+        loop {
+            tokio::select! {
+                piece = pieces.recv(), if hashing.len() < verifier.concurrency => {
+                    let Some(piece) = piece else {
+                        break;
+                    };
+                    let verifier = verifier.clone();
+                    let details = (piece.index, piece.contributors.clone());
+                    let task = hashing.spawn_blocking(move || {
+                        let passed = verifier.verify(piece.index, &piece.data);
+                        let banned = match &verifier.smart_ban {
+                            Some(smart_ban) if passed => smart_ban.on_passed(&piece),
+                            Some(smart_ban) => {
+                                smart_ban.on_failed(&piece);
+                                Vec::new()
+                            }
+                            None => Vec::new(),
+                        };
+                        Verification { piece, passed, banned }
+                    });
+                    submitted.insert(task.id(), details);
+                }
+                Some(joined) = hashing.join_next_with_id() => {
+                    if let Some(verification) = outcome(joined, &mut submitted) {
+                        verifier.finish(verification, &results, &have).await;
+                    }
+                }
+            }
+        }
+        while let Some(joined) = hashing.join_next_with_id().await {
+            if let Some(verification) = outcome(joined, &mut submitted) {
+                verifier.finish(verification, &results, &have).await;
+            }
+        }
+    }
+
+    async fn finish(
+        &self,
+        verification: Verification,
+        results: &mpsc::Sender<Verification>,
+        have: &broadcast::Sender<u32>,
+    ) {
+        let index = verification.piece.index;
+        if verification.passed {
+            self.bitfield
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .set(index as usize);
+            let _ = have.send(index);
+        } else {
+            tracing::warn!(
+                index,
+                peers = ?verification.piece.peers(),
+                "Piece failed hash check"
+            );
+        }
+        let _ = results.send(verification).await;
+    }
+}
+
+/// Takes the result of a hashing task, turning a task that panicked into a failed verification.
+fn outcome(
+    joined: Result<(Id, Verification), JoinError>,
+    submitted: &mut HashMap<Id, (u32, Vec<SocketAddr>)>,
+) -> Option<Verification> {
+    match joined {
+        Ok((id, verification)) => {
+            submitted.remove(&id);
+            Some(verification)
+        }
+        Err(e) => {
+            let (index, contributors) = submitted.remove(&e.id())?;
+            tracing::error!(index, error = %e, "Piece hashing task failed");
+            Some(Verification {
+                piece: CompletedPiece {
+                    index,
+                    data: Vec::new(),
+                    contributors,
+                },
+                passed: false,
+                banned: Vec::new(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::fixture::{self, peer};
+
+    /// Three pieces of 16 bytes, the last one 8 bytes.
+    fn torrent() -> TorrentFile {
+        fixture::torrent(16, &[40])
+    }
+
+    fn piece(index: u32, corrupt: bool) -> CompletedPiece {
+        let content = fixture::content(40);
+        let start = index as usize * 16;
+        let mut data = content[start..(start + 16).min(40)].to_vec();
+        if corrupt {
+            data[0] ^= 0xff;
+        }
+        CompletedPiece {
+            index,
+            data,
+            contributors: vec![peer(index as u8)],
+        }
+    }
+
+    #[test]
+    fn pieces_are_checked_against_their_hash() {
+        let verifier = PieceVerifier::new(&torrent(), Arc::new(Mutex::new(Bitfield::new(3))));
+        for index in 0..3 {
+            assert!(verifier.verify(index, &piece(index, false).data));
+            assert!(!verifier.verify(index, &piece(index, true).data));
+        }
+        assert!(!verifier.verify(3, &[]));
+    }
+
+    #[tokio::test]
+    async fn verified_pieces_are_recorded_and_announced() {
+        let bitfield = Arc::new(Mutex::new(Bitfield::new(3)));
+        let (handle, mut results) = PieceVerifier::new(&torrent(), bitfield.clone())
+            .with_concurrency(2)
+            .spawn();
+        let mut have = handle.subscribe();
+        handle.submit(piece(2, false)).await;
+        handle.submit(piece(0, true)).await;
+
+        let mut verifications = [results.recv().await.unwrap(), results.recv().await.unwrap()];
+        verifications.sort_by_key(|verification| verification.piece.index);
+        assert!(!verifications[0].passed);
+        assert_eq!(verifications[0].piece, piece(0, true));
+        assert!(verifications[1].passed);
+        assert_eq!(verifications[1].piece, piece(2, false));
+        assert!(verifications.iter().all(|v| v.banned.is_empty()));
+
+        assert_eq!(bitfield.lock().unwrap().pieces().collect::<Vec<_>>(), [2]);
+        assert_eq!(have.recv().await.unwrap(), 2);
+        assert!(have.try_recv().is_err());
+
+        // Queued pieces are finished before the task ends
+        handle.submit(piece(1, false)).await;
+        handle.shutdown().await;
+        assert!(results.recv().await.unwrap().passed);
+        assert!(results.recv().await.is_none());
+        assert!(bitfield.lock().unwrap().has(1));
+    }
+}
@@ -1,27 +1,42 @@
 //! Torrent file structure and parsing logic.
 //!
 //! This module defines the TorrentFile struct and related helpers for parsing, validating, and working with .torrent file metadata.
-use crate::bencode::BencodeValue;
+use crate::bencode::{self, BencodeValue};
 use crate::torrent::info_hash;
+use crate::torrent::signature::{self, Signature};
 
 use super::TorrentError;
 use anyhow::Ok;
 use anyhow::Result;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct TorrentFile {
-    pub announce: String,
+    pub announce: Option<String>,
     pub announce_list: Vec<Vec<String>>,
+    /// DHT bootstrap nodes from the `nodes` key (BEP 5), used by trackerless torrents.
+    ///
+    /// If the key holds anything besides valid `[host, port]` pairs, its original value is also
+    /// kept in `extra` and takes precedence when the torrent is re-encoded.
+    pub nodes: Vec<DhtNode>,
     pub creation_date: Option<SystemTime>,
     pub comment: String,
     pub created_by: String,
     pub encoding: String,
     pub info: InfoDict,
     pub info_hash: [u8; 20],
+    /// The bencoded info dictionary the `info_hash` was computed from; BEP 35 signatures cover
+    /// exactly these bytes.
+    pub info_bytes: Vec<u8>,
     pub pieces_hash: Vec<[u8; 20]>,
+    /// Signatures from the `signatures` dictionary (BEP 35), with their load-time verification status.
+    pub signatures: Vec<Signature>,
+    /// Root dictionary keys not recognised by the parser (e.g. `source`, `similar`, custom keys).
+    pub extra: HashMap<Vec<u8>, BencodeValue>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -33,12 +48,83 @@ pub struct InfoDict {
     pub length: Option<i64>,
     pub files: Vec<FileDict>,
     pub is_directory: bool,
+    /// Info dictionary keys not recognised by the parser, kept so the info hash can be reproduced.
+    pub extra: HashMap<Vec<u8>, BencodeValue>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct FileDict {
     pub length: i64,
     pub path: Vec<String>,
+    /// File entry keys not recognised by the parser (e.g. `attr`, `md5sum`).
+    pub extra: HashMap<Vec<u8>, BencodeValue>,
+}
+
+/// The piece layout of a torrent, in the `u32` units used by the peer wire protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceGeometry {
+    pub num_pieces: u32,
+    pub piece_length: u32,
+    /// Size of the last piece, which may be shorter than the others.
+    pub last_piece_length: u32,
+}
+
+impl PieceGeometry {
+    /**
+    Checks that `num_pieces` pieces of `piece_length` bytes cover exactly `total_length` bytes.
+
+    # How it works:
+    1. Rejects a zero piece length.
+    2. Rejects a piece count other than `total_length / piece_length`, rounded up.
+    3. Derives the size of the last piece from the remainder.
+
+    # Example
+    ```
+    use rs_torrent_client::torrent::file::PieceGeometry;
+
+    let geometry = PieceGeometry::new(3, 16384, 40000).unwrap();
+    assert_eq!(geometry.last_piece_length, 7232);
+    assert!(PieceGeometry::new(3, 0, 40000).is_err());
+    assert!(PieceGeometry::new(2, 16384, 40000).is_err());
+    ```
+    */
+    pub fn new(num_pieces: u32, piece_length: u32, total_length: u64) -> Result<Self> {
+        if piece_length == 0 {
+            return Err(TorrentError::InvalidFormat("Piece length is zero".to_string()).into());
+        }
+        let expected = total_length.div_ceil(piece_length as u64);
+        if expected != num_pieces as u64 {
+            return Err(TorrentError::InvalidFormat(format!(
+                "{} pieces of {} bytes cannot hold {} bytes",
+                num_pieces, piece_length, total_length
+            ))
+            .into());
+        }
+        let last_piece_length = match total_length % piece_length as u64 {
+            0 => piece_length,
+            rest => rest as u32,
+        };
+        Ok(Self {
+            num_pieces,
+            piece_length,
+            last_piece_length,
+        })
+    }
+}
+
+/// A DHT node contact listed in a torrent's `nodes` key.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DhtNode {
+    pub host: String,
+    pub port: u16,
+}
+
+impl DhtNode {
+    /// Resolves the node's host name into socket addresses that can be contacted over UDP.
+    pub async fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        let addrs = tokio::net::lookup_host((self.host.as_str(), self.port)).await?;
+        Ok(addrs.collect())
+    }
 }
 
 /**
@@ -59,7 +145,7 @@ The order of these hashes is crucial as it corresponds directly to the piece ind
 */
 #[tracing::instrument(level = "trace")]
 fn parse_pieces(pieces_bytes: &[u8]) -> Result<Vec<[u8; 20]>> {
-    if pieces_bytes.len() % 20 != 0 {
+    if !pieces_bytes.len().is_multiple_of(20) {
         return Err(TorrentError::InvalidPiecesHashLength.into());
     }
     let num_pieces = pieces_bytes.len() / 20;
@@ -126,18 +212,75 @@ fn parse_announce_list(value: BencodeValue) -> Result<Vec<Vec<String>>> {
     }
 }
 
+/**
+Takes an optional free-text field such as `comment` out of the root dictionary.
+
+# How it works:
+1. A non-empty UTF-8 string is removed from `dict` and returned.
+2. Anything else (an empty or non-UTF-8 string, e.g. a comment in the legacy `encoding`, or a
+   value of the wrong type) stays in `dict`, so it ends up in `extra` and is written back
+   byte for byte.
+*/
+fn take_text(dict: &mut HashMap<Vec<u8>, BencodeValue>, key: &[u8]) -> String {
+    match dict.get(key) {
+        Some(BencodeValue::String(s)) if !s.is_empty() => match std::str::from_utf8(s) {
+            std::result::Result::Ok(text) => {
+                let text = text.to_string();
+                dict.remove(key);
+                text
+            }
+            Err(_) => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/**
+Parses the DHT bootstrap nodes from the torrent file (BEP 5).
+
+# How it works:
+1. Takes the entries of the `nodes` list, each expected to be a `[host, port]` pair.
+2. Keeps entries whose host is a UTF-8 string and whose port fits in a `u16`.
+3. Skips any other entry, logging it at debug level: the nodes are optional bootstrap data,
+   so a malformed entry must not reject the whole torrent.
+*/
+fn parse_nodes(entries: &[BencodeValue]) -> Vec<DhtNode> {
+    let mut nodes = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let node = match entry {
+            BencodeValue::List(pair) => match pair.as_slice() {
+                [BencodeValue::String(host), BencodeValue::Integer(port)] => {
+                    match (String::from_utf8(host.clone()), u16::try_from(*port)) {
+                        (std::result::Result::Ok(host), std::result::Result::Ok(port)) => {
+                            Some(DhtNode { host, port })
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        match node {
+            Some(node) => nodes.push(node),
+            None => tracing::debug!(?entry, "Skipping invalid DHT node entry"),
+        }
+    }
+    nodes
+}
+
 /**
 Parses the info dictionary from the torrent file.
 
 # How it works:
 1. Extracts required fields such as `piece_length`, `pieces`, `private`, `name`, `length`, and `files`.
 2. Validates the structure and content of each field.
-3. Constructs an `InfoDict` struct with the parsed data.
+3. Constructs an `InfoDict` struct with the parsed data, keeping any remaining keys in `extra`.
 4. Returns an error if any required field is missing or invalid.
 */
 fn parse_info_dict(value: BencodeValue) -> Result<InfoDict> {
     // Step 1: Validate that the input is a dictionary
-    let dict = match value {
+    let mut dict = match value {
         BencodeValue::Dict(d) => d,
         _ => {
             return Err(TorrentError::InvalidFormat("Info is not a dictionary".to_string()).into());
@@ -145,48 +288,55 @@ fn parse_info_dict(value: BencodeValue) -> Result<InfoDict> {
     };
 
     // Step 2: Extract and validate piece_length (required field)
-    let piece_length = match dict.get(&b"piece length".to_vec()) {
-        Some(BencodeValue::Integer(i)) => *i,
+    let piece_length = match dict.remove(b"piece length".as_slice()) {
+        Some(BencodeValue::Integer(i)) => i,
         _ => return Err(TorrentError::MissingField("piece length".to_string()).into()),
     };
 
     // Step 3: Extract and validate pieces bytes (required field)
-    let pieces_bytes = match dict.get(&b"pieces".to_vec()) {
-        Some(BencodeValue::String(s)) => s.clone(),
+    let pieces_bytes = match dict.remove(b"pieces".as_slice()) {
+        Some(BencodeValue::String(s)) => s,
         _ => return Err(TorrentError::MissingField("pieces".to_string()).into()),
     };
 
     // Step 4: Extract private flag (optional field, defaults to false)
-    let private = match dict.get(&b"private".to_vec()) {
-        Some(BencodeValue::Integer(1)) => true,
-        _ => false,
-    };
+    // Any other value (such as an explicit `i0e`) is left in `extra` so it round-trips unchanged.
+    let private = matches!(
+        dict.get(b"private".as_slice()),
+        Some(BencodeValue::Integer(1))
+    );
+    if private {
+        dict.remove(b"private".as_slice());
+    }
 
     // Step 5: Extract and validate name (required field, must be UTF-8)
-    let name = match dict.get(&b"name".to_vec()) {
-        Some(BencodeValue::String(s)) => String::from_utf8(s.clone())
+    let name = match dict.remove(b"name".as_slice()) {
+        Some(BencodeValue::String(s)) => String::from_utf8(s)
             .map_err(|e| TorrentError::InvalidFormat(format!("Invalid name (not UTF-8): {}", e)))?,
         _ => return Err(TorrentError::MissingField("name".to_string()).into()),
     };
 
     // Step 6: Extract length (optional field for single-file torrents)
-    let length = match dict.get(&b"length".to_vec()) {
+    let length = match dict.get(b"length".as_slice()) {
         Some(BencodeValue::Integer(i)) => Some(*i),
         _ => None,
     };
+    if length.is_some() {
+        dict.remove(b"length".as_slice());
+    }
 
     // Step 7: Parse files list (optional field for multi-file torrents)
-    let files = match dict.get(&b"files".to_vec()) {
-        Some(BencodeValue::List(list)) => {
+    let files = match dict.remove(b"files".as_slice()) {
+        Some(BencodeValue::List(list)) if !list.is_empty() => {
             let mut files_vec = Vec::new();
 
             // Iterate through each file entry in the list
             for file_val in list {
                 // Step 7a: Validate that each file entry is a dictionary
-                if let BencodeValue::Dict(file_dict) = file_val {
+                if let BencodeValue::Dict(mut file_dict) = file_val {
                     // Step 7b: Extract file length (required for each file)
-                    let length = match file_dict.get(&b"length".to_vec()) {
-                        Some(BencodeValue::Integer(i)) => *i,
+                    let length = match file_dict.remove(b"length".as_slice()) {
+                        Some(BencodeValue::Integer(i)) => i,
                         _ => {
                             return Err(
                                 TorrentError::MissingField("file length".to_string()).into()
@@ -195,7 +345,7 @@ fn parse_info_dict(value: BencodeValue) -> Result<InfoDict> {
                     };
 
                     // Step 7c: Extract and validate file path (required for each file)
-                    let path = match file_dict.get(&b"path".to_vec()) {
+                    let path = match file_dict.remove(b"path".as_slice()) {
                         Some(BencodeValue::List(path_list)) => {
                             let mut path_vec = Vec::new();
 
@@ -203,7 +353,7 @@ fn parse_info_dict(value: BencodeValue) -> Result<InfoDict> {
                             for p in path_list {
                                 if let BencodeValue::String(s) = p {
                                     // Convert path component from bytes to UTF-8 string
-                                    path_vec.push(String::from_utf8(s.clone()).map_err(|e| {
+                                    path_vec.push(String::from_utf8(s).map_err(|e| {
                                         TorrentError::InvalidFormat(format!(
                                             "Invalid file path (not UTF-8): {}",
                                             e
@@ -222,7 +372,11 @@ fn parse_info_dict(value: BencodeValue) -> Result<InfoDict> {
                     };
 
                     // Step 7e: Create FileDict and add to files vector
-                    files_vec.push(FileDict { length, path });
+                    files_vec.push(FileDict {
+                        length,
+                        path,
+                        extra: file_dict,
+                    });
                 } else {
                     return Err(
                         TorrentError::InvalidFormat("File entry not a dict".to_string()).into(),
@@ -231,7 +385,12 @@ fn parse_info_dict(value: BencodeValue) -> Result<InfoDict> {
             }
             files_vec
         }
-        _ => Vec::new(), // No files list means single-file torrent
+        Some(other) => {
+            // Not a list, or an empty one; keep it untouched rather than silently dropping it
+            dict.insert(b"files".to_vec(), other);
+            Vec::new()
+        }
+        None => Vec::new(), // No files list means single-file torrent
     };
 
     // Step 8: Determine if this is a directory (multi-file) torrent
@@ -246,9 +405,65 @@ fn parse_info_dict(value: BencodeValue) -> Result<InfoDict> {
         length,
         files,
         is_directory,
+        extra: dict,
     })
 }
 
+impl InfoDict {
+    /**
+    Converts the info dictionary back into its bencode representation.
+
+    # How it works:
+    1. Starts from the unrecognised keys in `extra` so they round-trip unchanged.
+    2. Writes the required `name`, `piece length` and `pieces` keys.
+    3. Adds `private` only when set, as most creators omit it for public torrents.
+    4. Writes `length` for single-file torrents or a `files` list for multi-file ones.
+    */
+    pub fn to_bencode(&self) -> BencodeValue {
+        let mut dict = self.extra.clone();
+        dict.insert(
+            b"name".to_vec(),
+            BencodeValue::String(self.name.as_bytes().to_vec()),
+        );
+        dict.insert(
+            b"piece length".to_vec(),
+            BencodeValue::Integer(self.piece_length),
+        );
+        dict.insert(
+            b"pieces".to_vec(),
+            BencodeValue::String(self.pieces.clone()),
+        );
+        if self.private {
+            dict.insert(b"private".to_vec(), BencodeValue::Integer(1));
+        }
+        if let Some(length) = self.length {
+            dict.insert(b"length".to_vec(), BencodeValue::Integer(length));
+        }
+        if self.is_directory {
+            let files = self
+                .files
+                .iter()
+                .map(|file| {
+                    let mut file_dict = file.extra.clone();
+                    file_dict.insert(b"length".to_vec(), BencodeValue::Integer(file.length));
+                    file_dict.insert(
+                        b"path".to_vec(),
+                        BencodeValue::List(
+                            file.path
+                                .iter()
+                                .map(|p| BencodeValue::String(p.as_bytes().to_vec()))
+                                .collect(),
+                        ),
+                    );
+                    BencodeValue::Dict(file_dict)
+                })
+                .collect();
+            dict.insert(b"files".to_vec(), BencodeValue::List(files));
+        }
+        BencodeValue::Dict(dict)
+    }
+}
+
 impl TorrentFile {
     /**
     Returns the total length of all files in the torrent.
@@ -301,6 +516,24 @@ impl TorrentFile {
             }
         }
     }
+
+    /// Returns the piece layout, checked for use on the wire.
+    ///
+    /// Fails if the piece length or piece count does not fit in a `u32`, the total length is
+    /// negative, or the number of piece hashes does not match the content length.
+    pub fn piece_geometry(&self) -> Result<PieceGeometry> {
+        let num_pieces = u32::try_from(self.num_pieces()).map_err(|_| {
+            TorrentError::InvalidFormat(format!("Too many pieces: {}", self.num_pieces()))
+        })?;
+        let piece_length = u32::try_from(self.info.piece_length).map_err(|_| {
+            TorrentError::InvalidFormat(format!("Invalid piece length: {}", self.info.piece_length))
+        })?;
+        let total_length = u64::try_from(self.total_length()).map_err(|_| {
+            TorrentError::InvalidFormat(format!("Invalid total length: {}", self.total_length()))
+        })?;
+        PieceGeometry::new(num_pieces, piece_length, total_length)
+    }
+
     /**
     Returns a vector of file paths that contain data for a specific piece in the torrent.
 
@@ -337,14 +570,41 @@ impl TorrentFile {
         result_paths
     }
 
+    /**
+    Returns `true` if the torrent lists no trackers and must rely on the DHT to find peers.
+    */
+    pub fn is_trackerless(&self) -> bool {
+        self.trackers().is_empty()
+    }
+
+    /**
+    Resolves the torrent's `nodes` into addresses peer discovery can use to bootstrap the DHT.
+
+    # How it works:
+    1. Resolves each `[host, port]` pair from the `nodes` key.
+    2. Skips nodes whose host cannot be resolved, logging them at debug level.
+    3. Returns the addresses in the order they appear in the torrent.
+    */
+    pub async fn dht_bootstrap_nodes(&self) -> Vec<SocketAddr> {
+        let mut addrs = Vec::new();
+        for node in &self.nodes {
+            match node.resolve().await {
+                std::result::Result::Ok(resolved) => addrs.extend(resolved),
+                Err(e) => tracing::debug!(host = %node.host, error = %e, "Skipping DHT node"),
+            }
+        }
+        addrs
+    }
+
     /**
     Parses the torrent file data into a `TorrentFile` struct.
 
     # How it works:
-    1. Extracts the `announce`, `info`, `announce_list`, `creation_date`, `comment`, `created_by`, and `encoding` fields.
+    1. Extracts the `announce`, `nodes`, `info`, `announce_list`, `creation_date`, `comment`, `created_by`, and `encoding` fields.
+       `announce` is optional, since trackerless torrents rely on `nodes` or the DHT instead.
     2. Validates the structure and content of each field.
-    3. Calculates the `info_hash` and `pieces_hash`.
-    4. Constructs a `TorrentFile` struct with the parsed data.
+    3. Calculates the `info_hash` and `pieces_hash`, and verifies any `signatures` (BEP 35).
+    4. Constructs a `TorrentFile` struct with the parsed data, keeping any remaining root keys in `extra`.
     5. Returns an error if any required field is missing or invalid.
     */
     #[tracing::instrument(level = "debug")]
@@ -358,36 +618,51 @@ impl TorrentFile {
             }
         };
 
-        let announce_bytes = dict
-            .remove(&b"announce".to_vec())
-            .ok_or(TorrentError::MissingField("announce".to_string()))?;
-
-        let announce = match announce_bytes {
-            BencodeValue::String(s) => String::from_utf8(s).map_err(|e| {
+        let announce = match dict.remove(b"announce".as_slice()) {
+            Some(BencodeValue::String(s)) => Some(String::from_utf8(s).map_err(|e| {
                 TorrentError::InvalidFormat(format!("Invalid announce Url (not UTF-8): {}", e))
-            })?,
-            _ => return Err(TorrentError::MissingField("announce(not string)".to_string()).into()),
+            })?),
+            Some(_) => {
+                return Err(TorrentError::MissingField("announce(not string)".to_string()).into());
+            }
+            None => None,
+        };
+
+        let nodes = match dict.remove(b"nodes".as_slice()) {
+            Some(BencodeValue::List(entries)) => {
+                let nodes = parse_nodes(&entries);
+                if nodes.len() < entries.len() {
+                    // Some entries were skipped: keep the whole list so it is written back unchanged
+                    dict.insert(b"nodes".to_vec(), BencodeValue::List(entries));
+                }
+                nodes
+            }
+            Some(other) => {
+                // Not a list: keep the raw value so it is written back unchanged
+                tracing::debug!(nodes = ?other, "Ignoring nodes key that is not a list");
+                dict.insert(b"nodes".to_vec(), other);
+                Vec::new()
+            }
+            None => Vec::new(),
         };
 
         let info_dict_value = dict
-            .remove(&b"info".to_vec())
+            .remove(b"info".as_slice())
             .ok_or(TorrentError::MissingField("info".to_string()))?;
 
-        let info_dict_map = match &info_dict_value {
-            BencodeValue::Dict(d) => d.clone(),
-            _ => return Err(TorrentError::InvalidFormat("info is not a dict".to_string()).into()),
-        };
-
-        let info = parse_info_dict(info_dict_value)?;
+        let torrent = Self::from_info(info_dict_value, Vec::new())?;
 
-        let announce_list =
-            if let Some(announce_list_value) = dict.remove(&b"announce-list".to_vec()) {
-                parse_announce_list(announce_list_value)?
-            } else {
+        let announce_list = match dict.remove(b"announce-list".as_slice()) {
+            // An empty list names no trackers; keep it as is so it is written back unchanged
+            Some(BencodeValue::List(tiers)) if tiers.is_empty() => {
+                dict.insert(b"announce-list".to_vec(), BencodeValue::List(tiers));
                 Vec::new()
-            };
+            }
+            Some(announce_list_value) => parse_announce_list(announce_list_value)?,
+            None => Vec::new(),
+        };
 
-        let creation_date = if let Some(data_value) = dict.remove(&b"creation data".to_vec()) {
+        let creation_date = if let Some(data_value) = dict.remove(b"creation date".as_slice()) {
             match data_value {
                 BencodeValue::Integer(timestamp) => {
                     let secs = timestamp
@@ -407,32 +682,484 @@ impl TorrentFile {
             None
         };
 
-        let comment = match dict.remove(&b"comment".to_vec()) {
-            Some(BencodeValue::String(s)) => String::from_utf8(s).unwrap_or_default(),
-            _ => String::new(),
-        };
-        let created_by = match dict.remove(&b"created by".to_vec()) {
-            Some(BencodeValue::String(s)) => String::from_utf8(s).unwrap_or_default(),
-            _ => String::new(),
-        };
-        let encoding = match dict.remove(&b"encoding".to_vec()) {
-            Some(BencodeValue::String(s)) => String::from_utf8(s).unwrap_or_default(),
-            _ => String::new(),
-        };
+        let comment = take_text(&mut dict, b"comment");
+        let created_by = take_text(&mut dict, b"created by");
+        let encoding = take_text(&mut dict, b"encoding");
 
-        let info_hash = info_hash::calculate_info_hash(&info_dict_map)?;
-        let pieces_hash = parse_pieces(&info.pieces)?;
+        let signatures = match dict.remove(b"signatures".as_slice()) {
+            Some(value) => signature::parse_signatures(value, &torrent.info_bytes)?,
+            None => Vec::new(),
+        };
 
         Ok(TorrentFile {
             announce,
             announce_list,
+            nodes,
             creation_date,
             comment,
             created_by,
             encoding,
+            signatures,
+            extra: dict,
+            ..torrent
+        })
+    }
+
+    /**
+    Builds a `TorrentFile` from a bare info dictionary and a list of tracker tiers.
+
+    # Torrent Protocol Context:
+    Metadata fetched from peers (ut_metadata) or derived from a magnet link contains only the
+    info dictionary; the trackers come from elsewhere, such as the magnet link's `tr` parameters.
+
+    # How it works:
+    1. Parses the info dictionary and calculates the `info_hash` and `pieces_hash`.
+    2. Stores the trackers as the `announce_list`, with the first tracker also used as `announce`.
+    3. Leaves every other root field empty.
+    */
+    pub fn from_info(info: BencodeValue, trackers: Vec<Vec<String>>) -> Result<TorrentFile> {
+        let info_dict_map = match &info {
+            BencodeValue::Dict(d) => d.clone(),
+            _ => return Err(TorrentError::InvalidFormat("info is not a dict".to_string()).into()),
+        };
+
+        let mut info_bytes = Vec::new();
+        bencode::encoder::encode(&mut info_bytes, &info)?;
+        let info = parse_info_dict(info)?;
+        let info_hash = info_hash::calculate_info_hash(&info_dict_map)?;
+        let pieces_hash = parse_pieces(&info.pieces)?;
+
+        let announce_list: Vec<Vec<String>> = trackers
+            .into_iter()
+            .filter(|tier| !tier.is_empty())
+            .collect();
+        let announce = announce_list.first().and_then(|tier| tier.first()).cloned();
+
+        Ok(TorrentFile {
+            announce,
+            announce_list,
+            nodes: Vec::new(),
+            creation_date: None,
+            comment: String::new(),
+            created_by: String::new(),
+            encoding: String::new(),
             info,
             info_hash,
+            info_bytes,
             pieces_hash,
+            signatures: Vec::new(),
+            extra: HashMap::new(),
         })
     }
+
+    /**
+    Returns the tracker tiers to announce to.
+
+    # Torrent Protocol Context:
+    Per BEP 12, when `announce-list` is present it is authoritative and `announce` is ignored;
+    otherwise `announce` forms a single tier of one tracker.
+    */
+    pub fn trackers(&self) -> Vec<Vec<String>> {
+        let tiers: Vec<Vec<String>> = self
+            .announce_list
+            .iter()
+            .filter(|tier| !tier.is_empty())
+            .cloned()
+            .collect();
+        if !tiers.is_empty() {
+            return tiers;
+        }
+        match &self.announce {
+            Some(announce) => vec![vec![announce.clone()]],
+            None => Vec::new(),
+        }
+    }
+
+    /**
+    Converts the torrent back into its bencode representation.
+
+    # How it works:
+    1. Starts from the unrecognised root keys in `extra`.
+    2. Writes `info` and each optional field that is set, including `announce` and `nodes`.
+    3. The result can be passed to the bencode encoder to write a `.torrent` file.
+    */
+    pub fn to_bencode(&self) -> BencodeValue {
+        let mut dict = self.extra.clone();
+        if let Some(announce) = &self.announce {
+            dict.insert(
+                b"announce".to_vec(),
+                BencodeValue::String(announce.as_bytes().to_vec()),
+            );
+        }
+        // A `nodes` list kept in `extra` because of invalid entries is written back as it was
+        if !self.nodes.is_empty() && !dict.contains_key(b"nodes".as_slice()) {
+            let nodes = self
+                .nodes
+                .iter()
+                .map(|node| {
+                    BencodeValue::List(vec![
+                        BencodeValue::String(node.host.as_bytes().to_vec()),
+                        BencodeValue::Integer(node.port as i64),
+                    ])
+                })
+                .collect();
+            dict.insert(b"nodes".to_vec(), BencodeValue::List(nodes));
+        }
+        if !self.announce_list.is_empty() {
+            let tiers = self
+                .announce_list
+                .iter()
+                .map(|tier| {
+                    BencodeValue::List(
+                        tier.iter()
+                            .map(|url| BencodeValue::String(url.as_bytes().to_vec()))
+                            .collect(),
+                    )
+                })
+                .collect();
+            dict.insert(b"announce-list".to_vec(), BencodeValue::List(tiers));
+        }
+        if let Some(date) = self.creation_date {
+            let secs = date
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            dict.insert(b"creation date".to_vec(), BencodeValue::Integer(secs));
+        }
+        for (key, value) in [
+            (b"comment".as_slice(), &self.comment),
+            (b"created by".as_slice(), &self.created_by),
+            (b"encoding".as_slice(), &self.encoding),
+        ] {
+            if !value.is_empty() {
+                dict.insert(
+                    key.to_vec(),
+                    BencodeValue::String(value.as_bytes().to_vec()),
+                );
+            }
+        }
+        if !self.signatures.is_empty() {
+            let signatures = self
+                .signatures
+                .iter()
+                .map(|s| (s.signer.as_bytes().to_vec(), s.to_bencode()))
+                .collect();
+            dict.insert(b"signatures".to_vec(), BencodeValue::Dict(signatures));
+        }
+        dict.insert(b"info".to_vec(), self.info.to_bencode());
+        BencodeValue::Dict(dict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::{decoder, encoder};
+
+    fn string(s: &[u8]) -> BencodeValue {
+        BencodeValue::String(s.to_vec())
+    }
+
+    fn dict(entries: Vec<(&str, BencodeValue)>) -> BencodeValue {
+        BencodeValue::Dict(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value))
+                .collect(),
+        )
+    }
+
+    fn encode(value: &BencodeValue) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        encoder::encode(&mut bytes, value).unwrap();
+        bytes
+    }
+
+    /// Parses the encoded `root`, checks that re-encoding reproduces the same bytes and
+    /// returns the parsed torrent.
+    fn round_trip(root: BencodeValue) -> TorrentFile {
+        let bytes = encode(&root);
+        let torrent = TorrentFile::parse(decoder::decode(bytes.as_slice()).unwrap()).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&encode(&torrent.to_bencode())),
+            String::from_utf8_lossy(&bytes)
+        );
+        assert_eq!(encode(&torrent.info.to_bencode()), torrent.info_bytes);
+        torrent
+    }
+
+    #[test]
+    fn multi_file_torrent_round_trips() {
+        let info = dict(vec![
+            (
+                "files",
+                BencodeValue::List(vec![
+                    dict(vec![
+                        ("length", BencodeValue::Integer(3)),
+                        (
+                            "path",
+                            BencodeValue::List(vec![string(b"dir"), string(b"a")]),
+                        ),
+                        ("attr", string(b"x")),
+                    ]),
+                    dict(vec![
+                        ("length", BencodeValue::Integer(0)),
+                        ("path", BencodeValue::List(vec![string(b"b")])),
+                    ]),
+                ]),
+            ),
+            ("name", string(b"example")),
+            ("piece length", BencodeValue::Integer(16384)),
+            ("pieces", string(&[7; 20])),
+            ("private", BencodeValue::Integer(1)),
+            ("source", string(b"tracker")),
+        ]);
+        let torrent = round_trip(dict(vec![
+            ("announce", string(b"http://tracker.example/announce")),
+            (
+                "announce-list",
+                BencodeValue::List(vec![BencodeValue::List(vec![string(
+                    b"http://tracker.example/announce",
+                )])]),
+            ),
+            ("comment", string(b"hello")),
+            ("created by", string(b"creator/1.0")),
+            ("creation date", BencodeValue::Integer(1_700_000_000)),
+            ("encoding", string(b"UTF-8")),
+            ("info", info),
+            ("x-custom", BencodeValue::Integer(1)),
+        ]));
+        assert!(torrent.info.is_directory);
+        assert!(torrent.info.private);
+        assert_eq!(torrent.total_length(), 3);
+        assert_eq!(torrent.comment, "hello");
+        assert_eq!(torrent.created_by, "creator/1.0");
+        assert!(torrent.extra.contains_key(b"x-custom".as_slice()));
+    }
+
+    #[test]
+    fn undecodable_text_fields_round_trip() {
+        let info = dict(vec![
+            ("length", BencodeValue::Integer(3)),
+            ("name", string(b"n")),
+            ("piece length", BencodeValue::Integer(16384)),
+            ("pieces", string(&[7; 20])),
+        ]);
+        let torrent = round_trip(dict(vec![
+            // A Latin-1 comment, as written by creators honouring `encoding`
+            ("comment", string(b"caf\xe9")),
+            ("created by", string(b"")),
+            ("encoding", BencodeValue::Integer(1)),
+            ("info", info),
+        ]));
+        assert_eq!(torrent.comment, "");
+        assert_eq!(torrent.created_by, "");
+        assert_eq!(torrent.encoding, "");
+        assert_eq!(
+            torrent.extra.get(b"comment".as_slice()),
+            Some(&string(b"caf\xe9"))
+        );
+    }
+
+    #[test]
+    fn empty_file_list_round_trips() {
+        let info = dict(vec![
+            ("files", BencodeValue::List(Vec::new())),
+            ("length", BencodeValue::Integer(3)),
+            ("name", string(b"n")),
+            ("piece length", BencodeValue::Integer(16384)),
+            ("pieces", string(&[7; 20])),
+        ]);
+        let torrent = round_trip(dict(vec![("info", info)]));
+        assert!(!torrent.info.is_directory);
+        assert_eq!(torrent.total_length(), 3);
+    }
+
+    fn single_file_info() -> BencodeValue {
+        dict(vec![
+            ("length", BencodeValue::Integer(3)),
+            ("name", string(b"n")),
+            ("piece length", BencodeValue::Integer(16384)),
+            ("pieces", string(&[7; 20])),
+        ])
+    }
+
+    fn node(host: &[u8], port: i64) -> BencodeValue {
+        BencodeValue::List(vec![string(host), BencodeValue::Integer(port)])
+    }
+
+    #[test]
+    fn dht_nodes_are_parsed() {
+        let torrent = round_trip(dict(vec![
+            ("info", single_file_info()),
+            (
+                "nodes",
+                BencodeValue::List(vec![
+                    node(b"router.example.com", 6881),
+                    node(b"2001:db8::1", 51413),
+                    node(b"192.0.2.7", 0),
+                ]),
+            ),
+        ]));
+        assert_eq!(
+            torrent.nodes,
+            [
+                DhtNode {
+                    host: "router.example.com".to_string(),
+                    port: 6881
+                },
+                DhtNode {
+                    host: "2001:db8::1".to_string(),
+                    port: 51413
+                },
+                DhtNode {
+                    host: "192.0.2.7".to_string(),
+                    port: 0
+                },
+            ]
+        );
+        assert!(torrent.extra.is_empty());
+        assert!(torrent.is_trackerless());
+    }
+
+    #[test]
+    fn malformed_dht_nodes_are_skipped_but_kept() {
+        let torrent = round_trip(dict(vec![
+            ("info", single_file_info()),
+            (
+                "nodes",
+                BencodeValue::List(vec![
+                    node(b"router.example.com", 6881),
+                    node(b"192.0.2.7", 70000),
+                    node(b"\xff", 6881),
+                    string(b"192.0.2.8:6881"),
+                    BencodeValue::List(vec![string(b"192.0.2.9")]),
+                ]),
+            ),
+        ]));
+        assert_eq!(torrent.nodes.len(), 1);
+        assert_eq!(torrent.nodes[0].host, "router.example.com");
+
+        // Not a list at all
+        let torrent = round_trip(dict(vec![
+            ("info", single_file_info()),
+            ("nodes", string(b"router.example.com")),
+        ]));
+        assert!(torrent.nodes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn dht_nodes_resolve_to_addresses() {
+        let torrent = round_trip(dict(vec![
+            ("info", single_file_info()),
+            (
+                "nodes",
+                BencodeValue::List(vec![node(b"127.0.0.1", 6881), node(b"::1", 6882)]),
+            ),
+        ]));
+        assert_eq!(
+            torrent.dht_bootstrap_nodes().await,
+            [
+                "127.0.0.1:6881".parse::<SocketAddr>().unwrap(),
+                "[::1]:6882".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn torrents_with_trackers_are_not_trackerless() {
+        let torrent = round_trip(dict(vec![
+            ("announce", string(b"udp://tracker.example:6969")),
+            ("info", single_file_info()),
+            ("nodes", BencodeValue::List(vec![node(b"192.0.2.7", 6881)])),
+        ]));
+        assert!(!torrent.is_trackerless());
+
+        // An announce-list of empty tiers lists no trackers
+        let torrent = round_trip(dict(vec![
+            (
+                "announce-list",
+                BencodeValue::List(vec![BencodeValue::List(Vec::new())]),
+            ),
+            ("info", single_file_info()),
+        ]));
+        assert!(torrent.is_trackerless());
+    }
+
+    fn tiers(tiers: &[&[&str]]) -> Vec<Vec<String>> {
+        tiers
+            .iter()
+            .map(|tier| tier.iter().map(|url| url.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn announce_is_optional() {
+        let torrent = round_trip(dict(vec![("info", single_file_info())]));
+        assert_eq!(torrent.announce, None);
+        assert!(torrent.trackers().is_empty());
+
+        let torrent = TorrentFile::from_info(single_file_info(), Vec::new()).unwrap();
+        assert_eq!(torrent.announce, None);
+        assert!(torrent.is_trackerless());
+        assert_eq!(
+            encode(&torrent.info.to_bencode()),
+            encode(&single_file_info())
+        );
+
+        assert!(TorrentFile::parse(dict(vec![("announce", BencodeValue::Integer(1))])).is_err());
+        assert!(TorrentFile::parse(dict(vec![])).is_err());
+    }
+
+    #[test]
+    fn from_info_takes_trackers_from_elsewhere() {
+        let trackers = tiers(&[
+            &[],
+            &["udp://a.example:6969", "udp://b.example:6969"],
+            &["http://c.example/announce"],
+        ]);
+        let torrent = TorrentFile::from_info(single_file_info(), trackers).unwrap();
+        assert_eq!(torrent.announce.as_deref(), Some("udp://a.example:6969"));
+        assert_eq!(
+            torrent.trackers(),
+            tiers(&[
+                &["udp://a.example:6969", "udp://b.example:6969"],
+                &["http://c.example/announce"]
+            ])
+        );
+        let parsed = round_trip(torrent.to_bencode());
+        assert_eq!(parsed.info_hash, torrent.info_hash);
+        assert_eq!(parsed.trackers(), torrent.trackers());
+    }
+
+    #[test]
+    fn announce_list_takes_precedence_over_announce() {
+        let torrent = round_trip(dict(vec![
+            ("announce", string(b"http://ignored.example/announce")),
+            (
+                "announce-list",
+                BencodeValue::List(vec![
+                    BencodeValue::List(vec![string(b"http://a.example/announce")]),
+                    BencodeValue::List(Vec::new()),
+                    BencodeValue::List(vec![string(b"udp://b.example:6969")]),
+                ]),
+            ),
+            ("info", single_file_info()),
+        ]));
+        assert_eq!(
+            torrent.trackers(),
+            tiers(&[&["http://a.example/announce"], &["udp://b.example:6969"]])
+        );
+
+        // Without a usable announce-list, announce is a tier of its own
+        let torrent = round_trip(dict(vec![
+            ("announce", string(b"http://only.example/announce")),
+            ("announce-list", BencodeValue::List(Vec::new())),
+            ("info", single_file_info()),
+        ]));
+        assert_eq!(
+            torrent.trackers(),
+            tiers(&[&["http://only.example/announce"]])
+        );
+    }
 }
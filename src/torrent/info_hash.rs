@@ -24,9 +24,13 @@ use std::collections::HashMap;
 ///
 /// # Example
 /// ```
+/// use rs_torrent_client::torrent::info_hash::calculate_info_hash;
+/// use std::collections::HashMap;
+///
 /// let info_dict = HashMap::new();
 /// let hash = calculate_info_hash(&info_dict)?;
 /// assert_eq!(hash.len(), 20);
+/// # Ok::<(), anyhow::Error>(())
 /// ```
 pub fn calculate_info_hash(info_dict: &HashMap<Vec<u8>, BencodeValue>) -> Result<[u8; 20]> {
     let mut buffer = Vec::new();
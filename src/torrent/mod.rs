@@ -5,6 +5,8 @@ use serde_bencode;
 use thiserror::Error;
 pub mod file;
 pub mod info_hash;
+pub mod signature;
+pub mod validate;
 #[derive(Debug, Error)]
 pub enum TorrentError {
     #[error("I/O error: {0}")]
@@ -40,14 +42,29 @@ pub enum TorrentError {
     #[error("Bencode deserialization error: {0}")]
     BencodeDe(#[from] serde_bencode::Error),
 
+    #[error("Tracker returned an error: {0}")]
+    TrackerFailure(String),
+
+    #[error("Invalid tracker response: {0}")]
+    InvalidTrackerResponse(String),
+
+    #[error("Signature error: {0}")]
+    Signature(String),
+
     #[error("Handshake failed: Invalid protocol identifier")]
     HandshakeInvalidProtocol,
 
     #[error("Handshake failed: Info hash mismatch")]
     HandshakeInfoHashMismatch,
 
+    #[error("Handshake failed: Peer ID mismatch")]
+    HandshakePeerIdMismatch,
+
     #[error("Handshake timed out")]
     HandshakeTimeout,
+
+    #[error("Peer protocol error: {0}")]
+    PeerProtocol(String),
 }
 
 /// Result type for torrent operations derived from `std::result`
@@ -0,0 +1,444 @@
+//! Torrent signing and signature verification (BEP 35).
+//!
+//! This module parses the root `signatures` dictionary, signs the info dictionary with a local RSA key,
+//! and verifies existing signatures against the certificate embedded in the torrent or a trusted public key.
+use super::TorrentError;
+use crate::bencode::{self, BencodeValue};
+use crate::torrent::file::TorrentFile;
+use anyhow::Result;
+use rsa::pkcs1v15::{Signature as RsaSignature, SigningKey, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha1::Sha1;
+use std::collections::HashMap;
+use x509_cert::Certificate;
+use x509_cert::der::{Decode, Encode};
+
+/// Outcome of checking a signature when the torrent is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// The signature matches the public key in the embedded certificate.
+    Valid,
+    /// The signature does not match the public key in the embedded certificate.
+    Invalid,
+    /// No certificate is embedded, so the signature can only be checked against a trusted key.
+    Unverified,
+    /// The embedded certificate could not be parsed or does not hold an RSA key.
+    BadCertificate,
+}
+
+/// A single entry of the `signatures` dictionary.
+///
+/// Note that a `Valid` status only proves the signature matches the embedded certificate;
+/// whether that certificate is trusted is up to the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    /// The signer's identity, used as the key in the `signatures` dictionary.
+    pub signer: String,
+    /// DER-encoded X.509 certificate of the signer, if embedded.
+    pub certificate: Option<Vec<u8>>,
+    /// Optional signed extension dictionary, covered by the signature alongside the info dict.
+    pub info: Option<HashMap<Vec<u8>, BencodeValue>>,
+    /// RSA (PKCS #1 v1.5, SHA-1) signature bytes.
+    pub signature: Vec<u8>,
+    /// Result of verifying against the embedded certificate at load time.
+    pub status: SignatureStatus,
+}
+
+impl Signature {
+    /// Converts the signature back into its bencode dictionary form.
+    pub fn to_bencode(&self) -> BencodeValue {
+        let mut dict = HashMap::new();
+        if let Some(certificate) = &self.certificate {
+            dict.insert(
+                b"certificate".to_vec(),
+                BencodeValue::String(certificate.clone()),
+            );
+        }
+        if let Some(info) = &self.info {
+            dict.insert(b"info".to_vec(), BencodeValue::Dict(info.clone()));
+        }
+        dict.insert(
+            b"signature".to_vec(),
+            BencodeValue::String(self.signature.clone()),
+        );
+        BencodeValue::Dict(dict)
+    }
+
+    /// Verifies the signature over the given bencoded info dictionary with a trusted public key.
+    pub fn verify_with(&self, info: &[u8], key: &RsaPublicKey) -> bool {
+        let Ok(message) = signed_message(info, self.info.as_ref()) else {
+            return false;
+        };
+        let Ok(signature) = RsaSignature::try_from(self.signature.as_slice()) else {
+            return false;
+        };
+        VerifyingKey::<Sha1>::new(key.clone())
+            .verify(&message, &signature)
+            .is_ok()
+    }
+
+    /// Verifies the signature against the public key of its embedded certificate.
+    fn verify_embedded(&self, info: &[u8]) -> SignatureStatus {
+        let Some(certificate) = &self.certificate else {
+            return SignatureStatus::Unverified;
+        };
+        match certificate_public_key(certificate) {
+            Ok(key) if self.verify_with(info, &key) => SignatureStatus::Valid,
+            Ok(_) => SignatureStatus::Invalid,
+            Err(_) => SignatureStatus::BadCertificate,
+        }
+    }
+}
+
+/**
+Builds the byte string a signature covers.
+
+# Torrent Protocol Context:
+BEP 35 signs the bencoded info dictionary, followed by the bencoded signature `info`
+dictionary when the signer includes one. The info bytes must be the ones the info hash is
+computed from: re-encoding the parsed `InfoDict` could differ from them and break signatures.
+*/
+fn signed_message(
+    info: &[u8],
+    extension: Option<&HashMap<Vec<u8>, BencodeValue>>,
+) -> Result<Vec<u8>> {
+    let mut message = info.to_vec();
+    if let Some(extension) = extension {
+        bencode::encoder::encode(&mut message, &BencodeValue::Dict(extension.clone()))?;
+    }
+    Ok(message)
+}
+
+/// Extracts the RSA public key from a DER-encoded X.509 certificate.
+fn certificate_public_key(der: &[u8]) -> Result<RsaPublicKey> {
+    let certificate = Certificate::from_der(der)
+        .map_err(|e| TorrentError::Signature(format!("Invalid certificate: {}", e)))?;
+    let spki = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|e| TorrentError::Signature(format!("Invalid public key info: {}", e)))?;
+    let key = RsaPublicKey::from_public_key_der(&spki)
+        .map_err(|e| TorrentError::Signature(format!("Certificate key is not RSA: {}", e)))?;
+    Ok(key)
+}
+
+/**
+Parses the `signatures` dictionary and verifies each entry against the info dictionary.
+
+# How it works:
+1. Takes the `signatures` value, which maps signer identities to signature dictionaries.
+2. Extracts the required `signature` bytes and the optional `certificate` and `info` keys.
+3. Verifies each signature against its embedded certificate and records the status.
+4. Returns an error if the structure is invalid.
+*/
+pub(crate) fn parse_signatures(value: BencodeValue, info: &[u8]) -> Result<Vec<Signature>> {
+    let dict = match value {
+        BencodeValue::Dict(d) => d,
+        _ => {
+            return Err(
+                TorrentError::InvalidFormat("Signatures not a dictionary".to_string()).into(),
+            );
+        }
+    };
+
+    let mut signatures = Vec::with_capacity(dict.len());
+    for (signer, entry) in dict {
+        let signer = String::from_utf8(signer).map_err(|e| {
+            TorrentError::InvalidFormat(format!("Invalid signer name (not UTF-8): {}", e))
+        })?;
+        let mut entry = match entry {
+            BencodeValue::Dict(d) => d,
+            _ => {
+                return Err(
+                    TorrentError::InvalidFormat("Signature entry not a dict".to_string()).into(),
+                );
+            }
+        };
+        let signature = match entry.remove(b"signature".as_slice()) {
+            Some(BencodeValue::String(s)) => s,
+            _ => return Err(TorrentError::MissingField("signature".to_string()).into()),
+        };
+        let certificate = match entry.remove(b"certificate".as_slice()) {
+            Some(BencodeValue::String(s)) => Some(s),
+            _ => None,
+        };
+        let extension = match entry.remove(b"info".as_slice()) {
+            Some(BencodeValue::Dict(d)) => Some(d),
+            _ => None,
+        };
+
+        let mut parsed = Signature {
+            signer,
+            certificate,
+            info: extension,
+            signature,
+            status: SignatureStatus::Unverified,
+        };
+        parsed.status = parsed.verify_embedded(info);
+        signatures.push(parsed);
+    }
+    signatures.sort_by(|a, b| a.signer.cmp(&b.signer));
+    Ok(signatures)
+}
+
+impl TorrentFile {
+    /**
+    Signs the info dictionary and stores the signature under the given signer identity.
+
+    # How it works:
+    1. Takes the bencoded info dictionary the info hash covers, followed by the optional
+       extension dictionary, as BEP 35 requires.
+    2. Signs it with RSA PKCS #1 v1.5 over SHA-1 using the local private key.
+    3. Checks the new signature against `certificate`, if given, so a mismatched pair is caught early.
+    4. Replaces any existing signature from the same signer.
+    */
+    pub fn sign(
+        &mut self,
+        signer: &str,
+        key: &RsaPrivateKey,
+        certificate: Option<Vec<u8>>,
+        extension: Option<HashMap<Vec<u8>, BencodeValue>>,
+    ) -> Result<()> {
+        let message = signed_message(&self.info_bytes, extension.as_ref())?;
+        let signature = SigningKey::<Sha1>::new(key.clone())
+            .try_sign(&message)
+            .map_err(|e| TorrentError::Signature(e.to_string()))?;
+
+        let mut signature = Signature {
+            signer: signer.to_string(),
+            certificate,
+            info: extension,
+            signature: signature.to_vec(),
+            status: SignatureStatus::Unverified,
+        };
+        signature.status = signature.verify_embedded(&self.info_bytes);
+        self.signatures.retain(|s| s.signer != signer);
+        self.signatures.push(signature);
+        self.signatures.sort_by(|a, b| a.signer.cmp(&b.signer));
+        Ok(())
+    }
+
+    /**
+    Verifies the signature from `signer` against a trusted public key.
+
+    # How it works:
+    1. Looks up the signature by signer identity.
+    2. Returns `false` if there is none, otherwise checks it over the bencoded info dictionary.
+    */
+    pub fn verify_signature(&self, signer: &str, key: &RsaPublicKey) -> bool {
+        self.signatures
+            .iter()
+            .find(|s| s.signer == signer)
+            .is_some_and(|s| s.verify_with(&self.info_bytes, key))
+    }
+
+    /// Returns `true` if at least one signature matches the certificate embedded next to it.
+    ///
+    /// Anyone can embed a certificate of their own, so this says nothing about who signed the
+    /// torrent; check authenticity with `verify_signature` and a trusted key instead.
+    pub fn has_self_consistent_signature(&self) -> bool {
+        self.signatures
+            .iter()
+            .any(|s| s.status == SignatureStatus::Valid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs8::EncodePublicKey;
+    use std::time::Duration;
+    use x509_cert::der::asn1::BitString;
+    use x509_cert::der::oid::ObjectIdentifier;
+    use x509_cert::name::Name;
+    use x509_cert::serial_number::SerialNumber;
+    use x509_cert::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+    use x509_cert::time::Validity;
+    use x509_cert::{Certificate, TbsCertificate, Version};
+
+    /// sha1WithRSAEncryption
+    const SHA1_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.5");
+
+    fn key() -> RsaPrivateKey {
+        // Small keys keep the tests fast; the size does not matter for the checks made here
+        RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 512).unwrap()
+    }
+
+    /// Builds a DER certificate carrying the public half of `key`.
+    ///
+    /// Only the subject public key is read when verifying, so the certificate's own signature is
+    /// left empty.
+    fn certificate(key: &RsaPrivateKey) -> Vec<u8> {
+        let spki = key.to_public_key().to_public_key_der().unwrap();
+        let algorithm = AlgorithmIdentifierOwned {
+            oid: SHA1_WITH_RSA,
+            parameters: None,
+        };
+        Certificate {
+            tbs_certificate: TbsCertificate {
+                version: Version::V3,
+                serial_number: SerialNumber::from(1u32),
+                signature: algorithm.clone(),
+                issuer: Name::default(),
+                validity: Validity::from_now(Duration::from_secs(3600)).unwrap(),
+                subject: Name::default(),
+                subject_public_key_info: SubjectPublicKeyInfoOwned::from_der(spki.as_bytes())
+                    .unwrap(),
+                issuer_unique_id: None,
+                subject_unique_id: None,
+                extensions: None,
+            },
+            signature_algorithm: algorithm,
+            signature: BitString::from_bytes(&[]).unwrap(),
+        }
+        .to_der()
+        .unwrap()
+    }
+
+    fn torrent() -> TorrentFile {
+        let info = HashMap::from([
+            (b"name".to_vec(), BencodeValue::String(b"file.bin".to_vec())),
+            (b"piece length".to_vec(), BencodeValue::Integer(16384)),
+            (b"pieces".to_vec(), BencodeValue::String(vec![0; 20])),
+            (b"length".to_vec(), BencodeValue::Integer(1000)),
+        ]);
+        TorrentFile::parse(BencodeValue::Dict(HashMap::from([(
+            b"info".to_vec(),
+            BencodeValue::Dict(info),
+        )])))
+        .unwrap()
+    }
+
+    /// Encodes `torrent` and parses it again, as when a signed torrent is saved and loaded.
+    fn reload(torrent: &TorrentFile) -> TorrentFile {
+        TorrentFile::parse(torrent.to_bencode()).unwrap()
+    }
+
+    #[test]
+    fn signed_torrent_is_valid_after_reload() {
+        let key = key();
+        let mut torrent = torrent();
+        torrent
+            .sign("signer", &key, Some(certificate(&key)), None)
+            .unwrap();
+        assert_eq!(torrent.signatures[0].status, SignatureStatus::Valid);
+
+        let loaded = reload(&torrent);
+        assert_eq!(loaded.signatures.len(), 1);
+        assert_eq!(loaded.signatures[0].status, SignatureStatus::Valid);
+        assert!(loaded.has_self_consistent_signature());
+        assert!(loaded.verify_signature("signer", &key.to_public_key()));
+    }
+
+    #[test]
+    fn extension_dictionary_is_covered() {
+        let key = key();
+        let extension = HashMap::from([(b"note".to_vec(), BencodeValue::String(b"hi".to_vec()))]);
+        let mut torrent = torrent();
+        torrent
+            .sign("signer", &key, Some(certificate(&key)), Some(extension))
+            .unwrap();
+        assert_eq!(
+            reload(&torrent).signatures[0].status,
+            SignatureStatus::Valid
+        );
+
+        let mut tampered = torrent.to_bencode();
+        if let BencodeValue::Dict(root) = &mut tampered
+            && let Some(BencodeValue::Dict(signatures)) = root.get_mut(b"signatures".as_slice())
+            && let Some(BencodeValue::Dict(entry)) = signatures.get_mut(b"signer".as_slice())
+        {
+            entry.insert(
+                b"info".to_vec(),
+                BencodeValue::Dict(HashMap::from([(
+                    b"note".to_vec(),
+                    BencodeValue::String(b"bye".to_vec()),
+                )])),
+            );
+        }
+        let loaded = TorrentFile::parse(tampered).unwrap();
+        assert_eq!(loaded.signatures[0].status, SignatureStatus::Invalid);
+    }
+
+    #[test]
+    fn tampered_info_is_invalid() {
+        let key = key();
+        let mut torrent = torrent();
+        torrent
+            .sign("signer", &key, Some(certificate(&key)), None)
+            .unwrap();
+
+        let mut tampered = torrent.to_bencode();
+        if let BencodeValue::Dict(root) = &mut tampered
+            && let Some(BencodeValue::Dict(info)) = root.get_mut(b"info".as_slice())
+        {
+            info.insert(b"name".to_vec(), BencodeValue::String(b"evil.bin".to_vec()));
+        }
+        let loaded = TorrentFile::parse(tampered).unwrap();
+        assert_eq!(loaded.signatures[0].status, SignatureStatus::Invalid);
+        assert!(!loaded.has_self_consistent_signature());
+        assert!(!loaded.verify_signature("signer", &key.to_public_key()));
+    }
+
+    #[test]
+    fn unknown_info_keys_are_signed_as_loaded() {
+        let key = key();
+        let mut torrent = torrent();
+        let mut root = torrent.to_bencode();
+        if let BencodeValue::Dict(root) = &mut root
+            && let Some(BencodeValue::Dict(info)) = root.get_mut(b"info".as_slice())
+        {
+            info.insert(b"x-custom".to_vec(), BencodeValue::Integer(7));
+        }
+        torrent = TorrentFile::parse(root).unwrap();
+        torrent
+            .sign("signer", &key, Some(certificate(&key)), None)
+            .unwrap();
+        assert_eq!(
+            reload(&torrent).signatures[0].status,
+            SignatureStatus::Valid
+        );
+    }
+
+    #[test]
+    fn wrong_certificate_is_invalid() {
+        let (key, other) = (key(), key());
+        let mut torrent = torrent();
+        torrent
+            .sign("signer", &key, Some(certificate(&other)), None)
+            .unwrap();
+        assert_eq!(torrent.signatures[0].status, SignatureStatus::Invalid);
+        assert_eq!(
+            reload(&torrent).signatures[0].status,
+            SignatureStatus::Invalid
+        );
+        assert!(!torrent.verify_signature("signer", &other.to_public_key()));
+        assert!(torrent.verify_signature("signer", &key.to_public_key()));
+    }
+
+    #[test]
+    fn missing_or_bad_certificate() {
+        let key = key();
+        let mut torrent = torrent();
+        torrent.sign("plain", &key, None, None).unwrap();
+        torrent
+            .sign("broken", &key, Some(b"not a certificate".to_vec()), None)
+            .unwrap();
+
+        let loaded = reload(&torrent);
+        let status = |signer: &str| {
+            loaded
+                .signatures
+                .iter()
+                .find(|s| s.signer == signer)
+                .map(|s| s.status)
+        };
+        assert_eq!(status("plain"), Some(SignatureStatus::Unverified));
+        assert_eq!(status("broken"), Some(SignatureStatus::BadCertificate));
+        assert!(loaded.verify_signature("plain", &key.to_public_key()));
+        assert!(!loaded.verify_signature("nobody", &key.to_public_key()));
+    }
+}
@@ -0,0 +1,564 @@
+//! Torrent metadata validation.
+//!
+//! This module inspects a parsed `TorrentFile` and produces a structured report of warnings and errors,
+//! so problems with torrents from untrusted sources can be caught before a download is started.
+use crate::bencode;
+use crate::torrent::file::TorrentFile;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+
+/// Upper bound on the encoded size of the info dictionary before a warning is raised.
+pub const MAX_METADATA_SIZE: usize = 10 * 1024 * 1024;
+
+/// Tracker URL schemes this client knows how to announce to.
+const SUPPORTED_TRACKER_SCHEMES: [&str; 3] = ["http", "https", "udp"];
+
+/// How serious a validation issue is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The torrent is usable, but something about it is unusual or may confuse other clients.
+    Warning,
+    /// The torrent cannot be downloaded correctly as-is.
+    Error,
+}
+
+/// A single problem found while validating a torrent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// The number of piece hashes does not match `total_length / piece_length` (rounded up).
+    PieceCountMismatch { expected: usize, actual: usize },
+    /// The piece length is zero or negative.
+    InvalidPieceLength(i64),
+    /// The piece length is not a power of two.
+    NonPowerOfTwoPieceLength(i64),
+    /// A file in the torrent has a length of zero.
+    EmptyFile(PathBuf),
+    /// A file has a negative length.
+    NegativeFileLength(PathBuf),
+    /// The same path appears more than once in the file list.
+    DuplicatePath(PathBuf),
+    /// Two paths differ only by letter case and would collide on case-insensitive file systems.
+    CaseInsensitiveCollision(PathBuf, PathBuf),
+    /// The raw torrent is not canonically encoded (dictionary keys are unsorted or duplicated).
+    UnsortedKeys,
+    /// The encoded info dictionary exceeds `MAX_METADATA_SIZE`.
+    OversizedMetadata { size: usize, limit: usize },
+    /// A tracker URL could not be parsed or uses a scheme this client cannot announce to.
+    UnsupportedTrackerUrl(String),
+    /// The torrent is private but has no tracker, so its only peer source is the DHT
+    /// (through `nodes`), which private torrents are not allowed to use.
+    PrivateWithoutTrackers,
+}
+
+impl Issue {
+    /// Returns the severity associated with this kind of issue.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Issue::PieceCountMismatch { .. }
+            | Issue::InvalidPieceLength(_)
+            | Issue::NegativeFileLength(_)
+            | Issue::DuplicatePath(_)
+            | Issue::PrivateWithoutTrackers => Severity::Error,
+            Issue::NonPowerOfTwoPieceLength(_)
+            | Issue::EmptyFile(_)
+            | Issue::CaseInsensitiveCollision(_, _)
+            | Issue::UnsortedKeys
+            | Issue::OversizedMetadata { .. }
+            | Issue::UnsupportedTrackerUrl(_) => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::PieceCountMismatch { expected, actual } => write!(
+                f,
+                "expected {} piece hashes for the total length, found {}",
+                expected, actual
+            ),
+            Issue::InvalidPieceLength(len) => write!(f, "invalid piece length: {}", len),
+            Issue::NonPowerOfTwoPieceLength(len) => {
+                write!(f, "piece length {} is not a power of two", len)
+            }
+            Issue::EmptyFile(path) => write!(f, "empty file: {}", path.display()),
+            Issue::NegativeFileLength(path) => {
+                write!(f, "negative file length: {}", path.display())
+            }
+            Issue::DuplicatePath(path) => write!(f, "duplicate path: {}", path.display()),
+            Issue::CaseInsensitiveCollision(a, b) => write!(
+                f,
+                "paths collide on case-insensitive file systems: {} and {}",
+                a.display(),
+                b.display()
+            ),
+            Issue::UnsortedKeys => write!(
+                f,
+                "dictionary keys are not sorted; the info hash may differ from other clients"
+            ),
+            Issue::OversizedMetadata { size, limit } => write!(
+                f,
+                "info dictionary is {} bytes, larger than the {} byte limit",
+                size, limit
+            ),
+            Issue::UnsupportedTrackerUrl(url) => write!(f, "unsupported tracker URL: {}", url),
+            Issue::PrivateWithoutTrackers => {
+                write!(f, "private torrent has no trackers and cannot use DHT")
+            }
+        }
+    }
+}
+
+/// The result of validating a torrent: every issue found, in the order checks were run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    /// Returns `true` if no errors were found. Warnings do not make a report fail.
+    pub fn is_ok(&self) -> bool {
+        !self.issues.iter().any(|i| i.severity() == Severity::Error)
+    }
+
+    /// Returns the issues with `Severity::Error`.
+    pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|i| i.severity() == Severity::Error)
+    }
+
+    /// Returns the issues with `Severity::Warning`.
+    pub fn warnings(&self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|i| i.severity() == Severity::Warning)
+    }
+}
+
+impl TorrentFile {
+    /**
+    Validates the parsed torrent and returns a report of warnings and errors.
+
+    # How it works:
+    1. Checks the piece geometry: piece length, power-of-two size and piece count.
+    2. Checks the file list for empty files, duplicates and case-insensitive collisions.
+    3. Re-encodes the info dictionary to check its size against `MAX_METADATA_SIZE`.
+    4. Checks `announce` and every announce-list URL for a scheme the tracker client supports.
+    5. Flags private torrents that have no tracker to announce to, such as DHT-only ones.
+
+    Key ordering cannot be checked once the data is parsed; use `validate_raw` for that.
+    */
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        self.check_pieces(&mut report);
+        self.check_files(&mut report);
+        self.check_metadata_size(&mut report);
+        self.check_trackers(&mut report);
+        report
+    }
+
+    /**
+    Validates the torrent together with the raw bytes it was parsed from.
+
+    # How it works:
+    1. Runs every check from `validate`.
+    2. Walks the raw bytes of the root value and reports `UnsortedKeys` if the keys of any
+       dictionary are not in strictly ascending order.
+    3. Bytes after the root value are ignored, as the decoder ignores them; input that is not
+       valid bencode is left to the parser to reject.
+    */
+    pub fn validate_raw(&self, raw: &[u8]) -> ValidationReport {
+        let mut report = self.validate();
+        if has_sorted_keys(raw) == Some(false) {
+            report.issues.push(Issue::UnsortedKeys);
+        }
+        report
+    }
+
+    fn check_pieces(&self, report: &mut ValidationReport) {
+        let piece_length = self.info.piece_length;
+        if piece_length <= 0 {
+            report.issues.push(Issue::InvalidPieceLength(piece_length));
+            return;
+        }
+        if !(piece_length as u64).is_power_of_two() {
+            report
+                .issues
+                .push(Issue::NonPowerOfTwoPieceLength(piece_length));
+        }
+        let total_length = self.total_length().max(0);
+        let expected = (total_length as u64).div_ceil(piece_length as u64) as usize;
+        if expected != self.num_pieces() {
+            report.issues.push(Issue::PieceCountMismatch {
+                expected,
+                actual: self.num_pieces(),
+            });
+        }
+    }
+
+    fn check_files(&self, report: &mut ValidationReport) {
+        if !self.info.is_directory {
+            match self.info.length {
+                Some(0) => report
+                    .issues
+                    .push(Issue::EmptyFile(PathBuf::from(&self.info.name))),
+                Some(len) if len < 0 => report
+                    .issues
+                    .push(Issue::NegativeFileLength(PathBuf::from(&self.info.name))),
+                _ => {}
+            }
+            return;
+        }
+
+        let mut seen: HashSet<PathBuf> = HashSet::new();
+        let mut lowercase: HashMap<String, PathBuf> = HashMap::new();
+        for file in &self.info.files {
+            let path: PathBuf = file.path.iter().collect();
+            if file.length == 0 {
+                report.issues.push(Issue::EmptyFile(path.clone()));
+            } else if file.length < 0 {
+                report.issues.push(Issue::NegativeFileLength(path.clone()));
+            }
+            if !seen.insert(path.clone()) {
+                report.issues.push(Issue::DuplicatePath(path));
+                continue;
+            }
+            let folded = path.to_string_lossy().to_lowercase();
+            if let Some(existing) = lowercase.get(&folded) {
+                report
+                    .issues
+                    .push(Issue::CaseInsensitiveCollision(existing.clone(), path));
+            } else {
+                lowercase.insert(folded, path);
+            }
+        }
+    }
+
+    fn check_metadata_size(&self, report: &mut ValidationReport) {
+        let mut buffer = Vec::new();
+        if bencode::encoder::encode(&mut buffer, &self.info.to_bencode()).is_ok()
+            && buffer.len() > MAX_METADATA_SIZE
+        {
+            report.issues.push(Issue::OversizedMetadata {
+                size: buffer.len(),
+                limit: MAX_METADATA_SIZE,
+            });
+        }
+    }
+
+    fn check_trackers(&self, report: &mut ValidationReport) {
+        // `announce` is linted even when the announce-list overrides it, since older clients
+        // that ignore the announce-list still use it
+        let mut urls: Vec<&String> = self.announce.iter().collect();
+        for url in self.announce_list.iter().flatten() {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        for url in urls {
+            let supported = url::Url::parse(url)
+                .map(|u| SUPPORTED_TRACKER_SCHEMES.contains(&u.scheme()))
+                .unwrap_or(false);
+            if !supported {
+                report
+                    .issues
+                    .push(Issue::UnsupportedTrackerUrl(url.to_string()));
+            }
+        }
+
+        if self.info.private && self.trackers().is_empty() {
+            report.issues.push(Issue::PrivateWithoutTrackers);
+        }
+    }
+}
+
+/// A container being walked by `has_sorted_keys`.
+enum Frame<'a> {
+    List,
+    Dict {
+        last_key: Option<&'a [u8]>,
+        at_key: bool,
+    },
+}
+
+/**
+Checks that every dictionary in the first bencode value of `raw` has strictly ascending keys.
+
+# How it works:
+1. Walks the value with an explicit stack, so deeply nested input cannot overflow the call stack.
+2. Compares each dictionary key with the previous key of the same dictionary; an equal key is a
+   duplicate and counts as unsorted too.
+3. Stops at the end of the first value, whatever follows it.
+
+# Returns
+* `Option<bool>` - Whether the keys are sorted, or `None` if `raw` does not start with a
+  complete bencode value
+*/
+fn has_sorted_keys(raw: &[u8]) -> Option<bool> {
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut sorted = true;
+    let mut pos = 0;
+    loop {
+        let at_key = matches!(stack.last(), Some(Frame::Dict { at_key: true, .. }));
+        match *raw.get(pos)? {
+            b'e' if at_key || matches!(stack.last(), Some(Frame::List)) => {
+                stack.pop();
+                pos += 1;
+            }
+            b'l' if !at_key => {
+                stack.push(Frame::List);
+                pos += 1;
+                continue;
+            }
+            b'd' if !at_key => {
+                stack.push(Frame::Dict {
+                    last_key: None,
+                    at_key: true,
+                });
+                pos += 1;
+                continue;
+            }
+            b'i' if !at_key => pos += raw[pos..].iter().position(|&b| b == b'e')? + 1,
+            b'0'..=b'9' => {
+                let colon = pos + raw[pos..].iter().position(|&b| b == b':')?;
+                let length: usize = std::str::from_utf8(&raw[pos..colon]).ok()?.parse().ok()?;
+                let end = (colon + 1).checked_add(length)?;
+                let bytes = raw.get(colon + 1..end)?;
+                pos = end;
+                if let Some(Frame::Dict { last_key, at_key }) = stack.last_mut()
+                    && *at_key
+                {
+                    if last_key.is_some_and(|last| last >= bytes) {
+                        sorted = false;
+                    }
+                    *last_key = Some(bytes);
+                    *at_key = false;
+                    continue;
+                }
+            }
+            _ => return None,
+        }
+        // A value has ended
+        match stack.last_mut() {
+            None => return Some(sorted),
+            Some(Frame::Dict { at_key, .. }) => *at_key = true,
+            Some(Frame::List) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::{BencodeValue, decoder};
+    use crate::torrent::file::FileDict;
+
+    /// A valid single-file torrent: two pieces of 16 KiB.
+    const VALID: &str = "d8:announce31:http://tracker.example/announce4:infod6:lengthi32768e\
+        4:name4:file12:piece lengthi16384e6:pieces40:aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbbee";
+
+    fn torrent() -> TorrentFile {
+        TorrentFile::parse(decoder::decode(VALID.as_bytes()).unwrap()).unwrap()
+    }
+
+    fn file(path: &str, length: i64) -> FileDict {
+        FileDict {
+            length,
+            path: vec!["dir".to_string(), path.to_string()],
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn valid_torrents_have_no_issues() {
+        let torrent = torrent();
+        assert_eq!(torrent.validate(), ValidationReport::default());
+        assert_eq!(
+            torrent.validate_raw(VALID.as_bytes()),
+            ValidationReport::default()
+        );
+    }
+
+    #[test]
+    fn piece_geometry_is_checked() {
+        let mut torrent = torrent();
+        torrent.info.piece_length = 0;
+        assert_eq!(torrent.validate().issues, [Issue::InvalidPieceLength(0)]);
+
+        torrent.info.piece_length = 10_000;
+        let report = torrent.validate();
+        assert_eq!(
+            report.issues,
+            [
+                Issue::NonPowerOfTwoPieceLength(10_000),
+                Issue::PieceCountMismatch {
+                    expected: 4,
+                    actual: 2
+                },
+            ]
+        );
+        assert_eq!(
+            report.warnings().collect::<Vec<_>>(),
+            [&Issue::NonPowerOfTwoPieceLength(10_000)]
+        );
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn file_list_is_checked() {
+        let mut torrent = torrent();
+        torrent.info.is_directory = true;
+        torrent.info.length = None;
+        torrent.info.files = vec![
+            file("empty", 0),
+            file("negative", -1),
+            file("data", 32767),
+            file("data", 1),
+            file("Data", 1),
+        ];
+        let report = torrent.validate();
+        let path = |name: &str| ["dir", name].iter().collect::<PathBuf>();
+        for issue in [
+            Issue::EmptyFile(path("empty")),
+            Issue::NegativeFileLength(path("negative")),
+            Issue::DuplicatePath(path("data")),
+            Issue::CaseInsensitiveCollision(path("data"), path("Data")),
+        ] {
+            assert!(report.issues.contains(&issue), "missing {:?}", issue);
+        }
+        assert_eq!(report.errors().count(), 2);
+
+        // A single file is checked through `length`
+        let mut torrent = self::torrent();
+        torrent.info.length = Some(0);
+        assert!(
+            torrent
+                .validate()
+                .issues
+                .contains(&Issue::EmptyFile(PathBuf::from("file")))
+        );
+    }
+
+    #[test]
+    fn oversized_metadata_is_a_warning() {
+        let mut torrent = torrent();
+        torrent.info.extra.insert(
+            b"padding".to_vec(),
+            BencodeValue::String(vec![0; MAX_METADATA_SIZE]),
+        );
+        let report = torrent.validate();
+        assert!(matches!(
+            report.issues[..],
+            [Issue::OversizedMetadata { size, limit: MAX_METADATA_SIZE }] if size > MAX_METADATA_SIZE
+        ));
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn announce_is_checked_even_when_overridden() {
+        let mut torrent = torrent();
+        torrent.announce = Some("ftp://tracker.example/announce".to_string());
+        torrent.announce_list = vec![
+            vec!["udp://tracker.example:6969".to_string()],
+            vec![
+                "not a url".to_string(),
+                "ftp://tracker.example/announce".to_string(),
+            ],
+        ];
+        assert_eq!(
+            torrent.validate().issues,
+            [
+                Issue::UnsupportedTrackerUrl("ftp://tracker.example/announce".to_string()),
+                Issue::UnsupportedTrackerUrl("not a url".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn private_torrents_need_a_tracker() {
+        let mut torrent = torrent();
+        torrent.info.private = true;
+        assert!(torrent.validate().is_ok());
+
+        torrent.announce = None;
+        let report = torrent.validate();
+        assert_eq!(report.issues, [Issue::PrivateWithoutTrackers]);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn unsorted_and_duplicate_keys_are_reported() {
+        let torrent = torrent();
+        let unsorted = |raw: &str| {
+            torrent
+                .validate_raw(raw.as_bytes())
+                .issues
+                .contains(&Issue::UnsortedKeys)
+        };
+        assert!(unsorted("d1:bi1e1:ai2ee"));
+        assert!(unsorted("d1:ai1e1:ai2ee"));
+        // Nested dictionaries, also inside lists
+        assert!(unsorted("d1:ad1:bi1e1:ai2eee"));
+        assert!(unsorted("d1:ald1:b0:1:a0:eee"));
+        assert!(!unsorted("d1:ad1:ai1e1:bi2ee1:bl0:d1:a0:eee"));
+        assert!(!unsorted("d2:ab1:ce"));
+    }
+
+    #[test]
+    fn only_the_root_value_is_checked() {
+        let torrent = torrent();
+        let unsorted = |raw: &[u8]| {
+            torrent
+                .validate_raw(raw)
+                .issues
+                .contains(&Issue::UnsortedKeys)
+        };
+        let mut trailing = VALID.as_bytes().to_vec();
+        trailing.extend_from_slice(b"d1:bi1e1:ai2ee\n");
+        assert!(!unsorted(&trailing));
+        // Not key order problems, or not bencode at all
+        assert!(!unsorted(b"d1:ai-0ee"));
+        assert!(!unsorted(b"d1:b"));
+        assert!(!unsorted(b"d1:bi1e1:a"));
+        assert!(!unsorted(b"x"));
+    }
+
+    #[test]
+    fn every_issue_has_a_severity_and_message() {
+        let path = PathBuf::from("a");
+        let issues = [
+            (
+                Issue::PieceCountMismatch {
+                    expected: 1,
+                    actual: 2,
+                },
+                Severity::Error,
+            ),
+            (Issue::InvalidPieceLength(0), Severity::Error),
+            (Issue::NonPowerOfTwoPieceLength(3), Severity::Warning),
+            (Issue::EmptyFile(path.clone()), Severity::Warning),
+            (Issue::NegativeFileLength(path.clone()), Severity::Error),
+            (Issue::DuplicatePath(path.clone()), Severity::Error),
+            (
+                Issue::CaseInsensitiveCollision(path.clone(), path),
+                Severity::Warning,
+            ),
+            (Issue::UnsortedKeys, Severity::Warning),
+            (
+                Issue::OversizedMetadata { size: 2, limit: 1 },
+                Severity::Warning,
+            ),
+            (
+                Issue::UnsupportedTrackerUrl("x".to_string()),
+                Severity::Warning,
+            ),
+            (Issue::PrivateWithoutTrackers, Severity::Error),
+        ];
+        for (issue, severity) in issues {
+            assert_eq!(issue.severity(), severity, "{:?}", issue);
+            assert!(!issue.to_string().is_empty());
+        }
+    }
+}
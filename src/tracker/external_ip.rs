@@ -0,0 +1,324 @@
+//! External IP discovery (BEP 24) and BEP 42 node IDs.
+//!
+//! Trackers may report the address they saw a request come from in the `external ip` key, and peers
+//! do the same with the `yourip` extension field. Each report is a vote; the address with the most
+//! votes from distinct sources becomes the session's external address, which is used to recognise
+//! ourselves in peer lists and to derive a DHT node ID that other nodes can verify (BEP 42).
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+/// Upper bound on the votes remembered per address family; sources beyond it are ignored.
+pub const MAX_VOTES: usize = 256;
+
+/// Who reported an external address. Each source has one vote per address family.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum VoteSource {
+    /// A tracker, identified by its host name.
+    Tracker(String),
+    /// A peer, identified by its IP address so that one host cannot vote several times.
+    Peer(IpAddr),
+    /// A DHT node, identified by its IP address.
+    Dht(IpAddr),
+}
+
+/// Votes for the addresses of one family.
+#[derive(Debug, Default)]
+struct Ballot {
+    votes: HashMap<VoteSource, IpAddr>,
+    current: Option<IpAddr>,
+}
+
+impl Ballot {
+    /// Records a vote and returns `true` if the winning address changed.
+    fn vote(&mut self, source: VoteSource, ip: IpAddr) -> bool {
+        if self.votes.len() >= MAX_VOTES && !self.votes.contains_key(&source) {
+            return false;
+        }
+        self.votes.insert(source, ip);
+
+        let mut tally: HashMap<IpAddr, usize> = HashMap::new();
+        for ip in self.votes.values() {
+            *tally.entry(*ip).or_default() += 1;
+        }
+        let current_votes = self
+            .current
+            .and_then(|ip| tally.get(&ip).copied())
+            .unwrap_or(0);
+        let Some((&leader, &votes)) = tally.iter().max_by_key(|(_, votes)| **votes) else {
+            return false;
+        };
+        // Only switch when a rival strictly outvotes the current address, so ties do not flap
+        if Some(leader) != self.current && votes > current_votes {
+            self.current = Some(leader);
+            return true;
+        }
+        false
+    }
+}
+
+#[derive(Debug, Default)]
+struct Voter {
+    v4: Ballot,
+    v6: Ballot,
+}
+
+/// The session's view of its own external addresses, shared between trackers, peers and the DHT.
+///
+/// Cloning is cheap: clones share the same votes.
+#[derive(Debug, Clone, Default)]
+pub struct ExternalIp {
+    inner: Arc<Mutex<Voter>>,
+}
+
+impl ExternalIp {
+    /// Creates an empty voter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `source` saw us at `ip`.
+    ///
+    /// Unspecified, multicast and loopback addresses are ignored, and IPv4-mapped IPv6 addresses
+    /// count as IPv4.
+    ///
+    /// # Returns
+    /// * `bool` - `true` if this vote changed the external address of its family
+    pub fn vote(&self, source: VoteSource, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if ip.is_unspecified() || ip.is_multicast() || ip.is_loopback() {
+            return false;
+        }
+        let mut voter = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let changed = match ip {
+            IpAddr::V4(_) => voter.v4.vote(source, ip),
+            IpAddr::V6(_) => voter.v6.vote(source, ip),
+        };
+        if changed {
+            tracing::info!(%ip, "External address changed");
+        }
+        changed
+    }
+
+    /// Returns the external IPv4 address with the most votes, if any.
+    pub fn ipv4(&self) -> Option<IpAddr> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .v4
+            .current
+    }
+
+    /// Returns the external IPv6 address with the most votes, if any.
+    pub fn ipv6(&self) -> Option<IpAddr> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .v6
+            .current
+    }
+
+    /// Returns `true` if `addr` is one of our external addresses with our listen port,
+    /// i.e. a peer list entry pointing back at ourselves.
+    pub fn is_self(&self, addr: &SocketAddr, listen_port: u16) -> bool {
+        let ip = addr.ip().to_canonical();
+        addr.port() == listen_port && (self.ipv4() == Some(ip) || self.ipv6() == Some(ip))
+    }
+
+    /// Generates a BEP 42 DHT node ID for our external address, preferring IPv4.
+    ///
+    /// Returns `None` until an external address is known.
+    pub fn dht_node_id(&self) -> Option<[u8; 20]> {
+        let ip = self.ipv4().or_else(|| self.ipv6())?;
+        Some(node_id_for(ip, rand::rng().random()))
+    }
+}
+
+/**
+Derives a BEP 42 node ID from an external IP address.
+
+# How it works:
+1. Masks the address (`03 0f 3f ff` for IPv4, `01 03 07 0f 1f 3f 7f ff` for the first eight bytes of IPv6).
+2. Stores the low three bits of `r` in the top bits of the first masked byte.
+3. The first 21 bits of the node ID are the CRC32-C of the masked bytes, the last byte is `r`,
+   and everything in between is random.
+
+# Example
+```
+use rs_torrent_client::tracker::external_ip::{node_id_for, is_valid_node_id};
+
+let ip = "124.31.75.21".parse().unwrap();
+let id = node_id_for(ip, 1);
+assert_eq!(&id[..2], &[0x5f, 0xbf]);
+assert_eq!(id[2] & 0xf8, 0xb8);
+assert!(is_valid_node_id(&id, ip));
+```
+*/
+pub fn node_id_for(ip: IpAddr, r: u8) -> [u8; 20] {
+    let crc = crc32c(&masked_ip(ip, r));
+    let mut id = [0u8; 20];
+    rand::rng().fill(&mut id[..]);
+    id[0] = (crc >> 24) as u8;
+    id[1] = (crc >> 16) as u8;
+    id[2] = ((crc >> 8) as u8 & 0xf8) | (id[2] & 0x07);
+    id[19] = r;
+    id
+}
+
+/// Checks that a node ID was derived from `ip` as BEP 42 requires.
+pub fn is_valid_node_id(id: &[u8; 20], ip: IpAddr) -> bool {
+    let crc = crc32c(&masked_ip(ip, id[19]));
+    id[0] == (crc >> 24) as u8
+        && id[1] == (crc >> 16) as u8
+        && id[2] & 0xf8 == (crc >> 8) as u8 & 0xf8
+}
+
+/// Applies the BEP 42 mask to an address and mixes in the low bits of `r`.
+fn masked_ip(ip: IpAddr, r: u8) -> Vec<u8> {
+    const V4_MASK: [u8; 4] = [0x03, 0x0f, 0x3f, 0xff];
+    const V6_MASK: [u8; 8] = [0x01, 0x03, 0x07, 0x0f, 0x1f, 0x3f, 0x7f, 0xff];
+    let mut bytes: Vec<u8> = match ip.to_canonical() {
+        IpAddr::V4(ip) => ip
+            .octets()
+            .iter()
+            .zip(V4_MASK)
+            .map(|(b, m)| b & m)
+            .collect(),
+        IpAddr::V6(ip) => ip
+            .octets()
+            .iter()
+            .zip(V6_MASK)
+            .map(|(b, m)| b & m)
+            .collect(),
+    };
+    bytes[0] |= (r & 0x07) << 5;
+    bytes
+}
+
+/// CRC32-C (Castagnoli), as BEP 42 requires.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0x82F6_3B78 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn tracker(host: &str) -> VoteSource {
+        VoteSource::Tracker(host.to_string())
+    }
+
+    #[test]
+    fn first_vote_sets_the_address() {
+        let external = ExternalIp::new();
+        assert!(external.vote(tracker("a"), ip("203.0.113.1")));
+        assert_eq!(external.ipv4(), Some(ip("203.0.113.1")));
+        assert_eq!(external.ipv6(), None);
+        // The same vote again changes nothing
+        assert!(!external.vote(tracker("a"), ip("203.0.113.1")));
+    }
+
+    #[test]
+    fn a_rival_needs_strictly_more_votes() {
+        let external = ExternalIp::new();
+        external.vote(tracker("a"), ip("203.0.113.1"));
+        assert!(
+            !external.vote(tracker("b"), ip("203.0.113.2")),
+            "a tie keeps the current address"
+        );
+        assert_eq!(external.ipv4(), Some(ip("203.0.113.1")));
+        assert!(external.vote(VoteSource::Peer(ip("198.51.100.1")), ip("203.0.113.2")));
+        assert_eq!(external.ipv4(), Some(ip("203.0.113.2")));
+    }
+
+    #[test]
+    fn each_source_has_one_vote() {
+        let external = ExternalIp::new();
+        external.vote(tracker("a"), ip("203.0.113.1"));
+        external.vote(tracker("b"), ip("203.0.113.2"));
+        // Changing its mind moves the source's vote instead of adding one
+        assert!(external.vote(tracker("a"), ip("203.0.113.2")));
+        assert_eq!(external.ipv4(), Some(ip("203.0.113.2")));
+        assert!(!external.vote(tracker("a"), ip("203.0.113.2")));
+    }
+
+    #[test]
+    fn families_are_counted_separately() {
+        let external = ExternalIp::new();
+        external.vote(tracker("a"), ip("203.0.113.1"));
+        assert!(external.vote(tracker("a"), ip("2001:db8::1")));
+        assert_eq!(external.ipv4(), Some(ip("203.0.113.1")));
+        assert_eq!(external.ipv6(), Some(ip("2001:db8::1")));
+        // IPv4-mapped addresses vote for IPv4
+        external.vote(tracker("b"), ip("::ffff:203.0.113.9"));
+        external.vote(tracker("c"), ip("::ffff:203.0.113.9"));
+        assert_eq!(external.ipv4(), Some(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn meaningless_addresses_are_ignored() {
+        let external = ExternalIp::new();
+        for bad in ["0.0.0.0", "127.0.0.1", "224.0.0.1", "::", "::1", "ff02::1"] {
+            assert!(!external.vote(tracker("a"), ip(bad)));
+        }
+        assert_eq!((external.ipv4(), external.ipv6()), (None, None));
+    }
+
+    #[test]
+    fn votes_beyond_the_limit_are_ignored() {
+        let external = ExternalIp::new();
+        for n in 0..MAX_VOTES {
+            external.vote(tracker(&n.to_string()), ip("203.0.113.1"));
+        }
+        for n in 0..MAX_VOTES {
+            external.vote(tracker(&format!("late{}", n)), ip("203.0.113.2"));
+        }
+        assert_eq!(external.ipv4(), Some(ip("203.0.113.1")));
+    }
+
+    #[test]
+    fn recognises_itself_in_peer_lists() {
+        let external = ExternalIp::new();
+        external.vote(tracker("a"), ip("203.0.113.1"));
+        assert!(external.is_self(&"203.0.113.1:6881".parse().unwrap(), 6881));
+        assert!(external.is_self(&"[::ffff:203.0.113.1]:6881".parse().unwrap(), 6881));
+        assert!(!external.is_self(&"203.0.113.1:6882".parse().unwrap(), 6881));
+        assert!(!external.is_self(&"203.0.113.2:6881".parse().unwrap(), 6881));
+    }
+
+    #[test]
+    fn node_ids_follow_bep_42() {
+        let external = ExternalIp::new();
+        assert_eq!(external.dht_node_id(), None);
+        external.vote(tracker("a"), ip("124.31.75.21"));
+        let id = external.dht_node_id().unwrap();
+        assert!(is_valid_node_id(&id, ip("124.31.75.21")));
+        assert!(!is_valid_node_id(&id, ip("21.75.31.124")));
+
+        // Test vectors from BEP 42
+        for (addr, r, prefix) in [
+            ("21.75.31.124", 86, [0x5a, 0x3c, 0xe9]),
+            ("65.23.51.170", 22, [0xa5, 0xd4, 0x32]),
+            ("84.124.73.14", 65, [0x1b, 0x03, 0x21]),
+            ("43.213.53.83", 90, [0xe5, 0x6f, 0x6c]),
+        ] {
+            let id = node_id_for(ip(addr), r);
+            assert_eq!(&id[..2], &prefix[..2], "{}", addr);
+            assert_eq!(id[2] & 0xf8, prefix[2] & 0xf8, "{}", addr);
+            assert_eq!(id[19], r);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Peer list normalization.
+//!
+//! Trackers (and later DHT and PEX) hand out peer lists that may contain duplicates, unusable
+//! endpoints and our own address. This module cleans them up before they reach the peer manager:
+//! - IPv4-mapped IPv6 addresses are converted to plain IPv4, so both forms deduplicate
+//! - Port 0 and unspecified, broadcast and multicast addresses are dropped
+//! - Our own listen address (configured or discovered through BEP 24) is dropped
+//! - An optional `IpFilter` drops blocked address ranges
+//! - An optional `BannedPeers` set drops peers banned during the session, e.g. by the smart ban
+//!   for sending corrupt data
+use super::Peer;
+use super::external_ip::ExternalIp;
+use crate::torrent::TorrentError;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+/// A list of blocked IP address ranges.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    ranges: Vec<(IpAddr, IpAddr)>,
+}
+
+impl IpFilter {
+    /// Creates a filter that blocks nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Blocks every address from `start` to `end`, inclusive.
+    ///
+    /// Both ends must belong to the same address family, with `start <= end`.
+    pub fn block_range(&mut self, start: IpAddr, end: IpAddr) -> Result<()> {
+        let (start, end) = (start.to_canonical(), end.to_canonical());
+        if start.is_ipv4() != end.is_ipv4() || start > end {
+            return Err(TorrentError::InvalidFormat(format!(
+                "Invalid IP range: {} - {}",
+                start, end
+            ))
+            .into());
+        }
+        self.ranges.push((start, end));
+        Ok(())
+    }
+
+    /// Blocks a CIDR block such as `10.0.0.0/8` or `fc00::/7`.
+    ///
+    /// # Example
+    /// ```
+    /// use rs_torrent_client::tracker::filter::IpFilter;
+    ///
+    /// let mut filter = IpFilter::new();
+    /// filter.block_cidr("10.0.0.0/8").unwrap();
+    /// assert!(filter.is_blocked(&"10.1.2.3".parse().unwrap()));
+    /// assert!(!filter.is_blocked(&"11.0.0.1".parse().unwrap()));
+    /// ```
+    pub fn block_cidr(&mut self, cidr: &str) -> Result<()> {
+        let invalid = || TorrentError::InvalidFormat(format!("Invalid CIDR block: {}", cidr));
+        let (ip, prefix) = cidr.split_once('/').ok_or_else(invalid)?;
+        let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+        let prefix: u32 = prefix.parse().map_err(|_| invalid())?;
+        let (start, end) = match ip {
+            IpAddr::V4(ip) if prefix <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                let start = u32::from(ip) & mask;
+                (
+                    IpAddr::from(Ipv4Addr::from(start)),
+                    IpAddr::from(Ipv4Addr::from(start | !mask)),
+                )
+            }
+            IpAddr::V6(ip) if prefix <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                let start = u128::from(ip) & mask;
+                (
+                    IpAddr::from(start.to_be_bytes()),
+                    IpAddr::from((start | !mask).to_be_bytes()),
+                )
+            }
+            _ => return Err(invalid().into()),
+        };
+        self.block_range(start, end)
+    }
+
+    /// Returns `true` if the address falls in a blocked range.
+    pub fn is_blocked(&self, ip: &IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.ranges
+            .iter()
+            .any(|(start, end)| start.is_ipv4() == ip.is_ipv4() && *start <= ip && ip <= *end)
+    }
+
+    /// Returns `true` if nothing is blocked.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+/// Addresses banned for the rest of the session.
+///
+/// Whatever detects misbehaving peers adds to the set, and every peer filter holding a clone sees
+/// the bans at once: clones share the same set.
+#[derive(Debug, Clone, Default)]
+pub struct BannedPeers {
+    inner: Arc<Mutex<HashSet<IpAddr>>>,
+}
+
+impl BannedPeers {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bans `ip`.
+    ///
+    /// # Returns
+    /// * `bool` - `true` if the address was not banned before
+    pub fn ban(&self, ip: IpAddr) -> bool {
+        self.lock().insert(ip.to_canonical())
+    }
+
+    /// Returns `true` if `ip` is banned.
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.lock().contains(&ip.to_canonical())
+    }
+
+    /// Returns the banned addresses.
+    pub fn banned(&self) -> Vec<IpAddr> {
+        self.lock().iter().copied().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<IpAddr>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Decides which announced peers are worth connecting to.
+#[derive(Debug, Clone, Default)]
+pub struct PeerFilter {
+    listen_port: u16,
+    own_ips: Vec<IpAddr>,
+    external_ip: Option<ExternalIp>,
+    ip_filter: Option<Arc<IpFilter>>,
+    banned_peers: Option<BannedPeers>,
+}
+
+impl PeerFilter {
+    /// Creates a filter for a client listening on `listen_port`.
+    pub fn new(listen_port: u16) -> Self {
+        Self {
+            listen_port,
+            ..Default::default()
+        }
+    }
+
+    /// Treats `ip` with our listen port as ourselves.
+    pub fn with_own_ip(mut self, ip: IpAddr) -> Self {
+        self.own_ips.push(ip.to_canonical());
+        self
+    }
+
+    /// Treats the external addresses discovered by `external_ip` with our listen port as ourselves.
+    pub fn with_external_ip(mut self, external_ip: ExternalIp) -> Self {
+        self.external_ip = Some(external_ip);
+        self
+    }
+
+    /// Drops peers whose address is blocked by `ip_filter`.
+    pub fn with_ip_filter(mut self, ip_filter: Arc<IpFilter>) -> Self {
+        self.ip_filter = Some(ip_filter);
+        self
+    }
+
+    /// Drops peers whose address is in `banned_peers`.
+    pub fn with_banned_peers(mut self, banned_peers: BannedPeers) -> Self {
+        self.banned_peers = Some(banned_peers);
+        self
+    }
+
+    /// Returns `true` if the peer is a usable endpoint that is not ourselves, blocked or banned.
+    pub fn is_allowed(&self, peer: &Peer) -> bool {
+        let ip = peer.ip.to_canonical();
+        let unusable = match ip {
+            IpAddr::V4(v4) => v4.is_broadcast(),
+            IpAddr::V6(_) => false,
+        };
+        if peer.port == 0 || ip.is_unspecified() || ip.is_multicast() || unusable {
+            return false;
+        }
+        let addr = SocketAddr::new(ip, peer.port);
+        let own = addr.port() == self.listen_port && self.own_ips.contains(&ip)
+            || self
+                .external_ip
+                .as_ref()
+                .is_some_and(|external| external.is_self(&addr, self.listen_port));
+        !own && self
+            .ip_filter
+            .as_ref()
+            .is_none_or(|filter| !filter.is_blocked(&ip))
+            && self
+                .banned_peers
+                .as_ref()
+                .is_none_or(|banned| !banned.is_banned(&ip))
+    }
+
+    /**
+    Normalizes peers from any number of sources into a clean, duplicate-free list.
+
+    # How it works:
+    1. Converts IPv4-mapped IPv6 addresses to IPv4.
+    2. Drops peers rejected by `is_allowed`.
+    3. Keeps the first occurrence of each endpoint, preserving the sources' order.
+    */
+    pub fn apply(&self, peers: impl IntoIterator<Item = Peer>) -> Vec<Peer> {
+        let mut seen = HashSet::new();
+        peers
+            .into_iter()
+            .map(|peer| Peer {
+                ip: peer.ip.to_canonical(),
+                port: peer.port,
+            })
+            .filter(|peer| self.is_allowed(peer) && seen.insert(peer.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::external_ip::VoteSource;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn peer(s: &str) -> Peer {
+        let addr: SocketAddr = s.parse().unwrap();
+        Peer {
+            ip: addr.ip(),
+            port: addr.port(),
+        }
+    }
+
+    fn peers(list: &[&str]) -> Vec<Peer> {
+        list.iter().map(|s| peer(s)).collect()
+    }
+
+    #[test]
+    fn cidr_blocks_cover_their_whole_range() {
+        let mut filter = IpFilter::new();
+        assert!(filter.is_empty());
+        filter.block_cidr("192.168.1.77/24").unwrap();
+        filter.block_cidr("fc00::/7").unwrap();
+        filter.block_cidr("203.0.113.9/32").unwrap();
+
+        assert!(filter.is_blocked(&ip("192.168.1.0")));
+        assert!(filter.is_blocked(&ip("192.168.1.255")));
+        assert!(!filter.is_blocked(&ip("192.168.2.0")));
+        assert!(filter.is_blocked(&ip("fdff:ffff::1")));
+        assert!(!filter.is_blocked(&ip("fe00::1")));
+        assert!(filter.is_blocked(&ip("203.0.113.9")));
+        assert!(!filter.is_blocked(&ip("203.0.113.10")));
+        // IPv4-mapped addresses are checked as IPv4
+        assert!(filter.is_blocked(&ip("::ffff:192.168.1.5")));
+    }
+
+    #[test]
+    fn zero_prefix_blocks_the_whole_family() {
+        let mut filter = IpFilter::new();
+        filter.block_cidr("0.0.0.0/0").unwrap();
+        assert!(filter.is_blocked(&ip("255.255.255.255")));
+        assert!(!filter.is_blocked(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn invalid_cidr_blocks_are_rejected() {
+        let mut filter = IpFilter::new();
+        for bad in [
+            "10.0.0.0",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0/8",
+            "10.0.0.0/x",
+            "",
+        ] {
+            assert!(filter.block_cidr(bad).is_err(), "{}", bad);
+        }
+        assert!(filter.is_empty());
+    }
+
+    #[test]
+    fn ranges_are_inclusive_and_checked() {
+        let mut filter = IpFilter::new();
+        filter.block_range(ip("10.0.0.5"), ip("10.0.0.10")).unwrap();
+        assert!(filter.is_blocked(&ip("10.0.0.5")));
+        assert!(filter.is_blocked(&ip("10.0.0.10")));
+        assert!(!filter.is_blocked(&ip("10.0.0.11")));
+
+        assert!(filter.block_range(ip("10.0.0.10"), ip("10.0.0.5")).is_err());
+        assert!(filter.block_range(ip("10.0.0.1"), ip("::1")).is_err());
+    }
+
+    #[test]
+    fn apply_drops_unusable_endpoints_and_duplicates() {
+        let filter = PeerFilter::new(6881);
+        let result = filter.apply(peers(&[
+            "203.0.113.1:6881",
+            "[::ffff:203.0.113.1]:6881",
+            "203.0.113.2:0",
+            "0.0.0.0:6881",
+            "255.255.255.255:6881",
+            "224.0.0.1:6881",
+            "[ff02::1]:6881",
+            "[2001:db8::1]:6881",
+            "203.0.113.1:6882",
+        ]));
+        assert_eq!(
+            result,
+            peers(&["203.0.113.1:6881", "[2001:db8::1]:6881", "203.0.113.1:6882"])
+        );
+    }
+
+    #[test]
+    fn apply_drops_our_own_address() {
+        let external = ExternalIp::new();
+        external.vote(VoteSource::Tracker("t".to_string()), ip("198.51.100.7"));
+        let filter = PeerFilter::new(6881)
+            .with_own_ip(ip("203.0.113.1"))
+            .with_external_ip(external);
+        let result = filter.apply(peers(&[
+            "203.0.113.1:6881",
+            "198.51.100.7:6881",
+            "203.0.113.1:6882",
+            "198.51.100.7:51413",
+        ]));
+        // Other ports on our address may be other clients behind the same NAT
+        assert_eq!(result, peers(&["203.0.113.1:6882", "198.51.100.7:51413"]));
+    }
+
+    #[test]
+    fn apply_drops_blocked_and_banned_peers() {
+        let mut ip_filter = IpFilter::new();
+        ip_filter.block_cidr("10.0.0.0/8").unwrap();
+        let banned = BannedPeers::new();
+        let filter = PeerFilter::new(6881)
+            .with_ip_filter(Arc::new(ip_filter))
+            .with_banned_peers(banned.clone());
+        let list = peers(&["10.1.1.1:6881", "203.0.113.1:6881", "203.0.113.2:6881"]);
+        assert_eq!(filter.apply(list.clone()).len(), 2);
+
+        // Bans made after the filter was built still apply
+        assert!(banned.ban(ip("::ffff:203.0.113.2")));
+        assert!(!banned.ban(ip("203.0.113.2")));
+        assert_eq!(banned.banned(), [ip("203.0.113.2")]);
+        assert_eq!(filter.apply(list), peers(&["203.0.113.1:6881"]));
+    }
+}
@@ -0,0 +1,189 @@
+//! HTTP settings for tracker requests.
+//!
+//! Announces and scrapes share one `reqwest::Client`, so connections to a tracker are pooled
+//! and every request gets the same timeouts, User-Agent, proxy and compression settings.
+use anyhow::Result;
+use std::time::Duration;
+
+/// Time allowed to establish a connection to a tracker, unless configured otherwise.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time allowed between reads of a tracker response, unless configured otherwise.
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// Upper bound on a whole tracker request, unless configured otherwise.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Settings of the HTTP client used for HTTP(S) trackers.
+///
+/// UDP trackers are not affected; in particular they are never sent through the proxy.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Time allowed to establish a connection, including the TLS handshake.
+    pub connect_timeout: Duration,
+    /// Time allowed between two reads of the response.
+    pub read_timeout: Duration,
+    /// Upper bound on the whole request, from connecting to reading the last byte.
+    pub request_timeout: Duration,
+    /// Value of the `User-Agent` header; `None` uses the client identity's (see `ClientIdentity::user_agent`).
+    pub user_agent: Option<String>,
+    /// Proxy URL (`http://`, `https://`, `socks5://` or `socks5h://`). When unset, the usual
+    /// `HTTP_PROXY`/`HTTPS_PROXY`/`ALL_PROXY` environment variables are honoured.
+    pub proxy: Option<String>,
+    /// Advertises and transparently decodes gzip-compressed responses.
+    pub gzip: bool,
+    /// Accepts invalid TLS certificates, for private trackers with self-signed certificates.
+    pub accept_invalid_certs: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            user_agent: None,
+            proxy: None,
+            gzip: true,
+            accept_invalid_certs: false,
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Sets the connect, read and whole-request timeouts.
+    pub fn with_timeouts(mut self, connect: Duration, read: Duration, request: Duration) -> Self {
+        self.connect_timeout = connect;
+        self.read_timeout = read;
+        self.request_timeout = request;
+        self
+    }
+
+    /// Sets the `User-Agent` header.
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Sends every HTTP tracker request through the given HTTP or SOCKS5 proxy.
+    pub fn with_proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Enables or disables gzip compression of tracker responses.
+    pub fn with_gzip(mut self, enabled: bool) -> Self {
+        self.gzip = enabled;
+        self
+    }
+
+    /// Enables or disables TLS certificate validation bypass.
+    pub fn with_accept_invalid_certs(mut self, enabled: bool) -> Self {
+        self.accept_invalid_certs = enabled;
+        self
+    }
+
+    /// Builds the `reqwest::Client`, failing on an invalid proxy URL or TLS backend error.
+    ///
+    /// `default_user_agent` is sent when no User-Agent was configured.
+    pub fn build(&self, default_user_agent: &str) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .timeout(self.request_timeout)
+            .user_agent(
+                self.user_agent
+                    .as_deref()
+                    .unwrap_or(default_user_agent)
+                    .to_string(),
+            )
+            .gzip(self.gzip)
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::peer_id::ClientIdentity;
+    use crate::tracker::server::{ServerConfig, TrackerServer};
+    use crate::tracker::stub::{self, StubTracker};
+    use crate::tracker::{AnnounceParams, Client};
+
+    async fn user_agent(client: &Client) -> Option<String> {
+        let tracker = StubTracker::serve(|_| Some(stub::peers_response(900, &[]))).await;
+        client
+            .announce_to(
+                &tracker.url,
+                &stub::torrent(&tracker.url),
+                &AnnounceParams::default(),
+            )
+            .await
+            .unwrap();
+        tracker.requests()[0].user_agent.clone()
+    }
+
+    #[tokio::test]
+    async fn user_agent_follows_the_identity_unless_configured() {
+        let client = Client::new(6881).unwrap();
+        assert_eq!(
+            user_agent(&client).await,
+            Some(ClientIdentity::default().user_agent())
+        );
+
+        let identity = ClientIdentity::new("example", "EX", "1.2.3").unwrap();
+        let client = Client::new(6881).unwrap().with_identity(identity).unwrap();
+        assert_eq!(user_agent(&client).await.as_deref(), Some("example/1.2.3"));
+
+        let config = HttpConfig::default().with_user_agent("custom/1.0");
+        let client = client.with_http_config(&config).unwrap();
+        assert_eq!(user_agent(&client).await.as_deref(), Some("custom/1.0"));
+    }
+
+    #[tokio::test]
+    async fn error_statuses_are_not_parsed() {
+        let server = TrackerServer::new(ServerConfig::default());
+        let addr = server
+            .bind_http("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let url = format!("http://{}/missing", addr);
+        let error = Client::new(6881)
+            .unwrap()
+            .announce_to(&url, &stub::torrent(&url), &AnnounceParams::default())
+            .await
+            .unwrap_err();
+        let error = error.downcast::<reqwest::Error>().unwrap();
+        assert_eq!(error.status(), Some(reqwest::StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn slow_trackers_time_out() {
+        let tracker = StubTracker::serve(|_| None).await;
+        let timeout = Duration::from_millis(200);
+        let config = HttpConfig::default().with_timeouts(timeout, timeout, timeout);
+        let client = Client::new(6881)
+            .unwrap()
+            .with_http_config(&config)
+            .unwrap();
+        let error = client
+            .announce_to(
+                &tracker.url,
+                &stub::torrent(&tracker.url),
+                &AnnounceParams::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(error.downcast::<reqwest::Error>().unwrap().is_timeout());
+    }
+
+    #[test]
+    fn invalid_proxy_is_rejected() {
+        let config = HttpConfig::default().with_proxy("not a proxy");
+        assert!(config.build("agent").is_err());
+        let config = HttpConfig::default().with_proxy("socks5://127.0.0.1:1080");
+        assert!(config.build("agent").is_ok());
+    }
+}
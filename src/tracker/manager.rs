@@ -0,0 +1,397 @@
+//! Multi-tracker tiers and failover (BEP 12).
+//!
+//! This module keeps a torrent's tracker tiers in BEP 12 order and announces through them:
+//! each tier is shuffled once, trackers are tried in order, a tracker that responds is moved to
+//! the front of its tier, and a tier whose trackers all fail falls through to the next one.
+use super::{AnnounceParams, AnnounceResponse, Client, Peer};
+use crate::torrent::TorrentError;
+use crate::torrent::file::TorrentFile;
+use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// How long a single tracker may take to answer before the next one in its tier is tried.
+pub const DEFAULT_TRACKER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Outcome of contacting a single tracker during a tiered announce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackerStatus {
+    /// The tracker responded with `peers` peers and asked to be contacted again after `interval` seconds.
+    Working {
+        peers: usize,
+        interval: i64,
+        warning: Option<String>,
+    },
+    /// The request failed; holds the error message.
+    Failed(String),
+}
+
+/// The status of one tracker after an announce round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackerResult {
+    pub url: String,
+    pub tier: usize,
+    pub status: TrackerStatus,
+}
+
+/// The merged result of announcing through the tracker tiers.
+#[derive(Debug, Clone, Default)]
+pub struct TieredAnnounce {
+    /// Peers from every tracker that responded, without duplicates.
+    pub peers: Vec<Peer>,
+    /// The shortest interval requested by a responding tracker, if any responded.
+    pub interval: Option<i64>,
+    /// The longest `min interval` requested by a responding tracker, if any sent one.
+    pub min_interval: Option<i64>,
+    /// One entry per tracker contacted, in the order they were contacted.
+    pub trackers: Vec<TrackerResult>,
+}
+
+impl TieredAnnounce {
+    /// Returns `true` if at least one tracker responded.
+    pub fn is_success(&self) -> bool {
+        self.trackers
+            .iter()
+            .any(|t| matches!(t.status, TrackerStatus::Working { .. }))
+    }
+}
+
+/// Keeps the tracker tiers of a torrent and announces to them following BEP 12.
+#[derive(Debug, Clone)]
+pub struct TrackerManager {
+    tiers: Vec<Vec<String>>,
+    announce_to_all_tiers: bool,
+    tracker_timeout: Duration,
+    tracker_ids: HashMap<String, String>,
+}
+
+impl TrackerManager {
+    /// Creates a manager from tracker tiers, shuffling the trackers within each tier.
+    pub fn new(tiers: Vec<Vec<String>>) -> Self {
+        let mut rng = rand::rng();
+        let tiers = tiers
+            .into_iter()
+            .filter(|tier| !tier.is_empty())
+            .map(|mut tier| {
+                tier.shuffle(&mut rng);
+                tier
+            })
+            .collect();
+        Self {
+            tiers,
+            announce_to_all_tiers: false,
+            tracker_timeout: DEFAULT_TRACKER_TIMEOUT,
+            tracker_ids: HashMap::new(),
+        }
+    }
+
+    /// Creates a manager for the torrent's trackers (see `TorrentFile::trackers`).
+    pub fn from_torrent(torrent: &TorrentFile) -> Self {
+        Self::new(torrent.trackers())
+    }
+
+    /// When enabled, one working tracker from every tier is contacted instead of stopping
+    /// at the first tier that responds. BEP 12 leaves this off by default.
+    pub fn with_announce_to_all_tiers(mut self, enabled: bool) -> Self {
+        self.announce_to_all_tiers = enabled;
+        self
+    }
+
+    /// Sets how long each tracker may take to answer, so an unresponsive tracker cannot hold up
+    /// the rest of its tier.
+    pub fn with_tracker_timeout(mut self, timeout: Duration) -> Self {
+        self.tracker_timeout = timeout;
+        self
+    }
+
+    /// Returns the tiers in their current order, reflecting shuffling and promotions.
+    pub fn tiers(&self) -> &[Vec<String>] {
+        &self.tiers
+    }
+
+    /// Announces through the tiers and returns the merged peers and per-tracker status.
+    ///
+    /// `params` carries the live transfer counters and lifecycle event sent to every tracker contacted.
+    /// The `tracker id` each tracker returns is remembered and sent back to it on later announces.
+    ///
+    /// # How it works
+    /// 1. Walks the tiers in order, trying each tracker of a tier in turn; a tracker that does not
+    ///    answer within the tracker timeout counts as failed.
+    /// 2. The first tracker in a tier to respond is moved to the front of that tier, and the rest
+    ///    of the tier is skipped.
+    /// 3. If every tracker in a tier fails, the next tier is tried.
+    /// 4. Stops after the first responding tier unless `announce_to_all_tiers` is enabled.
+    #[tracing::instrument(skip(self, client, torrent), level = "debug")]
+    pub async fn announce(
+        &mut self,
+        client: &Client,
+        torrent: &TorrentFile,
+        params: &AnnounceParams,
+    ) -> TieredAnnounce {
+        let mut result = TieredAnnounce::default();
+        let mut seen = HashSet::new();
+
+        for (tier_index, tier) in self.tiers.iter_mut().enumerate() {
+            let mut working = None;
+            for (index, url) in tier.iter().enumerate() {
+                let mut tracker_params = params.clone();
+                if tracker_params.trackerid.is_none() {
+                    tracker_params.trackerid = self.tracker_ids.get(url).cloned();
+                }
+                let announce = client.announce_to(url, torrent, &tracker_params);
+                let response = match tokio::time::timeout(self.tracker_timeout, announce).await {
+                    Ok(response) => response,
+                    Err(_) => Err(TorrentError::TrackerFailure(format!(
+                        "No response within {:?}",
+                        self.tracker_timeout
+                    ))
+                    .into()),
+                };
+                match response {
+                    Ok(response) => {
+                        if let Some(tracker_id) = &response.tracker_id {
+                            self.tracker_ids.insert(url.clone(), tracker_id.clone());
+                        }
+                        result.trackers.push(TrackerResult {
+                            url: url.clone(),
+                            tier: tier_index,
+                            status: TrackerStatus::Working {
+                                peers: response.peers.len(),
+                                interval: response.interval,
+                                warning: response.warning_message.clone(),
+                            },
+                        });
+                        merge(&mut result, &mut seen, response);
+                        working = Some(index);
+                        break;
+                    }
+                    Err(e) => {
+                        tracing::debug!(url = %url, error = %e, "Tracker announce failed");
+                        result.trackers.push(TrackerResult {
+                            url: url.clone(),
+                            tier: tier_index,
+                            status: TrackerStatus::Failed(e.to_string()),
+                        });
+                    }
+                }
+            }
+
+            if let Some(index) = working {
+                // Promote the responding tracker so it is tried first next time
+                let url = tier.remove(index);
+                tier.insert(0, url);
+                if !self.announce_to_all_tiers {
+                    break;
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Adds the peers of a response to the merged result, skipping duplicates.
+fn merge(result: &mut TieredAnnounce, seen: &mut HashSet<Peer>, response: AnnounceResponse) {
+    result.interval = Some(match result.interval {
+        Some(interval) => interval.min(response.interval),
+        None => response.interval,
+    });
+    if let Some(min_interval) = response.min_interval {
+        result.min_interval = Some(
+            result
+                .min_interval
+                .map_or(min_interval, |m| m.max(min_interval)),
+        );
+    }
+    for peer in response.peers {
+        if seen.insert(peer.clone()) {
+            result.peers.push(peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::BencodeValue;
+    use crate::tracker::stub::{self, StubTracker};
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    fn peer(n: u8) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, n), 6881)
+    }
+
+    async fn working(peers: &[SocketAddrV4]) -> StubTracker {
+        let body = stub::peers_response(900, peers);
+        StubTracker::serve(move |_| Some(body.clone())).await
+    }
+
+    async fn failing() -> StubTracker {
+        StubTracker::serve(|_| Some(stub::failure_response("unregistered torrent"))).await
+    }
+
+    async fn announce(manager: &mut TrackerManager) -> TieredAnnounce {
+        let client = Client::new(6881).unwrap();
+        let torrent = stub::torrent("http://127.0.0.1/announce");
+        manager
+            .announce(&client, &torrent, &AnnounceParams::default())
+            .await
+    }
+
+    fn urls(trackers: &[TrackerResult]) -> Vec<&str> {
+        trackers.iter().map(|t| t.url.as_str()).collect()
+    }
+
+    #[test]
+    fn trackers_are_shuffled_within_their_tier() {
+        let tier: Vec<String> = (0..8).map(|n| format!("http://t{}/announce", n)).collect();
+        let tiers = vec![
+            tier.clone(),
+            vec!["http://backup/announce".to_string()],
+            vec![],
+        ];
+        let mut orders = HashSet::new();
+        for _ in 0..20 {
+            let manager = TrackerManager::new(tiers.clone());
+            assert_eq!(manager.tiers().len(), 2, "empty tiers are dropped");
+            let mut shuffled = manager.tiers()[0].clone();
+            orders.insert(shuffled.clone());
+            shuffled.sort();
+            assert_eq!(shuffled, tier);
+            assert_eq!(manager.tiers()[1], tiers[1]);
+        }
+        assert!(orders.len() > 1, "tier was never shuffled");
+    }
+
+    #[tokio::test]
+    async fn fails_over_within_a_tier_and_promotes_the_responder() {
+        let (bad, good) = (failing().await, working(&[peer(1)]).await);
+        let mut manager = TrackerManager {
+            tiers: vec![vec![bad.url.clone(), good.url.clone()]],
+            ..TrackerManager::new(Vec::new())
+        };
+
+        let result = announce(&mut manager).await;
+        assert!(result.is_success());
+        assert_eq!(
+            urls(&result.trackers),
+            [bad.url.as_str(), good.url.as_str()]
+        );
+        assert!(matches!(
+            &result.trackers[0].status,
+            TrackerStatus::Failed(reason) if reason.contains("unregistered torrent")
+        ));
+        assert_eq!(result.interval, Some(900));
+        assert_eq!(manager.tiers()[0], [good.url.as_str(), bad.url.as_str()]);
+
+        // The promoted tracker answers first next time, so the failing one is not contacted
+        let result = announce(&mut manager).await;
+        assert_eq!(urls(&result.trackers), [good.url.as_str()]);
+        assert_eq!(bad.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn falls_through_to_the_next_tier() {
+        let (first, second, third) = (failing().await, working(&[]).await, working(&[]).await);
+        let mut manager = TrackerManager::new(vec![
+            vec![first.url.clone()],
+            vec![second.url.clone()],
+            vec![third.url.clone()],
+        ]);
+
+        let result = announce(&mut manager).await;
+        assert_eq!(
+            urls(&result.trackers),
+            [first.url.as_str(), second.url.as_str()]
+        );
+        assert_eq!(result.trackers[1].tier, 1);
+        assert!(
+            third.requests().is_empty(),
+            "stopped at the first working tier"
+        );
+
+        let result = announce(&mut manager.with_announce_to_all_tiers(true)).await;
+        assert_eq!(
+            urls(&result.trackers),
+            [first.url.as_str(), second.url.as_str(), third.url.as_str()]
+        );
+    }
+
+    #[tokio::test]
+    async fn every_tier_failing_is_not_a_success() {
+        let tracker = failing().await;
+        let mut manager = TrackerManager::new(vec![vec![tracker.url.clone()]]);
+        let result = announce(&mut manager).await;
+        assert!(!result.is_success());
+        assert_eq!(result.interval, None);
+    }
+
+    #[tokio::test]
+    async fn unresponsive_tracker_times_out() {
+        let (silent, good) = (StubTracker::serve(|_| None).await, working(&[]).await);
+        let mut manager = TrackerManager {
+            tiers: vec![vec![silent.url.clone(), good.url.clone()]],
+            ..TrackerManager::new(Vec::new())
+        }
+        .with_tracker_timeout(Duration::from_millis(200));
+
+        let result = announce(&mut manager).await;
+        assert_eq!(
+            urls(&result.trackers),
+            [silent.url.as_str(), good.url.as_str()]
+        );
+        assert!(matches!(
+            &result.trackers[0].status,
+            TrackerStatus::Failed(reason) if reason.contains("No response within")
+        ));
+        assert!(result.is_success());
+    }
+
+    #[tokio::test]
+    async fn tracker_id_is_sent_back() {
+        let tracker = StubTracker::serve(|_| {
+            Some(stub::response(vec![
+                ("interval", BencodeValue::Integer(900)),
+                ("peers", stub::compact_peers(&[])),
+                ("tracker id", BencodeValue::String(b"abc".to_vec())),
+            ]))
+        })
+        .await;
+        let mut manager = TrackerManager::new(vec![vec![tracker.url.clone()]]);
+        announce(&mut manager).await;
+        announce(&mut manager).await;
+
+        let requests = tracker.requests();
+        assert_eq!(requests[0].param("trackerid"), None);
+        assert_eq!(requests[1].param("trackerid"), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn peers_are_merged_without_duplicates() {
+        let first = working(&[peer(1), peer(2)]).await;
+        let second = StubTracker::serve(|_| {
+            Some(stub::response(vec![
+                ("interval", BencodeValue::Integer(300)),
+                ("min interval", BencodeValue::Integer(60)),
+                ("peers", stub::compact_peers(&[peer(2), peer(3)])),
+            ]))
+        })
+        .await;
+        let mut manager =
+            TrackerManager::new(vec![vec![first.url.clone()], vec![second.url.clone()]])
+                .with_announce_to_all_tiers(true);
+
+        let result = announce(&mut manager).await;
+        let peers: Vec<SocketAddrV4> = result
+            .peers
+            .iter()
+            .map(|p| match p.ip {
+                std::net::IpAddr::V4(ip) => SocketAddrV4::new(ip, p.port),
+                ip => panic!("unexpected peer {}", ip),
+            })
+            .collect();
+        assert_eq!(peers, [peer(1), peer(2), peer(3)]);
+        // The shortest interval and the longest min interval win
+        assert_eq!(result.interval, Some(300));
+        assert_eq!(result.min_interval, Some(60));
+    }
+}
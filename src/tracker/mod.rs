@@ -1,22 +1,53 @@
 //! Tracker client logic for the BitTorrent protocol.
 //!
 //! This module implements communication with BitTorrent trackers, including:
-//! - Announce requests and responses
-//! - Peer parsing (compact and non-compact)
-//! - Peer ID generation and URL encoding helpers
+//! - Announce requests and responses over HTTP and UDP (BEP 15)
+//! - Multi-tracker tiers with failover (BEP 12)
+//! - Peer parsing (compact and non-compact, IPv4 and IPv6 per BEP 7)
+//! - External IP discovery from tracker responses (BEP 24)
+//! - Peer list normalization: deduplication, invalid endpoints, IP filtering and banned peers
+//! - Client identity, peer ID generation and URL encoding helpers
 //!
 //! Used by the client to discover peers for a torrent.
+use crate::torrent::TorrentError;
 use crate::torrent::file::TorrentFile;
 use anyhow::{Ok, Result};
-use rand::Rng;
+use external_ip::{ExternalIp, VoteSource};
+use filter::{BannedPeers, IpFilter, PeerFilter};
+use http::HttpConfig;
+use peer_id::ClientIdentity;
 use serde::Deserialize;
-use std::net::{IpAddr, Ipv4Addr};
+use serde_bencode::value::Value;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use udp::UdpTrackerClient;
+pub mod external_ip;
+pub mod filter;
+pub mod http;
+pub mod manager;
+pub mod peer_id;
+pub mod scheduler;
+pub mod scrape;
+pub mod server;
+#[cfg(test)]
+mod stub;
+pub mod udp;
 
 // Represents a client communicating with a bittorent tracker
 #[derive(Debug)]
 pub struct Client {
+    identity: ClientIdentity,
     peer_id: [u8; 20],
     port: u16,
+    key: u32,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    http_config: HttpConfig,
+    http: reqwest::Client,
+    udp: UdpTrackerClient,
+    external_ip: ExternalIp,
+    ip_filter: Option<Arc<IpFilter>>,
+    banned_peers: Option<BannedPeers>,
 }
 
 // Contains the parameters for a tracker announce request
@@ -29,71 +60,332 @@ pub struct AnnounceRequest {
     pub downloaded: i64,
     pub compact: bool,
     pub left: i64,
+    pub event: AnnounceEvent,
+    pub numwant: Option<u32>,
+    pub key: u32,
+    pub trackerid: Option<String>,
+    pub no_peer_id: bool,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+/// The `event` sent with an announce, telling the tracker where we are in the torrent's lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnnounceEvent {
+    /// A regular periodic announce; no `event` parameter is sent.
+    #[default]
+    None,
+    /// The first announce after the download starts.
+    Started,
+    /// Sent once when the download completes (not when starting as a seeder).
+    Completed,
+    /// Sent when the client shuts down gracefully.
+    Stopped,
+}
+
+impl AnnounceEvent {
+    /// Returns the value of the HTTP `event` query parameter, or `None` for periodic announces.
+    pub fn as_str(&self) -> Option<&'static str> {
+        match self {
+            AnnounceEvent::None => None,
+            AnnounceEvent::Started => Some("started"),
+            AnnounceEvent::Completed => Some("completed"),
+            AnnounceEvent::Stopped => Some("stopped"),
+        }
+    }
+
+    /// Returns the event code used by the UDP tracker protocol (BEP 15).
+    pub fn udp_code(&self) -> u32 {
+        match self {
+            AnnounceEvent::None => 0,
+            AnnounceEvent::Completed => 1,
+            AnnounceEvent::Started => 2,
+            AnnounceEvent::Stopped => 3,
+        }
+    }
+
+    /// Parses the HTTP `event` query parameter; unknown and empty values are periodic announces.
+    pub fn from_query(value: &str) -> Self {
+        match value {
+            "started" => AnnounceEvent::Started,
+            "completed" => AnnounceEvent::Completed,
+            "stopped" => AnnounceEvent::Stopped,
+            _ => AnnounceEvent::None,
+        }
+    }
+
+    /// Parses a UDP event code; unknown codes are periodic announces.
+    pub fn from_udp_code(code: u32) -> Self {
+        match code {
+            1 => AnnounceEvent::Completed,
+            2 => AnnounceEvent::Started,
+            3 => AnnounceEvent::Stopped,
+            _ => AnnounceEvent::None,
+        }
+    }
+}
+
+/// Caller-supplied state for an announce: live transfer counters and lifecycle event.
+#[derive(Debug, Clone, Default)]
+pub struct AnnounceParams {
+    /// Total bytes uploaded since the `started` event.
+    pub uploaded: i64,
+    /// Total bytes downloaded since the `started` event.
+    pub downloaded: i64,
+    /// Bytes still needed; `None` uses the torrent's total length.
+    pub left: Option<i64>,
+    pub event: AnnounceEvent,
+    /// Number of peers wanted; `None` lets the tracker choose.
+    pub numwant: Option<u32>,
+    /// The `tracker id` returned by this tracker on a previous announce, if any.
+    pub trackerid: Option<String>,
+    /// Asks the tracker to omit peer IDs from non-compact peer lists.
+    pub no_peer_id: bool,
 }
 
 /// Represents a peer recieved from the tracker.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
 pub struct Peer {
     pub ip: IpAddr,
     pub port: u16,
 }
 
 /// Contains the parsed response from a tracker.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct AnnounceResponse {
     pub interval: i64,
-    pub peers: Vec<Peer>, // A list of peers that client can connect to.
-}
-
-#[derive(Debug, Deserialize)]
-struct TrackerResponse {
-    #[serde(default)]
-    interval: i64,
-    #[serde(default)]
-    peers: Peers,
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum Peers {
-    Compact(Vec<u8>),
-    NonCompact(Vec<PeerDict>),
+    pub min_interval: Option<i64>, // Trackers may refuse announces more frequent than this.
+    pub tracker_id: Option<String>, // Must be sent back as `trackerid` on later announces.
+    pub warning_message: Option<String>, // Non-fatal message from the tracker.
+    pub complete: Option<i64>,     // Number of seeders, if reported.
+    pub incomplete: Option<i64>,   // Number of leechers, if reported.
+    pub peers: Vec<Peer>,          // A list of peers that client can connect to.
+    pub external_ip: Option<IpAddr>, // Our address as seen by the tracker (BEP 24).
 }
 
-impl Default for Peers {
-    fn default() -> Self {
-        Peers::Compact(Vec::new())
-    }
+/// Swarm statistics for a single torrent, as returned by a tracker scrape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ScrapeStats {
+    pub complete: i64,   // Number of seeders
+    pub downloaded: i64, // Number of completed downloads
+    pub incomplete: i64, // Number of leechers
 }
 
+// Text fields are kept as raw values too: trackers do not always send UTF-8, and one bad
+// message should not make the whole response unreadable.
 #[derive(Debug, Deserialize)]
-struct PeerDict {
-    ip: String,
-    port: u16,
+struct TrackerResponse {
+    #[serde(rename = "failure reason")]
+    failure_reason: Option<Value>,
+    #[serde(rename = "warning message")]
+    warning_message: Option<Value>,
+    interval: Option<i64>,
+    #[serde(rename = "min interval")]
+    min_interval: Option<i64>,
+    #[serde(rename = "tracker id")]
+    tracker_id: Option<Value>,
+    complete: Option<i64>,
+    incomplete: Option<i64>,
+    // Kept as a raw value: compact peers are a byte string, which serde cannot
+    // tell apart from a list when deserializing into an untagged enum.
+    peers: Option<Value>,
+    peers6: Option<Value>,
+    #[serde(rename = "external ip")]
+    external_ip: Option<Value>,
 }
 
 impl Client {
+    /// Creates a client listening on `port`, identified by the default `ClientIdentity`.
     pub fn new(port: u16) -> Result<Self> {
+        let identity = ClientIdentity::default();
+        let http_config = HttpConfig::default();
         Ok(Self {
-            peer_id: generate_peer_id()?,
+            peer_id: identity.generate_peer_id(),
+            http: http_config.build(&identity.user_agent())?,
+            identity,
             port,
+            key: rand::random(),
+            ipv4: None,
+            ipv6: None,
+            http_config,
+            udp: UdpTrackerClient::default(),
+            external_ip: ExternalIp::new(),
+            ip_filter: None,
+            banned_peers: None,
         })
     }
+
+    /// Identifies as another client: generates a new peer ID with the identity's prefix, and
+    /// uses its User-Agent unless the HTTP configuration sets one.
+    pub fn with_identity(mut self, identity: ClientIdentity) -> Result<Self> {
+        self.peer_id = identity.generate_peer_id();
+        self.http = self.http_config.build(&identity.user_agent())?;
+        self.identity = identity;
+        Ok(self)
+    }
+
+    /// Shares an external address voter with other clients or the peer layer, so that every
+    /// source votes on one session-wide address.
+    pub fn with_external_ip(mut self, external_ip: ExternalIp) -> Self {
+        self.external_ip = external_ip;
+        self
+    }
+
+    /// Returns the external address voter fed by tracker responses.
+    pub fn external_ip(&self) -> &ExternalIp {
+        &self.external_ip
+    }
+
+    /// Drops announced peers whose address is blocked by `ip_filter`.
+    pub fn with_ip_filter(mut self, ip_filter: Arc<IpFilter>) -> Self {
+        self.ip_filter = Some(ip_filter);
+        self
+    }
+
+    /// Drops announced peers in `banned_peers`, such as those the smart ban caught sending
+    /// corrupt data.
+    pub fn with_banned_peers(mut self, banned_peers: BannedPeers) -> Self {
+        self.banned_peers = Some(banned_peers);
+        self
+    }
+
+    /// Returns the filter applied to announced peers: our own addresses with our port, the
+    /// discovered external address, and the IP filter and banned peers, if any.
+    pub fn peer_filter(&self) -> PeerFilter {
+        let mut filter = PeerFilter::new(self.port).with_external_ip(self.external_ip.clone());
+        if let Some(ipv4) = self.ipv4 {
+            filter = filter.with_own_ip(ipv4.into());
+        }
+        if let Some(ipv6) = self.ipv6 {
+            filter = filter.with_own_ip(ipv6.into());
+        }
+        if let Some(ip_filter) = &self.ip_filter {
+            filter = filter.with_ip_filter(ip_filter.clone());
+        }
+        if let Some(banned_peers) = &self.banned_peers {
+            filter = filter.with_banned_peers(banned_peers.clone());
+        }
+        filter
+    }
+
+    /// Returns the peer ID sent to trackers and peers.
+    pub fn peer_id(&self) -> &[u8; 20] {
+        &self.peer_id
+    }
+
+    /// Sets the addresses reported to trackers in the `ipv4=` and `ipv6=` announce parameters (BEP 7).
+    ///
+    /// Lets a dual-stack client advertise the address family it did not announce over,
+    /// so the tracker can hand it out to peers of both families.
+    pub fn with_announce_addresses(
+        mut self,
+        ipv4: Option<Ipv4Addr>,
+        ipv6: Option<Ipv6Addr>,
+    ) -> Self {
+        self.ipv4 = ipv4;
+        self.ipv6 = ipv6;
+        self
+    }
+
+    /// Replaces the HTTP client used for HTTP(S) trackers with one built from `config`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rs_torrent_client::tracker::Client;
+    /// use rs_torrent_client::tracker::http::HttpConfig;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let client = Client::new(6881)?
+    ///     .with_http_config(&HttpConfig::default().with_proxy("socks5h://127.0.0.1:9050"))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_http_config(mut self, config: &HttpConfig) -> Result<Self> {
+        self.http = config.build(&self.identity.user_agent())?;
+        self.http_config = config.clone();
+        Ok(self)
+    }
+
+    /// Replaces the UDP retransmission schedule (`base * 2^n` for `n` in `0..=max_retries`).
+    ///
+    /// The default is the BEP 15 schedule of a 15 second base and 8 retries; a shorter one gives up
+    /// on unresponsive trackers sooner.
+    pub fn with_udp_retransmission(mut self, base: std::time::Duration, max_retries: u32) -> Self {
+        self.udp = UdpTrackerClient::new(base, max_retries);
+        self
+    }
+
     /// Sends an announce request to the tracker to get a list of peers.
+    ///
+    /// Only the first tracker of the first tier is contacted; use `TrackerManager` for BEP 12 failover.
     #[tracing::instrument(skip(self, torrent), level = "debug")]
     pub async fn announce(&self, torrent: &TorrentFile) -> Result<AnnounceResponse> {
+        let trackers = torrent.trackers();
+        let announce = trackers
+            .first()
+            .and_then(|tier| tier.first())
+            .ok_or(TorrentError::MissingField("announce".to_string()))?;
+        self.announce_to(announce, torrent, &AnnounceParams::default())
+            .await
+    }
+
+    /// Sends an announce request for `torrent` to a specific tracker URL.
+    ///
+    /// `udp://` trackers use the BEP 15 protocol; `http://` and `https://` trackers use HTTP GET.
+    /// The returned peers are deduplicated and cleaned up by `peer_filter`.
+    #[tracing::instrument(skip(self, torrent), level = "debug")]
+    pub async fn announce_to(
+        &self,
+        tracker: &str,
+        torrent: &TorrentFile,
+        params: &AnnounceParams,
+    ) -> Result<AnnounceResponse> {
         let request = AnnounceRequest {
             info_hash: torrent.info_hash,
             peer_id: self.peer_id,
             port: self.port,
-            uploaded: 0,
-            downloaded: 0,
+            uploaded: params.uploaded,
+            downloaded: params.downloaded,
             compact: true,
-            left: torrent.total_length(),
+            left: params.left.unwrap_or_else(|| torrent.total_length()),
+            event: params.event,
+            numwant: params.numwant,
+            key: self.key,
+            trackerid: params.trackerid.clone(),
+            no_peer_id: params.no_peer_id,
+            ipv4: self.ipv4,
+            ipv6: self.ipv6,
         };
-        // Build url with query paramters
-        let mut url = url::Url::parse(&torrent.announce)?;
-        let params = [
+        let url = url::Url::parse(tracker)?;
+        let host = url.host_str().unwrap_or_default().to_string();
+        let mut response = match url.scheme() {
+            "udp" => self.udp.announce(&url, &request).await?,
+            "http" | "https" => self.announce_http(url, &request).await?,
+            scheme => {
+                return Err(TorrentError::InvalidFormat(format!(
+                    "Unsupported tracker scheme: {}",
+                    scheme
+                ))
+                .into());
+            }
+        };
+        if let Some(ip) = response.external_ip {
+            self.external_ip.vote(VoteSource::Tracker(host), ip);
+        }
+        response.peers = self.peer_filter().apply(response.peers);
+        Ok(response)
+    }
+
+    /// Sends an announce request to an HTTP(S) tracker.
+    ///
+    /// The binary `info_hash` and `peer_id` are percent-encoded by `url_encode` and appended to the
+    /// query verbatim, since `Url::query_pairs_mut` would encode the `%` signs a second time.
+    async fn announce_http(
+        &self,
+        mut url: url::Url,
+        request: &AnnounceRequest,
+    ) -> Result<AnnounceResponse> {
+        let mut params = vec![
             ("info_hash", url_encode(&request.info_hash)),
             ("peer_id", url_encode(&request.peer_id)),
             ("port", request.port.to_string()),
@@ -101,10 +393,38 @@ impl Client {
             ("downloaded", request.downloaded.to_string()),
             ("left", request.left.to_string()),
             ("compact", (request.compact as i32).to_string()),
+            ("key", format!("{:08X}", request.key)),
         ];
-        url.query_pairs_mut().extend_pairs(&params).finish();
+        if let Some(event) = request.event.as_str() {
+            params.push(("event", event.to_string()));
+        }
+        if let Some(numwant) = request.numwant {
+            params.push(("numwant", numwant.to_string()));
+        }
+        if let Some(trackerid) = &request.trackerid {
+            params.push(("trackerid", url_encode(trackerid.as_bytes())));
+        }
+        if request.no_peer_id {
+            params.push(("no_peer_id", "1".to_string()));
+        }
+        if let Some(ipv4) = request.ipv4 {
+            params.push(("ipv4", ipv4.to_string()));
+        }
+        if let Some(ipv6) = request.ipv6 {
+            params.push(("ipv6", url_encode(ipv6.to_string().as_bytes())));
+        }
+        let query = params
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join("&");
+        let query = match url.query() {
+            Some(existing) if !existing.is_empty() => format!("{}&{}", existing, query),
+            _ => query,
+        };
+        url.set_query(Some(&query));
         tracing::debug!(?url, "Making announce request to tracker");
-        let response = reqwest::get(url).await?;
+        let response = self.http.get(url).send().await?.error_for_status()?;
         let response_bytes = response.bytes().await?;
         Self::parse_announce_response(&response_bytes)
     }
@@ -117,6 +437,11 @@ impl Client {
     /// - **Compact format**: The "peers" field is a byte string where each peer is represented by 6 bytes
     ///   (4 bytes for the IPv4 address and 2 bytes for the port, in network byte order).
     /// - **Non-compact format**: The "peers" field is a list of dictionaries, each containing "ip" and "port".
+    /// - **IPv6 (BEP 7)**: The "peers6" field is a byte string of 18-byte entries
+    ///   (16 bytes for the IPv6 address and 2 bytes for the port). Both families are merged into `peers`.
+    ///
+    /// A `failure reason` is returned as `TorrentError::TrackerFailure`, and a response with neither
+    /// peers nor a failure reason (or without an `interval`) is rejected as invalid.
     ///
     /// # Arguments
     /// * `bytes` - A byte slice containing the bencoded tracker response.
@@ -125,7 +450,7 @@ impl Client {
     /// * `Result<AnnounceResponse>` - The parsed announce response, including the interval and the list of peers.
     ///
     /// # Example
-    /// ```
+    /// ```ignore
     /// let response_bytes = ...; // bytes from tracker
     /// let announce_response = Client::parse_announce_response(&response_bytes)?;
     /// println!("Interval: {}", announce_response.interval);
@@ -137,62 +462,127 @@ impl Client {
         // 1. Deserialize the tracker response from bencoded bytes
         let tracker_response: TrackerResponse = serde_bencode::from_bytes(bytes)?;
 
-        // 2. Parse the peers field, handling both compact and non-compact forms
-        let peers = match tracker_response.peers {
-            // 2a. Compact: each peer is 6 bytes (4 for IP, 2 for port)
-            Peers::Compact(bytes) => bytes
-                .chunks_exact(6)
-                .map(|chunk| {
-                    let ip = Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]);
-                    let port = u16::from_be_bytes([chunk[4], chunk[5]]);
-                    Peer {
-                        ip: IpAddr::V4(ip),
-                        port,
-                    }
-                })
-                .collect(),
-            // 2b. Non-compact: each peer is a dictionary with "ip" and "port"
-            Peers::NonCompact(dicts) => dicts
-                .into_iter()
-                .filter_map(|dict| {
-                    dict.ip.parse::<IpAddr>().ok().map(|ip| Peer {
-                        ip,
-                        port: dict.port,
-                    })
-                })
-                .collect(),
+        // 2. A failure reason means the request failed; no other keys are meaningful
+        if let Some(reason) = lossy_string(tracker_response.failure_reason) {
+            return Err(TorrentError::TrackerFailure(reason).into());
+        }
+        let warning_message = lossy_string(tracker_response.warning_message);
+        if let Some(warning) = &warning_message {
+            tracing::warn!(%warning, "Tracker returned a warning");
+        }
+
+        // 3. Parse the peers field, handling both compact and non-compact forms
+        if tracker_response.peers.is_none() && tracker_response.peers6.is_none() {
+            return Err(TorrentError::InvalidTrackerResponse(
+                "neither peers nor a failure reason".to_string(),
+            )
+            .into());
+        }
+        let mut peers = match tracker_response.peers {
+            // 3a. Compact: each peer is 6 bytes (4 for IP, 2 for port)
+            Some(Value::Bytes(bytes)) => parse_compact_peers_v4(&bytes),
+            // 3b. Non-compact: each peer is a dictionary with "ip" and "port"
+            Some(Value::List(dicts)) => dicts.iter().filter_map(parse_peer_dict).collect(),
+            Some(_) => {
+                return Err(TorrentError::InvalidTrackerResponse(
+                    "peers has invalid type".to_string(),
+                )
+                .into());
+            }
+            None => Vec::new(),
+        };
+        // 3c. IPv6 compact: each peer is 18 bytes (16 for IP, 2 for port)
+        match tracker_response.peers6 {
+            Some(Value::Bytes(bytes)) => peers.extend(parse_compact_peers_v6(&bytes)),
+            Some(_) => {
+                return Err(TorrentError::InvalidTrackerResponse(
+                    "peers6 has invalid type".to_string(),
+                )
+                .into());
+            }
+            None => {}
+        }
+
+        // 4. `external ip` (BEP 24) is the raw 4- or 16-byte address the tracker saw us at
+        let external_ip = match tracker_response.external_ip {
+            Some(Value::Bytes(bytes)) => match bytes.len() {
+                4 => <[u8; 4]>::try_from(bytes.as_slice()).ok().map(IpAddr::from),
+                16 => <[u8; 16]>::try_from(bytes.as_slice())
+                    .ok()
+                    .map(IpAddr::from),
+                _ => None,
+            },
+            _ => None,
         };
 
-        // 3. Return the parsed announce response
+        let interval = tracker_response
+            .interval
+            .ok_or(TorrentError::InvalidTrackerResponse(
+                "missing interval".to_string(),
+            ))?;
+
+        // 5. Return the parsed announce response
         Ok(AnnounceResponse {
-            interval: tracker_response.interval,
+            interval,
+            min_interval: tracker_response.min_interval,
+            tracker_id: lossy_string(tracker_response.tracker_id),
+            warning_message,
+            complete: tracker_response.complete,
+            incomplete: tracker_response.incomplete,
             peers,
+            external_ip,
         })
     }
 }
-/// Generates a unique peer ID for this client.
-///
-/// This function creates a 20-byte peer ID that follows the BitTorrent protocol specification.
-/// The peer ID consists of:
-/// - A 9-byte prefix identifying the client ("-RT0001-") RT = Rust torrent
-/// - 11 random bytes to ensure uniqueness
-///
-/// # Returns
-/// * `Result<[u8; 20]>` - A 20-byte peer ID, or an error if generation fails
-///
-/// # Example
-/// ```rust
-/// let peer_id = generate_peer_id()?;
-/// assert_eq!(peer_id.len(), 20);
-/// assert_eq!(&peer_id[..9], b"-GT0001-");
-///
-fn generate_peer_id() -> Result<[u8; 20]> {
-    let mut peer_id = [0u8; 20];
-    let prefix = b"-RT0001-";
-    peer_id[..prefix.len()].copy_from_slice(prefix);
-    let mut rng = rand::rng();
-    rng.fill(&mut peer_id[prefix.len()..]);
-    Ok(peer_id)
+
+/// Reads a tracker-supplied string, replacing invalid UTF-8 rather than rejecting it.
+fn lossy_string(value: Option<Value>) -> Option<String> {
+    match value? {
+        Value::Bytes(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        _ => None,
+    }
+}
+
+/// Parses compact IPv4 peers: 6 bytes each, a 4-byte address followed by a 2-byte port (big-endian).
+pub(crate) fn parse_compact_peers_v4(bytes: &[u8]) -> Vec<Peer> {
+    bytes
+        .chunks_exact(6)
+        .map(|chunk| Peer {
+            ip: IpAddr::V4(Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3])),
+            port: u16::from_be_bytes([chunk[4], chunk[5]]),
+        })
+        .collect()
+}
+
+/// Parses compact IPv6 peers: 18 bytes each, a 16-byte address followed by a 2-byte port (big-endian).
+pub(crate) fn parse_compact_peers_v6(bytes: &[u8]) -> Vec<Peer> {
+    bytes
+        .chunks_exact(18)
+        .map(|chunk| {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&chunk[..16]);
+            Peer {
+                ip: IpAddr::V6(Ipv6Addr::from(octets)),
+                port: u16::from_be_bytes([chunk[16], chunk[17]]),
+            }
+        })
+        .collect()
+}
+
+/// Parses a single non-compact peer dictionary, skipping entries with a missing or invalid `ip` or `port`.
+fn parse_peer_dict(value: &Value) -> Option<Peer> {
+    let Value::Dict(dict) = value else {
+        return None;
+    };
+    let ip = match dict.get(b"ip".as_slice()) {
+        Some(Value::Bytes(ip)) => std::str::from_utf8(ip).ok()?.parse::<IpAddr>().ok()?,
+        _ => return None,
+    };
+    let port = match dict.get(b"port".as_slice()) {
+        Some(Value::Int(port)) => u16::try_from(*port).ok()?,
+        _ => return None,
+    };
+    Some(Peer { ip, port })
 }
 
 /// URL-encodes a byte slice according to RFC 3986.
@@ -209,7 +599,7 @@ fn generate_peer_id() -> Result<[u8; 20]> {
 /// * `String` - The URL-encoded string
 ///
 /// # Example
-/// ```rust
+/// ```ignore
 /// let bytes = b"Hello World!";
 /// let encoded = url_encode(bytes);
 /// assert_eq!(encoded, "Hello%20World%21");
@@ -228,3 +618,134 @@ fn url_encode(bytes: &[u8]) -> String {
     }
     encoded
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> Result<AnnounceResponse> {
+        Client::parse_announce_response(bytes)
+    }
+
+    fn failure(bytes: &[u8]) -> TorrentError {
+        parse(bytes)
+            .unwrap_err()
+            .downcast::<TorrentError>()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_compact_peers() {
+        let response = parse(
+            b"d8:completei3e10:incompletei5e8:intervali900e12:min intervali60e\
+              5:peers12:\x0a\x00\x00\x01\x1a\xe1\x0a\x00\x00\x02\x00\x50e",
+        )
+        .unwrap();
+        assert_eq!(response.interval, 900);
+        assert_eq!(response.min_interval, Some(60));
+        assert_eq!((response.complete, response.incomplete), (Some(3), Some(5)));
+        assert_eq!(
+            response.peers,
+            [
+                Peer {
+                    ip: "10.0.0.1".parse().unwrap(),
+                    port: 6881
+                },
+                Peer {
+                    ip: "10.0.0.2".parse().unwrap(),
+                    port: 80
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_peer_dictionaries() {
+        let response =
+            parse(b"d8:intervali900e5:peersld2:ip8:10.0.0.24:porti80eed2:ip3:bad4:porti1eeee")
+                .unwrap();
+        assert_eq!(
+            response.peers,
+            [Peer {
+                ip: "10.0.0.2".parse().unwrap(),
+                port: 80
+            }]
+        );
+    }
+
+    #[test]
+    fn merges_ipv6_peers() {
+        let mut body = b"d8:intervali900e5:peers6:\x0a\x00\x00\x01\x1a\xe16:peers618:".to_vec();
+        body.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        body.extend_from_slice(&[0x1a, 0xe2]);
+        body.push(b'e');
+        let response = parse(&body).unwrap();
+        assert_eq!(response.peers.len(), 2);
+        assert_eq!(
+            response.peers[1].ip,
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(response.peers[1].port, 6882);
+
+        // IPv6 peers alone are enough
+        let mut body = b"d8:intervali900e6:peers618:".to_vec();
+        body.extend_from_slice(&[0; 16]);
+        body.extend_from_slice(&[0x1a, 0xe2]);
+        body.push(b'e');
+        assert_eq!(parse(&body).unwrap().peers.len(), 1);
+    }
+
+    #[test]
+    fn failure_reason_is_an_error() {
+        assert!(matches!(
+            failure(b"d14:failure reason4:nopee"),
+            TorrentError::TrackerFailure(reason) if reason == "nope"
+        ));
+        // Not UTF-8, but still reported
+        assert!(matches!(
+            failure(b"d14:failure reason3:n\xffpe"),
+            TorrentError::TrackerFailure(reason) if reason == "n\u{fffd}p"
+        ));
+    }
+
+    #[test]
+    fn keeps_warning_and_tracker_id() {
+        let response =
+            parse(b"d8:intervali900e5:peers0:10:tracker id3:a\xffc15:warning message2:hie")
+                .unwrap();
+        assert_eq!(response.warning_message.as_deref(), Some("hi"));
+        assert_eq!(response.tracker_id.as_deref(), Some("a\u{fffd}c"));
+    }
+
+    #[test]
+    fn reads_external_ip() {
+        let response =
+            parse(b"d11:external ip4:\xcb\x00\x71\x078:intervali900e5:peers0:e").unwrap();
+        assert_eq!(response.external_ip, Some("203.0.113.7".parse().unwrap()));
+        let response = parse(b"d11:external ip3:abc8:intervali900e5:peers0:e").unwrap();
+        assert_eq!(response.external_ip, None);
+    }
+
+    #[test]
+    fn incomplete_responses_are_rejected() {
+        assert!(matches!(
+            failure(b"d5:peers0:e"),
+            TorrentError::InvalidTrackerResponse(reason) if reason == "missing interval"
+        ));
+        assert!(matches!(
+            failure(b"d8:intervali900ee"),
+            TorrentError::InvalidTrackerResponse(_)
+        ));
+        assert!(matches!(
+            failure(b"d8:intervali900e5:peersi1ee"),
+            TorrentError::InvalidTrackerResponse(reason) if reason == "peers has invalid type"
+        ));
+    }
+
+    #[test]
+    fn url_encode_keeps_unreserved_characters() {
+        assert_eq!(url_encode(b"Hello World!"), "Hello%20World%21");
+        assert_eq!(url_encode(b"a-b.c_d~"), "a-b.c_d~");
+        assert_eq!(url_encode(&[0x00, 0xff]), "%00%FF");
+    }
+}
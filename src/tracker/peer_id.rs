@@ -0,0 +1,378 @@
+//! Client identity and peer ID conventions.
+//!
+//! Peer IDs conventionally start with a prefix naming the client and its version, which lets
+//! trackers and peers tell clients apart. This module generates our own Azureus-style peer IDs
+//! (`-RT0010-` followed by random bytes) and identifies other clients from theirs:
+//! - Azureus-style: `-` + two-letter client code + four version characters + `-`
+//! - Shadow-style: one client letter + up to five version characters, padded with `-`
+//! - Mainline-style: `M` + dash-separated version digits, as in `M4-3-6--`
+use crate::torrent::TorrentError;
+use anyhow::Result;
+use rand::Rng;
+use std::fmt;
+
+/// Client code used in our own peer IDs.
+pub const CLIENT_CODE: &str = "RT";
+/// Client name used in our own User-Agent.
+pub const CLIENT_NAME: &str = "rs-torrent-client";
+
+/// How this client identifies itself in peer IDs and HTTP requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    name: String,
+    code: [u8; 2],
+    version: String,
+    digits: [u8; 4],
+}
+
+impl Default for ClientIdentity {
+    /// Identifies as `rs-torrent-client` with the crate's version, e.g. `-RT0010-` for 0.0.1.
+    ///
+    /// Version components that do not fit in one character are clamped to `Z`, so any crate
+    /// version gives a usable identity.
+    fn default() -> Self {
+        let version = env!("CARGO_PKG_VERSION");
+        let mut digits = [b'0'; 4];
+        for (digit, component) in digits.iter_mut().zip(release(version).split('.')) {
+            let n = component.parse::<u32>().unwrap_or(u32::MAX).min(35);
+            *digit = version_digit(n).unwrap_or(b'Z');
+        }
+        let mut code = [0u8; 2];
+        code.copy_from_slice(CLIENT_CODE.as_bytes());
+        Self {
+            name: CLIENT_NAME.to_string(),
+            code,
+            version: version.to_string(),
+            digits,
+        }
+    }
+}
+
+impl ClientIdentity {
+    /// Creates an identity from a client name, a two-character Azureus-style code and a version.
+    ///
+    /// The version is a dotted string such as `1.2.3` (a `-pre` suffix is ignored). Up to four
+    /// components are encoded as one character each, `0-9` then `A-Z`, so each must be at most 35.
+    ///
+    /// # Example
+    /// ```
+    /// use rs_torrent_client::tracker::peer_id::ClientIdentity;
+    ///
+    /// let identity = ClientIdentity::new("example", "EX", "1.12.3").unwrap();
+    /// assert_eq!(&identity.prefix(), b"-EX1C30-");
+    /// assert_eq!(identity.user_agent(), "example/1.12.3");
+    /// ```
+    pub fn new(name: &str, code: &str, version: &str) -> Result<Self> {
+        let code: [u8; 2] = code
+            .as_bytes()
+            .try_into()
+            .ok()
+            .filter(|c: &[u8; 2]| c.iter().all(u8::is_ascii_alphanumeric))
+            .ok_or_else(|| {
+                TorrentError::InvalidFormat(format!("Client code must be two characters: {}", code))
+            })?;
+
+        let mut digits = [b'0'; 4];
+        for (index, component) in release(version).split('.').enumerate() {
+            digits[index] = component
+                .parse::<u32>()
+                .ok()
+                .filter(|_| index < digits.len())
+                .and_then(version_digit)
+                .ok_or_else(|| {
+                    TorrentError::InvalidFormat(format!("Unsupported client version: {}", version))
+                })?;
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            code,
+            version: version.to_string(),
+            digits,
+        })
+    }
+
+    /// Returns the eight-byte Azureus-style peer ID prefix, e.g. `-RT0010-`.
+    pub fn prefix(&self) -> [u8; 8] {
+        let mut prefix = [b'-'; 8];
+        prefix[1..3].copy_from_slice(&self.code);
+        prefix[3..7].copy_from_slice(&self.digits);
+        prefix
+    }
+
+    /// Generates a peer ID: the prefix followed by twelve random bytes.
+    pub fn generate_peer_id(&self) -> [u8; 20] {
+        let mut peer_id = [0u8; 20];
+        let prefix = self.prefix();
+        peer_id[..prefix.len()].copy_from_slice(&prefix);
+        rand::rng().fill(&mut peer_id[prefix.len()..]);
+        peer_id
+    }
+
+    /// Returns the `User-Agent` matching the peer ID prefix, e.g. `rs-torrent-client/0.0.1`.
+    pub fn user_agent(&self) -> String {
+        format!("{}/{}", self.name, self.version)
+    }
+}
+
+/// Strips a pre-release or build suffix, leaving the dotted release numbers.
+fn release(version: &str) -> &str {
+    version.split(['-', '+']).next().unwrap_or_default()
+}
+
+/// Encodes one version component as a peer ID character, `0-9` then `A-Z`.
+fn version_digit(n: u32) -> Option<u8> {
+    char::from_digit(n, 36).map(|c| c.to_ascii_uppercase() as u8)
+}
+
+/// A client identified from its peer ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerClient {
+    /// The client's name, or `Unknown (XX)` for unrecognised codes.
+    pub name: String,
+    /// The version encoded in the peer ID, in dotted form.
+    pub version: String,
+}
+
+impl fmt::Display for PeerClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.version.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{} {}", self.name, self.version)
+        }
+    }
+}
+
+/// Azureus-style client codes.
+const AZUREUS_CLIENTS: &[(&str, &str)] = &[
+    ("AG", "Ares"),
+    ("AZ", "Vuze"),
+    ("BB", "BitBuddy"),
+    ("BC", "BitComet"),
+    ("BF", "Bitflu"),
+    ("BI", "BiglyBT"),
+    ("BT", "BitTorrent"),
+    ("DE", "Deluge"),
+    ("FD", "Free Download Manager"),
+    ("FW", "FrostWire"),
+    ("KT", "KTorrent"),
+    ("LT", "libtorrent (Rakshasa)"),
+    ("lt", "libtorrent"),
+    ("PI", "PicoTorrent"),
+    ("qB", "qBittorrent"),
+    ("RT", "rs-torrent-client"),
+    ("SD", "Thunder"),
+    ("TR", "Transmission"),
+    ("TX", "Tixati"),
+    ("UM", "µTorrent for Mac"),
+    ("UT", "µTorrent"),
+    ("UW", "µTorrent Web"),
+    ("WW", "WebTorrent"),
+    ("XL", "Xunlei"),
+];
+
+/// Shadow-style client letters.
+const SHADOW_CLIENTS: &[(u8, &str)] = &[
+    (b'A', "ABC"),
+    (b'O', "Osprey Permaseed"),
+    (b'Q', "BTQueue"),
+    (b'R', "Tribler"),
+    (b'S', "Shadow's client"),
+    (b'T', "BitTornado"),
+    (b'U', "UPnP NAT Bit Torrent"),
+];
+
+/**
+Identifies the client that generated a peer ID, for display in peer lists.
+
+# How it works:
+1. `-XX1234-` is read as Azureus-style: a two-character client code and four version characters.
+2. `M1-2-3--` is read as Mainline-style: dash-separated version numbers.
+3. A known Shadow-style letter followed by version characters and `-` padding is read as Shadow-style.
+4. Anything else is not recognised and returns `None`.
+
+# Example
+```
+use rs_torrent_client::tracker::peer_id::identify;
+
+let client = identify(b"-qB4520-abcdefghijkl").unwrap();
+assert_eq!(client.to_string(), "qBittorrent 4.5.2");
+let client = identify(b"T03I-----00000000000").unwrap();
+assert_eq!(client.to_string(), "BitTornado 0.3.18");
+```
+*/
+pub fn identify(peer_id: &[u8]) -> Option<PeerClient> {
+    if peer_id.len() != 20 {
+        return None;
+    }
+    identify_azureus(peer_id)
+        .or_else(|| identify_mainline(peer_id))
+        .or_else(|| identify_shadow(peer_id))
+}
+
+fn identify_azureus(peer_id: &[u8]) -> Option<PeerClient> {
+    if peer_id[0] != b'-' || peer_id[7] != b'-' {
+        return None;
+    }
+    let code = std::str::from_utf8(&peer_id[1..3]).ok()?;
+    if !code.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let numbers = peer_id[3..7]
+        .iter()
+        .map(|&c| (c as char).to_digit(36))
+        .collect::<Option<Vec<u32>>>()?;
+
+    let name = AZUREUS_CLIENTS
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map_or_else(
+            || format!("Unknown ({})", code),
+            |(_, name)| name.to_string(),
+        );
+    Some(PeerClient {
+        name,
+        version: dotted(&numbers),
+    })
+}
+
+fn identify_mainline(peer_id: &[u8]) -> Option<PeerClient> {
+    if peer_id[0] != b'M' {
+        return None;
+    }
+    let head = std::str::from_utf8(&peer_id[1..8]).ok()?;
+    let numbers = head
+        .trim_end_matches('-')
+        .split('-')
+        .map(|n| n.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    if numbers.len() != 3 {
+        return None;
+    }
+    Some(PeerClient {
+        name: "BitTorrent (Mainline)".to_string(),
+        version: dotted(&numbers),
+    })
+}
+
+fn identify_shadow(peer_id: &[u8]) -> Option<PeerClient> {
+    let name = SHADOW_CLIENTS
+        .iter()
+        .find(|(letter, _)| *letter == peer_id[0])
+        .map(|(_, name)| *name)?;
+    // Up to five version characters from 0-9A-Za-z., padded with `-`; a full-length version
+    // must be followed by the usual `---` so random peer IDs are not mistaken for Shadow-style
+    let version = &peer_id[1..6];
+    let end = version
+        .iter()
+        .position(|&c| c == b'-')
+        .unwrap_or(version.len());
+    let padded = version[end..].iter().all(|&c| c == b'-');
+    if end == 0 || !padded || (end == version.len() && &peer_id[6..9] != b"---") {
+        return None;
+    }
+    let numbers = version[..end]
+        .iter()
+        .map(|&c| shadow_digit(c))
+        .collect::<Option<Vec<u32>>>()?;
+    Some(PeerClient {
+        name: name.to_string(),
+        version: numbers
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join("."),
+    })
+}
+
+/// Decodes one Shadow-style version character.
+fn shadow_digit(c: u8) -> Option<u32> {
+    match c {
+        b'0'..=b'9' => Some((c - b'0') as u32),
+        b'A'..=b'Z' => Some((c - b'A') as u32 + 10),
+        b'a'..=b'z' => Some((c - b'a') as u32 + 36),
+        b'.' => Some(62),
+        _ => None,
+    }
+}
+
+/// Formats version numbers as `major.minor.patch`, adding the fourth number only when non-zero.
+fn dotted(numbers: &[u32]) -> String {
+    let shown = if numbers.len() > 3 && numbers[3..].iter().all(|&n| n == 0) {
+        &numbers[..3]
+    } else {
+        numbers
+    };
+    shown
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_id(prefix: &[u8]) -> [u8; 20] {
+        let mut id = [b'x'; 20];
+        id[..prefix.len()].copy_from_slice(prefix);
+        id
+    }
+
+    #[test]
+    fn default_identity_matches_the_crate_version() {
+        let identity = ClientIdentity::default();
+        let expected = ClientIdentity::new(CLIENT_NAME, CLIENT_CODE, env!("CARGO_PKG_VERSION"));
+        assert_eq!(identity, expected.unwrap());
+        assert_eq!(&identity.generate_peer_id()[..3], b"-RT");
+    }
+
+    #[test]
+    fn unsupported_versions_are_rejected() {
+        assert!(ClientIdentity::new("example", "EX", "1.36.0").is_err());
+        assert!(ClientIdentity::new("example", "EX", "1.2.3.4.5").is_err());
+        assert!(ClientIdentity::new("example", "EX", "1.x").is_err());
+        assert!(ClientIdentity::new("example", "E", "1.0.0").is_err());
+        let identity = ClientIdentity::new("example", "EX", "2.10.0-pre").unwrap();
+        assert_eq!(&identity.prefix(), b"-EX2A00-");
+    }
+
+    #[test]
+    fn identifies_azureus_style() {
+        let client = identify(&peer_id(b"-TR3000-")).unwrap();
+        assert_eq!(client.name, "Transmission");
+        assert_eq!(client.version, "3.0.0");
+
+        let client = identify(&peer_id(b"-ZZ1234-")).unwrap();
+        assert_eq!(client.to_string(), "Unknown (ZZ) 1.2.3.4");
+        assert_eq!(identify(&peer_id(b"-TR3?00-")), None);
+    }
+
+    #[test]
+    fn identifies_mainline_style() {
+        let client = identify(&peer_id(b"M4-3-6--")).unwrap();
+        assert_eq!(client.to_string(), "BitTorrent (Mainline) 4.3.6");
+        let client = identify(&peer_id(b"M7-10-2-")).unwrap();
+        assert_eq!(client.version, "7.10.2");
+        assert_eq!(identify(&peer_id(b"M4-3----")), None);
+    }
+
+    #[test]
+    fn identifies_shadow_style() {
+        let client = identify(&peer_id(b"S58B-----")).unwrap();
+        assert_eq!(client.to_string(), "Shadow's client 5.8.11");
+        let client = identify(&peer_id(b"A2345----")).unwrap();
+        assert_eq!(client.version, "2.3.4.5");
+        // Full-length versions need the `---` padding after them
+        assert!(identify(&peer_id(b"T12345---")).is_some());
+        assert_eq!(identify(&peer_id(b"T12345xyz")), None);
+        assert_eq!(identify(&peer_id(b"X12------")), None);
+    }
+
+    #[test]
+    fn wrong_length_is_not_identified() {
+        assert_eq!(identify(b"-TR3000-"), None);
+    }
+}
@@ -0,0 +1,600 @@
+//! Periodic re-announce scheduling.
+//!
+//! The scheduler runs as a background task per torrent: it announces `started` when spawned,
+//! re-announces whenever the trackers' `interval` elapses, backs off exponentially (with jitter)
+//! while every tracker is failing, and announces `stopped` when it is shut down. Peers not seen
+//! in an earlier announce are published on a channel for the peer manager to consume.
+use super::manager::{TieredAnnounce, TrackerManager};
+use super::{AnnounceEvent, AnnounceParams, Client, Peer};
+use crate::torrent::file::TorrentFile;
+use rand::Rng;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, sleep_until, timeout};
+
+/// Interval used when no tracker has responded yet, or a tracker asks for a nonsensical interval.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// Lower bound on the re-announce interval, whatever the tracker asks for.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(30);
+/// Upper bound on the tracker-supplied `interval` and `min interval`.
+pub const MAX_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// First retry delay after a failed announce; doubles with every consecutive failure.
+pub const BACKOFF_BASE: Duration = Duration::from_secs(15);
+/// Upper bound on the retry delay after failed announces.
+pub const BACKOFF_MAX: Duration = Duration::from_secs(30 * 60);
+/// How long the final `stopped` announce may take before the scheduler gives up on it.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Commands accepted by a running scheduler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchedulerCommand {
+    /// Announce as soon as the trackers' `min interval` allows.
+    ForceReannounce,
+    /// Updates the transfer counters sent with the next announce.
+    Progress {
+        uploaded: i64,
+        downloaded: i64,
+        left: i64,
+    },
+    /// The download finished; announces `completed` immediately.
+    Completed,
+    /// Announces `stopped` and ends the task.
+    Shutdown,
+}
+
+/// Handle to a running scheduler task.
+#[derive(Debug)]
+pub struct SchedulerHandle {
+    commands: mpsc::Sender<SchedulerCommand>,
+    task: JoinHandle<()>,
+}
+
+impl SchedulerHandle {
+    /// Requests an announce as soon as the trackers' `min interval` allows.
+    pub async fn force_reannounce(&self) {
+        let _ = self.commands.send(SchedulerCommand::ForceReannounce).await;
+    }
+
+    /// Updates the transfer counters reported on the next announce.
+    pub async fn update_progress(&self, uploaded: i64, downloaded: i64, left: i64) {
+        let _ = self
+            .commands
+            .send(SchedulerCommand::Progress {
+                uploaded,
+                downloaded,
+                left,
+            })
+            .await;
+    }
+
+    /// Reports that the download has completed.
+    pub async fn completed(&self) {
+        let _ = self.commands.send(SchedulerCommand::Completed).await;
+    }
+
+    /// Announces `stopped` to the trackers and waits for the task to finish.
+    pub async fn shutdown(self) {
+        let _ = self.commands.send(SchedulerCommand::Shutdown).await;
+        let _ = self.task.await;
+    }
+}
+
+/// Re-announces a torrent to its trackers for as long as it runs.
+pub struct AnnounceScheduler {
+    client: Arc<Client>,
+    torrent: Arc<TorrentFile>,
+    manager: TrackerManager,
+    params: AnnounceParams,
+    /// Events still waiting for a successful announce (`started`, then `completed`), oldest first.
+    pending_events: VecDeque<AnnounceEvent>,
+    min_interval: Duration,
+    last_announce: Option<Instant>,
+    failures: u32,
+    seen: HashSet<Peer>,
+}
+
+impl AnnounceScheduler {
+    /// Creates a scheduler for the torrent. Nothing is sent until it is spawned.
+    ///
+    /// # Arguments
+    /// * `client` - The tracker client used for every announce
+    /// * `torrent` - The torrent being announced
+    /// * `manager` - The tracker tiers to announce through
+    /// * `left` - Bytes still to download when the scheduler starts
+    pub fn new(
+        client: Arc<Client>,
+        torrent: Arc<TorrentFile>,
+        manager: TrackerManager,
+        left: i64,
+    ) -> Self {
+        Self {
+            client,
+            torrent,
+            manager,
+            params: AnnounceParams {
+                left: Some(left),
+                ..Default::default()
+            },
+            pending_events: VecDeque::from([AnnounceEvent::Started]),
+            min_interval: Duration::ZERO,
+            last_announce: None,
+            failures: 0,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Starts the scheduler on the tokio runtime.
+    ///
+    /// # Returns
+    /// * `(SchedulerHandle, mpsc::Receiver<Vec<Peer>>)` - A handle to control the task, and a channel
+    ///   that receives every batch of newly discovered peers
+    pub fn spawn(self) -> (SchedulerHandle, mpsc::Receiver<Vec<Peer>>) {
+        let (command_tx, command_rx) = mpsc::channel(16);
+        let (peer_tx, peer_rx) = mpsc::channel(16);
+        let task = tokio::spawn(self.run(command_rx, peer_tx));
+        (
+            SchedulerHandle {
+                commands: command_tx,
+                task,
+            },
+            peer_rx,
+        )
+    }
+
+    /**
+    The scheduler loop.
+
+    # How it works:
+    1. Announces `started` right away.
+    2. Sleeps until the next announce is due, handling commands in the meantime.
+    3. A forced re-announce moves the deadline forward, but never before `min interval` has passed.
+    4. `Completed` is queued behind a `started` that has not gone through yet, and announced
+       immediately after it, since trackers count completions.
+    5. Commands are still handled while an announce is in flight, so a slow tracker cannot delay
+       a shutdown.
+    6. On `Shutdown`, or when every handle is dropped, abandons any announce in flight, announces
+       `stopped` and returns.
+    */
+    async fn run(
+        mut self,
+        mut commands: mpsc::Receiver<SchedulerCommand>,
+        peers: mpsc::Sender<Vec<Peer>>,
+    ) {
+        let mut next = Instant::now();
+        loop {
+            tokio::select! {
+                _ = sleep_until(next) => match self.announce(&mut commands, &peers).await {
+                    Some(at) => next = at,
+                    None => break,
+                },
+                command = commands.recv() => match command {
+                    Some(SchedulerCommand::ForceReannounce) => {
+                        next = next.min(self.earliest_announce());
+                    }
+                    Some(SchedulerCommand::Shutdown) | None => break,
+                    Some(command) => {
+                        if record(&mut self.params, &mut self.pending_events, command) {
+                            next = Instant::now();
+                        }
+                    }
+                },
+            }
+        }
+        self.stop().await;
+    }
+
+    /// Announces once, still handling commands while the trackers are being contacted.
+    ///
+    /// # Returns
+    /// * `Option<Instant>` - When the next announce is due, or `None` if a shutdown cut it short
+    async fn announce(
+        &mut self,
+        commands: &mut mpsc::Receiver<SchedulerCommand>,
+        peers: &mpsc::Sender<Vec<Peer>>,
+    ) -> Option<Instant> {
+        let mut params = self.params.clone();
+        params.event = self.pending_events.front().copied().unwrap_or_default();
+        let mut announce_again = false;
+        let result = {
+            let announce = self.manager.announce(&self.client, &self.torrent, &params);
+            tokio::pin!(announce);
+            loop {
+                tokio::select! {
+                    result = &mut announce => break Some(result),
+                    command = commands.recv() => match command {
+                        // The announce in flight already serves a forced re-announce
+                        Some(SchedulerCommand::ForceReannounce) => {}
+                        Some(SchedulerCommand::Shutdown) | None => break None,
+                        Some(command) => {
+                            announce_again |=
+                                record(&mut self.params, &mut self.pending_events, command);
+                        }
+                    },
+                }
+            }
+        }?;
+        let next = self.finish_announce(result, params.event, peers).await;
+        Some(if announce_again { Instant::now() } else { next })
+    }
+
+    /// Handles the result of an announce sent with `event`, and returns when the next one is due.
+    async fn finish_announce(
+        &mut self,
+        result: TieredAnnounce,
+        event: AnnounceEvent,
+        peers: &mpsc::Sender<Vec<Peer>>,
+    ) -> Instant {
+        let now = Instant::now();
+        self.last_announce = Some(now);
+
+        if !result.is_success() {
+            self.failures = self.failures.saturating_add(1);
+            let delay = backoff(self.failures);
+            tracing::warn!(
+                failures = self.failures,
+                ?delay,
+                "Every tracker failed, backing off"
+            );
+            return now + delay;
+        }
+
+        self.failures = 0;
+        if event != AnnounceEvent::None {
+            self.pending_events.pop_front();
+        }
+        self.min_interval = result
+            .min_interval
+            .and_then(seconds)
+            .unwrap_or(Duration::ZERO);
+        let interval = next_interval(&result).max(self.min_interval);
+
+        let new_peers: Vec<Peer> = result
+            .peers
+            .into_iter()
+            .filter(|peer| self.seen.insert(peer.clone()))
+            .collect();
+        if !new_peers.is_empty() {
+            tracing::debug!(count = new_peers.len(), "Publishing new peers");
+            // The peer manager may have gone away; keep announcing regardless
+            let _ = peers.send(new_peers).await;
+        }
+        if !self.pending_events.is_empty() {
+            // `completed` was queued behind `started`; send it right away
+            return now;
+        }
+        now + interval
+    }
+
+    /// The earliest time the trackers allow another announce.
+    fn earliest_announce(&self) -> Instant {
+        match self.last_announce {
+            Some(last) => (last + self.min_interval).max(Instant::now()),
+            None => Instant::now(),
+        }
+    }
+
+    /// Sends the final `stopped` announce, bounded by `STOP_TIMEOUT`.
+    async fn stop(mut self) {
+        // A torrent that never reached a tracker has nothing to stop
+        if self.last_announce.is_none()
+            || self.pending_events.front() == Some(&AnnounceEvent::Started)
+        {
+            return;
+        }
+        let mut params = self.params.clone();
+        params.event = AnnounceEvent::Stopped;
+        let announce = self.manager.announce(&self.client, &self.torrent, &params);
+        if timeout(STOP_TIMEOUT, announce).await.is_err() {
+            tracing::debug!("Timed out sending stopped announce");
+        }
+    }
+}
+
+/// Records a progress update or a completion.
+///
+/// # Returns
+/// * `bool` - `true` if an announce is due right away
+fn record(
+    params: &mut AnnounceParams,
+    pending_events: &mut VecDeque<AnnounceEvent>,
+    command: SchedulerCommand,
+) -> bool {
+    match command {
+        SchedulerCommand::Progress {
+            uploaded,
+            downloaded,
+            left,
+        } => {
+            params.uploaded = uploaded;
+            params.downloaded = downloaded;
+            params.left = Some(left);
+            false
+        }
+        SchedulerCommand::Completed => {
+            params.left = Some(0);
+            if !pending_events.contains(&AnnounceEvent::Completed) {
+                pending_events.push_back(AnnounceEvent::Completed);
+            }
+            true
+        }
+        SchedulerCommand::ForceReannounce | SchedulerCommand::Shutdown => false,
+    }
+}
+
+/// Picks the re-announce interval from a successful tiered announce.
+fn next_interval(result: &TieredAnnounce) -> Duration {
+    result
+        .interval
+        .and_then(seconds)
+        .unwrap_or(DEFAULT_INTERVAL)
+        .max(MIN_INTERVAL)
+}
+
+/// Converts a tracker-supplied number of seconds, rejecting zero and negative values.
+///
+/// Values are capped at `MAX_INTERVAL`, so a huge interval can neither overflow the deadline
+/// nor keep the torrent from announcing for years.
+fn seconds(value: i64) -> Option<Duration> {
+    u64::try_from(value)
+        .ok()
+        .filter(|&s| s > 0)
+        .map(|s| Duration::from_secs(s).min(MAX_INTERVAL))
+}
+
+/// Retry delay after `failures` consecutive failed announces.
+///
+/// The delay doubles with every failure up to `BACKOFF_MAX`, and a random half of it is
+/// jittered away so that many clients do not retry a recovering tracker in lockstep.
+fn backoff(failures: u32) -> Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    let delay = BACKOFF_BASE.saturating_mul(1 << exponent).min(BACKOFF_MAX);
+    let half = delay / 2;
+    half + half.mul_f64(rand::rng().random::<f64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::BencodeValue;
+    use crate::tracker::stub::{self, StubRequest, StubTracker};
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// How far the paused clock may jump at once while a test waits.
+    const STEP: Duration = Duration::from_millis(100);
+    /// Slack allowed when comparing announce times, covering the steps an HTTP exchange takes.
+    const SLACK: Duration = Duration::from_secs(2);
+
+    fn peer(n: u8) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, n), 6881)
+    }
+
+    /// A response without peers that also sets `min interval`.
+    fn response(interval: i64, min_interval: i64) -> Vec<u8> {
+        stub::response(vec![
+            ("interval", BencodeValue::Integer(interval)),
+            ("min interval", BencodeValue::Integer(min_interval)),
+            ("peers", stub::compact_peers(&[])),
+        ])
+    }
+
+    /// Spawns a scheduler announcing to `tracker` alone.
+    fn spawn(tracker: &StubTracker) -> (SchedulerHandle, mpsc::Receiver<Vec<Peer>>) {
+        let client = Arc::new(Client::new(6881).unwrap());
+        let torrent = Arc::new(stub::torrent(&tracker.url));
+        let manager = TrackerManager::new(vec![vec![tracker.url.clone()]]);
+        AnnounceScheduler::new(client, torrent, manager, 3).spawn()
+    }
+
+    /// Lets the paused clock run for `duration`.
+    ///
+    /// The clock moves in small steps so the stub's socket I/O is handled between timer
+    /// expirations, instead of the clock jumping straight to the next timeout.
+    async fn run_for(duration: Duration) {
+        let end = Instant::now() + duration;
+        while Instant::now() < end {
+            tokio::time::sleep(STEP).await;
+        }
+    }
+
+    fn events(requests: &[StubRequest]) -> Vec<&str> {
+        requests.iter().map(StubRequest::event).collect()
+    }
+
+    fn gaps(requests: &[StubRequest]) -> Vec<Duration> {
+        requests.windows(2).map(|w| w[1].at - w[0].at).collect()
+    }
+
+    fn assert_near(actual: Duration, expected: Duration) {
+        assert!(
+            actual.abs_diff(expected) <= SLACK,
+            "expected about {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn huge_intervals_are_capped() {
+        assert_eq!(seconds(i64::MAX), Some(MAX_INTERVAL));
+        assert_eq!(seconds(0), None);
+        assert_eq!(seconds(-5), None);
+        let result = TieredAnnounce {
+            interval: Some(i64::MAX),
+            ..Default::default()
+        };
+        assert_eq!(next_interval(&result), MAX_INTERVAL);
+        let result = TieredAnnounce {
+            interval: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(next_interval(&result), MIN_INTERVAL);
+        assert_eq!(next_interval(&TieredAnnounce::default()), DEFAULT_INTERVAL);
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_cap() {
+        for failures in 1..=20 {
+            let full = BACKOFF_BASE
+                .saturating_mul(1 << (failures - 1).min(16))
+                .min(BACKOFF_MAX);
+            for _ in 0..20 {
+                let delay = backoff(failures);
+                assert!(delay >= full / 2 && delay <= full, "{:?}", delay);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reannounces_every_interval() {
+        let tracker = StubTracker::serve(|_| Some(stub::peers_response(120, &[peer(1)]))).await;
+        let (handle, _peers) = spawn(&tracker);
+        run_for(Duration::from_secs(250)).await;
+
+        let requests = tracker.requests();
+        assert_eq!(events(&requests), ["started", "", ""]);
+        for gap in gaps(&requests) {
+            assert_near(gap, Duration::from_secs(120));
+        }
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn interval_is_raised_to_min_interval() {
+        let tracker = StubTracker::serve(|_| Some(response(10, 90))).await;
+        let (handle, _peers) = spawn(&tracker);
+        run_for(Duration::from_secs(200)).await;
+
+        let requests = tracker.requests();
+        assert_eq!(requests.len(), 3);
+        for gap in gaps(&requests) {
+            assert_near(gap, Duration::from_secs(90));
+        }
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_announces_back_off_exponentially() {
+        let count = AtomicUsize::new(0);
+        let tracker = StubTracker::serve(move |_| {
+            Some(match count.fetch_add(1, Ordering::SeqCst) {
+                0..3 => stub::failure_response("overloaded"),
+                _ => stub::peers_response(1800, &[]),
+            })
+        })
+        .await;
+        let (handle, _peers) = spawn(&tracker);
+        run_for(Duration::from_secs(150)).await;
+
+        let requests = tracker.requests();
+        // `started` is retried until a tracker accepts it
+        assert_eq!(events(&requests), ["started"; 4]);
+        let gaps = gaps(&requests);
+        for (gap, full) in gaps.iter().zip([15, 30, 60]) {
+            let full = Duration::from_secs(full);
+            assert!(
+                *gap + SLACK >= full / 2 && *gap <= full + SLACK,
+                "retry after {:?}, expected {:?} with jitter",
+                gap,
+                full
+            );
+        }
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_announces_stopped() {
+        let tracker = StubTracker::serve(|_| Some(stub::peers_response(1800, &[]))).await;
+        let (handle, _peers) = spawn(&tracker);
+        run_for(Duration::from_secs(5)).await;
+        handle.update_progress(10, 20, 0).await;
+        handle.shutdown().await;
+
+        let requests = tracker.requests();
+        assert_eq!(events(&requests), ["started", "stopped"]);
+        assert_eq!(requests[1].param("uploaded"), Some("10"));
+        assert_eq!(requests[1].param("downloaded"), Some("20"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn nothing_to_stop_before_started_went_through() {
+        let tracker = StubTracker::serve(|_| Some(stub::failure_response("no"))).await;
+        let (handle, _peers) = spawn(&tracker);
+        run_for(Duration::from_secs(5)).await;
+        handle.shutdown().await;
+
+        assert_eq!(events(&tracker.requests()), ["started"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forced_reannounce_waits_for_min_interval() {
+        let tracker = StubTracker::serve(|_| Some(response(1800, 60))).await;
+        let (handle, _peers) = spawn(&tracker);
+        run_for(Duration::from_secs(10)).await;
+        handle.force_reannounce().await;
+        run_for(Duration::from_secs(80)).await;
+
+        let requests = tracker.requests();
+        assert_eq!(events(&requests), ["started", ""]);
+        assert_near(requests[1].at - requests[0].at, Duration::from_secs(60));
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn completed_is_announced_right_away() {
+        let tracker = StubTracker::serve(|_| Some(stub::peers_response(1800, &[]))).await;
+        let (handle, _peers) = spawn(&tracker);
+        run_for(Duration::from_secs(10)).await;
+        handle.completed().await;
+        run_for(Duration::from_secs(5)).await;
+
+        let requests = tracker.requests();
+        assert_eq!(events(&requests), ["started", "completed"]);
+        assert_eq!(requests[1].param("left"), Some("0"));
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_new_peers_are_published() {
+        let count = AtomicUsize::new(0);
+        let tracker = StubTracker::serve(move |_| {
+            Some(match count.fetch_add(1, Ordering::SeqCst) {
+                0 => stub::peers_response(60, &[peer(1), peer(2)]),
+                _ => stub::peers_response(60, &[peer(2), peer(1), peer(3)]),
+            })
+        })
+        .await;
+        let (handle, mut peers) = spawn(&tracker);
+        run_for(Duration::from_secs(130)).await;
+
+        let as_peer = |addr: SocketAddrV4| Peer {
+            ip: (*addr.ip()).into(),
+            port: addr.port(),
+        };
+        assert_eq!(
+            peers.recv().await.unwrap(),
+            [as_peer(peer(1)), as_peer(peer(2))]
+        );
+        assert_eq!(peers.recv().await.unwrap(), [as_peer(peer(3))]);
+        // The third announce brought nothing new
+        assert_eq!(tracker.requests().len(), 3);
+        assert!(peers.try_recv().is_err());
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn huge_intervals_do_not_stop_the_task() {
+        let tracker = StubTracker::serve(|_| Some(response(i64::MAX, i64::MAX))).await;
+        let (handle, _peers) = spawn(&tracker);
+        run_for(Duration::from_secs(5)).await;
+        handle.force_reannounce().await;
+        run_for(Duration::from_secs(5)).await;
+        handle.shutdown().await;
+
+        // The task survived to announce `stopped`
+        assert_eq!(events(&tracker.requests()), ["started", "stopped"]);
+    }
+}
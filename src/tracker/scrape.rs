@@ -0,0 +1,219 @@
+//! Tracker scrape support for HTTP and UDP trackers.
+//!
+//! A scrape asks a tracker for swarm statistics (seeders, leechers and completed downloads)
+//! without announcing, so the health of a torrent can be checked before starting it.
+use super::{Client, ScrapeStats, url_encode};
+use crate::torrent::TorrentError;
+use anyhow::Result;
+use serde_bencode::value::Value;
+use std::collections::HashMap;
+
+impl Client {
+    /// Scrapes a tracker for the swarm statistics of one or more torrents.
+    ///
+    /// The scrape endpoint is derived from the announce URL (see `scrape_url`) for HTTP trackers;
+    /// `udp://` trackers use the BEP 15 scrape action, split into batches of at most
+    /// `udp::MAX_SCRAPE_HASHES` info hashes.
+    ///
+    /// # Arguments
+    /// * `tracker` - The tracker's announce URL
+    /// * `info_hashes` - The info hashes to query
+    ///
+    /// # Returns
+    /// * `Result<HashMap<[u8; 20], ScrapeStats>>` - Statistics keyed by info hash; torrents the tracker
+    ///   does not know about are absent from the map
+    #[tracing::instrument(skip(self, info_hashes), level = "debug")]
+    pub async fn scrape(
+        &self,
+        tracker: &str,
+        info_hashes: &[[u8; 20]],
+    ) -> Result<HashMap<[u8; 20], ScrapeStats>> {
+        let url = url::Url::parse(tracker)?;
+        match url.scheme() {
+            "udp" => {
+                let mut result = HashMap::with_capacity(info_hashes.len());
+                for batch in info_hashes.chunks(super::udp::MAX_SCRAPE_HASHES) {
+                    let stats = self.udp.scrape(&url, batch).await?;
+                    result.extend(batch.iter().copied().zip(stats));
+                }
+                Ok(result)
+            }
+            "http" | "https" => self.scrape_http(scrape_url(&url)?, info_hashes).await,
+            scheme => Err(TorrentError::InvalidFormat(format!(
+                "Unsupported tracker scheme: {}",
+                scheme
+            ))
+            .into()),
+        }
+    }
+
+    /// Sends a scrape request to an HTTP(S) tracker, with one `info_hash` parameter per torrent.
+    async fn scrape_http(
+        &self,
+        mut url: url::Url,
+        info_hashes: &[[u8; 20]],
+    ) -> Result<HashMap<[u8; 20], ScrapeStats>> {
+        let query = info_hashes
+            .iter()
+            .map(|info_hash| format!("info_hash={}", url_encode(info_hash)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let query = match url.query() {
+            Some(existing) if !existing.is_empty() => format!("{}&{}", existing, query),
+            _ => query,
+        };
+        url.set_query(Some(&query));
+        tracing::debug!(?url, "Making scrape request to tracker");
+        let response = self.http.get(url).send().await?.error_for_status()?;
+        let response_bytes = response.bytes().await?;
+        parse_scrape_response(&response_bytes)
+    }
+}
+
+/// Derives the scrape URL from an HTTP announce URL.
+///
+/// By convention the scrape URL is found by replacing `announce` with `scrape` in the last path
+/// component, which must start with `announce`:
+/// - `http://example.com/announce` becomes `http://example.com/scrape`
+/// - `http://example.com/x/announce.php?k=v` becomes `http://example.com/x/scrape.php?k=v`
+///
+/// Trackers whose announce URL does not follow this convention do not support scraping.
+pub fn scrape_url(announce: &url::Url) -> Result<url::Url> {
+    let path = announce.path();
+    let (dir, last) = match path.rfind('/') {
+        Some(index) => path.split_at(index + 1),
+        None => ("", path),
+    };
+    let Some(rest) = last.strip_prefix("announce") else {
+        return Err(TorrentError::InvalidFormat(format!(
+            "Tracker does not support scrape: {}",
+            announce
+        ))
+        .into());
+    };
+    let mut url = announce.clone();
+    url.set_path(&format!("{}scrape{}", dir, rest));
+    Ok(url)
+}
+
+/// Parses the bencoded response of an HTTP scrape request.
+///
+/// The response holds a `files` dictionary keyed by the raw 20-byte info hash, each with
+/// `complete`, `downloaded` and `incomplete` counts.
+fn parse_scrape_response(bytes: &[u8]) -> Result<HashMap<[u8; 20], ScrapeStats>> {
+    let Value::Dict(mut root) = serde_bencode::from_bytes::<Value>(bytes)? else {
+        return Err(
+            TorrentError::InvalidTrackerResponse("scrape is not a dict".to_string()).into(),
+        );
+    };
+    if let Some(Value::Bytes(reason)) = root.remove(b"failure reason".as_slice()) {
+        return Err(
+            TorrentError::TrackerFailure(String::from_utf8_lossy(&reason).into_owned()).into(),
+        );
+    }
+    let files = match root.remove(b"files".as_slice()) {
+        Some(Value::Dict(files)) => files,
+        _ => {
+            return Err(TorrentError::InvalidTrackerResponse(
+                "scrape is missing files".to_string(),
+            )
+            .into());
+        }
+    };
+
+    let mut result = HashMap::with_capacity(files.len());
+    for (info_hash, stats) in files {
+        let Ok(info_hash) = <[u8; 20]>::try_from(info_hash.as_slice()) else {
+            continue;
+        };
+        let Value::Dict(stats) = stats else {
+            continue;
+        };
+        let count = |key: &[u8]| match stats.get(key) {
+            Some(Value::Int(n)) => *n,
+            _ => 0,
+        };
+        result.insert(
+            info_hash,
+            ScrapeStats {
+                complete: count(b"complete"),
+                downloaded: count(b"downloaded"),
+                incomplete: count(b"incomplete"),
+            },
+        );
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrape(announce: &str) -> Result<String> {
+        scrape_url(&url::Url::parse(announce)?).map(String::from)
+    }
+
+    #[test]
+    fn scrape_url_replaces_announce() {
+        assert_eq!(
+            scrape("http://example.com/announce").unwrap(),
+            "http://example.com/scrape"
+        );
+        assert_eq!(
+            scrape("http://example.com/x/announce.php?k=v").unwrap(),
+            "http://example.com/x/scrape.php?k=v"
+        );
+        assert_eq!(
+            scrape("https://example.com:8443/announce?passkey=abc").unwrap(),
+            "https://example.com:8443/scrape?passkey=abc"
+        );
+    }
+
+    #[test]
+    fn scrape_url_needs_announce_in_the_last_component() {
+        assert!(scrape("http://example.com/a").is_err());
+        assert!(scrape("http://example.com/x/announce/").is_err());
+        assert!(scrape("http://example.com/announce/x").is_err());
+        assert!(scrape("http://example.com/").is_err());
+    }
+
+    #[test]
+    fn parses_files_dictionary() {
+        let mut body = b"d5:filesd20:".to_vec();
+        body.extend_from_slice(&[1; 20]);
+        body.extend_from_slice(b"d8:completei5e10:downloadedi50e10:incompletei10ee20:");
+        body.extend_from_slice(&[2; 20]);
+        body.extend_from_slice(b"d8:completei1ee3:bad");
+        body.extend_from_slice(b"d8:completei9eeee");
+
+        let stats = parse_scrape_response(&body).unwrap();
+        assert_eq!(stats.len(), 2, "short info hashes are skipped");
+        assert_eq!(
+            stats[&[1; 20]],
+            ScrapeStats {
+                complete: 5,
+                downloaded: 50,
+                incomplete: 10,
+            }
+        );
+        assert_eq!(
+            stats[&[2; 20]],
+            ScrapeStats {
+                complete: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_failures_and_missing_files() {
+        let error = parse_scrape_response(b"d14:failure reason4:nopee").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<TorrentError>(),
+            Some(TorrentError::TrackerFailure(reason)) if reason == "nope"
+        ));
+        assert!(parse_scrape_response(b"d5:filesi1ee").is_err());
+        assert!(parse_scrape_response(b"le").is_err());
+        assert!(parse_scrape_response(b"d5:filesdee").unwrap().is_empty());
+    }
+}
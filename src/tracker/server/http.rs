@@ -0,0 +1,345 @@
+//! HTTP front end of the embedded tracker.
+//!
+//! A deliberately small HTTP/1.x server: it reads one `GET` request per connection, answers
+//! `/announce` and `/scrape` with a bencoded body and closes the connection, which is all
+//! BitTorrent clients need from a tracker.
+use super::{AnnounceReply, PeerAnnounce, TrackerState};
+use crate::bencode::{self, BencodeValue};
+use crate::tracker::{AnnounceEvent, ScrapeStats};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+/// Largest request head accepted; announces are a few hundred bytes.
+const MAX_REQUEST_SIZE: usize = 8192;
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts connections until the listener fails, handling each one in its own task.
+pub(super) async fn serve(listener: TcpListener, state: Arc<Mutex<TrackerState>>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, remote)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, remote, state).await {
+                        tracing::debug!(%remote, error = %e, "HTTP tracker request failed");
+                    }
+                });
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "HTTP tracker stopped accepting connections");
+                return;
+            }
+        }
+    }
+}
+
+/// Reads one request from the connection and writes the response.
+async fn handle(
+    mut stream: TcpStream,
+    remote: SocketAddr,
+    state: Arc<Mutex<TrackerState>>,
+) -> anyhow::Result<()> {
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream)).await??;
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next(), parts.next());
+
+    let (status, body) = match (method, target) {
+        (Some("GET"), Some(target)) => {
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            let query = parse_query(query);
+            match path {
+                "/announce" => (200, announce(&query, remote, &state).await),
+                "/scrape" => (200, scrape(&query, &state).await),
+                _ => (404, b"Not Found".to_vec()),
+            }
+        }
+        _ => (400, b"Bad Request".to_vec()),
+    };
+
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Bad Request",
+    };
+    let header = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads the request line and headers, up to the blank line that ends them.
+async fn read_head(stream: &mut TcpStream) -> anyhow::Result<String> {
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buffer.len() > MAX_REQUEST_SIZE {
+            anyhow::bail!("Request head too large");
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("Connection closed before the request was complete");
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Handles `/announce` and returns the bencoded response body.
+async fn announce(
+    query: &HashMap<String, Vec<Vec<u8>>>,
+    remote: SocketAddr,
+    state: &Mutex<TrackerState>,
+) -> Vec<u8> {
+    let request = match parse_announce(query, remote) {
+        Ok(request) => request,
+        Err(reason) => return failure(&reason),
+    };
+    let compact = param(query, "compact").is_none_or(|v| v != b"0");
+    let no_peer_id = param(query, "no_peer_id").is_some_and(|v| v == b"1");
+
+    let reply = state.lock().await.announce(&request, Instant::now());
+    match reply {
+        Ok(reply) => encode(&announce_response(
+            &reply,
+            request.addr.ip(),
+            compact,
+            no_peer_id,
+        )),
+        Err(reason) => failure(&reason),
+    }
+}
+
+/// Extracts the announce parameters from the query string.
+fn parse_announce(
+    query: &HashMap<String, Vec<Vec<u8>>>,
+    remote: SocketAddr,
+) -> std::result::Result<PeerAnnounce, String> {
+    let info_hash = param(query, "info_hash")
+        .and_then(|v| <[u8; 20]>::try_from(v).ok())
+        .ok_or("Missing or invalid info_hash")?;
+    let peer_id = param(query, "peer_id")
+        .and_then(|v| <[u8; 20]>::try_from(v).ok())
+        .ok_or("Missing or invalid peer_id")?;
+    let port = number::<u16>(query, "port")
+        .filter(|&port| port != 0)
+        .ok_or("Missing or invalid port")?;
+    let event = param(query, "event")
+        .map(|v| AnnounceEvent::from_query(&String::from_utf8_lossy(v)))
+        .unwrap_or_default();
+
+    // The `ip` parameter is ignored: trusting it would let anyone add other hosts to a swarm
+    let ip = match remote.ip() {
+        IpAddr::V6(ip) => ip.to_canonical(),
+        ip => ip,
+    };
+    Ok(PeerAnnounce {
+        info_hash,
+        peer_id,
+        addr: SocketAddr::new(ip, port),
+        uploaded: number(query, "uploaded").unwrap_or(0),
+        downloaded: number(query, "downloaded").unwrap_or(0),
+        left: number(query, "left").unwrap_or(0),
+        event,
+        numwant: number(query, "numwant"),
+    })
+}
+
+/**
+Builds the announce response dictionary.
+
+# Torrent Protocol Context:
+Compact responses (BEP 23) pack IPv4 peers into `peers` as 6-byte entries and IPv6 peers into
+`peers6` (BEP 7) as 18-byte entries. Non-compact responses list one dictionary per peer, leaving
+out `peer id` when the client sent `no_peer_id=1`. The address the request came from is
+returned as `external ip` (BEP 24).
+*/
+fn announce_response(
+    reply: &AnnounceReply,
+    external_ip: IpAddr,
+    compact: bool,
+    no_peer_id: bool,
+) -> BencodeValue {
+    let mut dict = HashMap::new();
+    dict.insert(
+        b"interval".to_vec(),
+        BencodeValue::Integer(reply.interval.as_secs() as i64),
+    );
+    dict.insert(
+        b"min interval".to_vec(),
+        BencodeValue::Integer(reply.min_interval.as_secs() as i64),
+    );
+    dict.insert(b"complete".to_vec(), BencodeValue::Integer(reply.complete));
+    let external_ip = match external_ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    dict.insert(b"external ip".to_vec(), BencodeValue::String(external_ip));
+    dict.insert(
+        b"incomplete".to_vec(),
+        BencodeValue::Integer(reply.incomplete),
+    );
+
+    if compact {
+        let mut peers = Vec::new();
+        let mut peers6 = Vec::new();
+        for peer in &reply.peers {
+            match peer.addr {
+                SocketAddr::V4(addr) => {
+                    peers.extend_from_slice(&addr.ip().octets());
+                    peers.extend_from_slice(&addr.port().to_be_bytes());
+                }
+                SocketAddr::V6(addr) => {
+                    peers6.extend_from_slice(&addr.ip().octets());
+                    peers6.extend_from_slice(&addr.port().to_be_bytes());
+                }
+            }
+        }
+        dict.insert(b"peers".to_vec(), BencodeValue::String(peers));
+        if !peers6.is_empty() {
+            dict.insert(b"peers6".to_vec(), BencodeValue::String(peers6));
+        }
+    } else {
+        let peers = reply
+            .peers
+            .iter()
+            .map(|peer| {
+                let mut entry = HashMap::new();
+                entry.insert(
+                    b"ip".to_vec(),
+                    BencodeValue::String(peer.addr.ip().to_string().into_bytes()),
+                );
+                entry.insert(
+                    b"port".to_vec(),
+                    BencodeValue::Integer(peer.addr.port() as i64),
+                );
+                if !no_peer_id {
+                    entry.insert(
+                        b"peer id".to_vec(),
+                        BencodeValue::String(peer.peer_id.to_vec()),
+                    );
+                }
+                BencodeValue::Dict(entry)
+            })
+            .collect();
+        dict.insert(b"peers".to_vec(), BencodeValue::List(peers));
+    }
+    BencodeValue::Dict(dict)
+}
+
+/// Handles `/scrape` and returns the bencoded response body.
+async fn scrape(query: &HashMap<String, Vec<Vec<u8>>>, state: &Mutex<TrackerState>) -> Vec<u8> {
+    let mut info_hashes = Vec::new();
+    for value in query.get("info_hash").into_iter().flatten() {
+        match <[u8; 20]>::try_from(value.as_slice()) {
+            std::result::Result::Ok(info_hash) => info_hashes.push(info_hash),
+            Err(_) => return failure("Invalid info_hash"),
+        }
+    }
+
+    let state = state.lock().await;
+    if info_hashes.is_empty() && !state.config().full_scrape {
+        return failure("Full scrape is disabled");
+    }
+    let stats = state.scrape(&info_hashes);
+    drop(state);
+    let files = stats
+        .into_iter()
+        .map(|(info_hash, stats)| (info_hash.to_vec(), scrape_entry(&stats)))
+        .collect();
+    let mut dict = HashMap::new();
+    dict.insert(b"files".to_vec(), BencodeValue::Dict(files));
+    encode(&BencodeValue::Dict(dict))
+}
+
+/// Builds the `files` entry of one torrent in a scrape response.
+fn scrape_entry(stats: &ScrapeStats) -> BencodeValue {
+    let mut entry = HashMap::new();
+    entry.insert(b"complete".to_vec(), BencodeValue::Integer(stats.complete));
+    entry.insert(
+        b"downloaded".to_vec(),
+        BencodeValue::Integer(stats.downloaded),
+    );
+    entry.insert(
+        b"incomplete".to_vec(),
+        BencodeValue::Integer(stats.incomplete),
+    );
+    BencodeValue::Dict(entry)
+}
+
+/// Encodes a response carrying only a `failure reason`.
+fn failure(reason: &str) -> Vec<u8> {
+    let mut dict = HashMap::new();
+    dict.insert(
+        b"failure reason".to_vec(),
+        BencodeValue::String(reason.as_bytes().to_vec()),
+    );
+    encode(&BencodeValue::Dict(dict))
+}
+
+fn encode(value: &BencodeValue) -> Vec<u8> {
+    let mut body = Vec::new();
+    // Writing into a Vec cannot fail
+    let _ = bencode::encoder::encode(&mut body, value);
+    body
+}
+
+/// Splits a query string into percent-decoded values, keeping repeated keys such as `info_hash`.
+fn parse_query(query: &str) -> HashMap<String, Vec<Vec<u8>>> {
+    let mut params: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let key = String::from_utf8_lossy(&percent_decode(key)).into_owned();
+        params.entry(key).or_default().push(percent_decode(value));
+    }
+    params
+}
+
+/// Decodes `%XX` escapes and `+` into raw bytes; info hashes and peer IDs are binary.
+fn percent_decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len()
+                && bytes[i + 1].is_ascii_hexdigit()
+                && bytes[i + 2].is_ascii_hexdigit() =>
+            {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+                i += 3;
+                continue;
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    decoded
+}
+
+/// Returns the first value of a query parameter.
+fn param<'a>(query: &'a HashMap<String, Vec<Vec<u8>>>, key: &str) -> Option<&'a [u8]> {
+    query
+        .get(key)
+        .and_then(|values| values.first())
+        .map(Vec::as_slice)
+}
+
+/// Parses a numeric query parameter.
+fn number<T: std::str::FromStr>(query: &HashMap<String, Vec<Vec<u8>>>, key: &str) -> Option<T> {
+    std::str::from_utf8(param(query, key)?).ok()?.parse().ok()
+}
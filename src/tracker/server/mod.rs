@@ -0,0 +1,680 @@
+//! Embedded BitTorrent tracker.
+//!
+//! This module runs a small in-memory tracker, useful for private distribution and integration tests:
+//! - HTTP `/announce` and `/scrape`, with compact and non-compact peer lists (`http.rs`)
+//! - The UDP tracker protocol (BEP 15), optionally on a separate socket (`udp.rs`)
+//! - Announce interval enforcement and expiry of peers that stop announcing
+//! - A periodic sweep that drops expired peers and empty swarms, and a cap on the number of swarms
+//! - An optional info-hash whitelist, for trackers serving only known torrents
+//! - Full scrape (a scrape naming no torrent) only when enabled, since it lists every info hash
+//!
+//! Both front ends share one `TrackerState`, so a peer announcing over UDP is handed out to HTTP clients too.
+use super::{AnnounceEvent, ScrapeStats};
+use anyhow::Result;
+use rand::seq::IteratorRandom;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Once, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+pub mod http;
+pub mod udp;
+
+/// Interval clients are asked to wait between announces, unless configured otherwise.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// Minimum time between two periodic announces from the same peer, unless configured otherwise.
+pub const DEFAULT_MIN_INTERVAL: Duration = Duration::from_secs(60);
+/// Number of peers returned when the client does not send `numwant`.
+pub const DEFAULT_NUMWANT: usize = 50;
+/// Upper bound on the number of peers returned by a single announce.
+pub const MAX_NUMWANT: usize = 200;
+/// Number of torrents tracked at once, unless configured otherwise.
+pub const DEFAULT_MAX_SWARMS: usize = 100_000;
+/// How often expired peers and empty swarms are swept away.
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Settings of an embedded tracker.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Interval sent to clients in every announce response.
+    pub interval: Duration,
+    /// Periodic announces arriving sooner than this after the previous one are rejected.
+    pub min_interval: Duration,
+    /// Peers that have not announced for this long are dropped from their swarm.
+    pub peer_timeout: Duration,
+    /// When set, only these info hashes are tracked; announces for others are refused.
+    pub whitelist: Option<HashSet<[u8; 20]>>,
+    /// Announces that would start a new swarm beyond this many are refused.
+    pub max_swarms: usize,
+    /// Whether a scrape naming no torrent returns the statistics of every torrent.
+    pub full_scrape: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            min_interval: DEFAULT_MIN_INTERVAL,
+            peer_timeout: DEFAULT_INTERVAL * 2,
+            whitelist: None,
+            max_swarms: DEFAULT_MAX_SWARMS,
+            full_scrape: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Sets the announce interval; the peer timeout follows at twice the interval.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self.peer_timeout = interval * 2;
+        self
+    }
+
+    /// Sets the minimum time between periodic announces from one peer.
+    pub fn with_min_interval(mut self, min_interval: Duration) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    /// Restricts the tracker to the given info hashes.
+    pub fn with_whitelist(mut self, info_hashes: impl IntoIterator<Item = [u8; 20]>) -> Self {
+        self.whitelist = Some(info_hashes.into_iter().collect());
+        self
+    }
+
+    /// Sets the number of torrents that may be tracked at once.
+    pub fn with_max_swarms(mut self, max_swarms: usize) -> Self {
+        self.max_swarms = max_swarms;
+        self
+    }
+
+    /// Enables full scrape, which reveals every tracked info hash to anyone who asks.
+    pub fn with_full_scrape(mut self, enabled: bool) -> Self {
+        self.full_scrape = enabled;
+        self
+    }
+}
+
+/// An announce received by either front end, after protocol-specific decoding.
+#[derive(Debug, Clone)]
+pub struct PeerAnnounce {
+    pub info_hash: [u8; 20],
+    pub peer_id: [u8; 20],
+    /// Address peers should connect to: the request's source IP with the announced port.
+    pub addr: SocketAddr,
+    pub uploaded: i64,
+    pub downloaded: i64,
+    pub left: i64,
+    pub event: AnnounceEvent,
+    /// Number of peers requested; `None` uses `DEFAULT_NUMWANT`.
+    pub numwant: Option<usize>,
+}
+
+/// A peer handed out in an announce reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwarmPeer {
+    pub peer_id: [u8; 20],
+    pub addr: SocketAddr,
+}
+
+/// The tracker's answer to a successful announce.
+#[derive(Debug, Clone)]
+pub struct AnnounceReply {
+    pub interval: Duration,
+    pub min_interval: Duration,
+    pub complete: i64,
+    pub incomplete: i64,
+    pub peers: Vec<SwarmPeer>,
+}
+
+/// A peer as remembered by the tracker.
+#[derive(Debug, Clone)]
+struct PeerEntry {
+    addr: SocketAddr,
+    left: i64,
+    last_announce: Instant,
+}
+
+/// The peers of one torrent.
+#[derive(Debug, Default)]
+struct Swarm {
+    peers: HashMap<[u8; 20], PeerEntry>,
+    /// Number of `completed` events received.
+    downloaded: i64,
+}
+
+impl Swarm {
+    fn stats(&self) -> ScrapeStats {
+        let complete = self.peers.values().filter(|p| p.left == 0).count() as i64;
+        ScrapeStats {
+            complete,
+            downloaded: self.downloaded,
+            incomplete: self.peers.len() as i64 - complete,
+        }
+    }
+}
+
+/// Swarm state shared by the HTTP and UDP front ends.
+#[derive(Debug)]
+pub struct TrackerState {
+    config: ServerConfig,
+    swarms: HashMap<[u8; 20], Swarm>,
+}
+
+impl TrackerState {
+    /// Creates an empty tracker.
+    pub fn new(config: ServerConfig) -> Self {
+        Self {
+            config,
+            swarms: HashMap::new(),
+        }
+    }
+
+    /// Returns the tracker's settings.
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Returns the number of torrents currently tracked.
+    pub fn swarm_count(&self) -> usize {
+        self.swarms.len()
+    }
+
+    /// Returns `true` if the tracker serves the given torrent.
+    pub fn is_allowed(&self, info_hash: &[u8; 20]) -> bool {
+        self.config
+            .whitelist
+            .as_ref()
+            .is_none_or(|whitelist| whitelist.contains(info_hash))
+    }
+
+    /**
+    Records an announce and picks the peers to return.
+
+    # How it works:
+    1. Refuses torrents outside the whitelist, and new torrents once `max_swarms` are tracked
+       (after sweeping away expired peers and empty swarms to make room).
+    2. Refuses periodic announces sent before `min_interval` has passed; lifecycle events are always accepted.
+    3. Drops peers that stopped announcing, then removes, adds or updates the announcing peer.
+    4. Counts a `completed` event once per peer.
+    5. Returns up to `numwant` randomly chosen peers other than the one announcing.
+
+    # Returns
+    * `Result<AnnounceReply, String>` - The reply, or the failure reason to send to the client
+    */
+    pub fn announce(
+        &mut self,
+        request: &PeerAnnounce,
+        now: Instant,
+    ) -> std::result::Result<AnnounceReply, String> {
+        if !self.is_allowed(&request.info_hash) {
+            return Err("Torrent is not registered with this tracker".to_string());
+        }
+        if !self.swarms.contains_key(&request.info_hash)
+            && self.swarms.len() >= self.config.max_swarms
+        {
+            self.prune(now);
+            if self.swarms.len() >= self.config.max_swarms {
+                return Err("Tracker is tracking too many torrents".to_string());
+            }
+        }
+        let peer_timeout = self.config.peer_timeout;
+        let min_interval = self.config.min_interval;
+        let swarm = self.swarms.entry(request.info_hash).or_default();
+        swarm
+            .peers
+            .retain(|_, peer| now.duration_since(peer.last_announce) < peer_timeout);
+
+        let previous = swarm.peers.get(&request.peer_id).cloned();
+        if let Some(previous) = &previous
+            && request.event == AnnounceEvent::None
+            && now.duration_since(previous.last_announce) < min_interval
+        {
+            return Err(format!(
+                "Announce interval too short, wait {} seconds",
+                min_interval.as_secs()
+            ));
+        }
+
+        if request.event == AnnounceEvent::Stopped {
+            swarm.peers.remove(&request.peer_id);
+        } else {
+            if request.event == AnnounceEvent::Completed
+                && previous.as_ref().is_none_or(|p| p.left != 0)
+            {
+                swarm.downloaded += 1;
+            }
+            swarm.peers.insert(
+                request.peer_id,
+                PeerEntry {
+                    addr: request.addr,
+                    left: request.left.max(0),
+                    last_announce: now,
+                },
+            );
+        }
+
+        let numwant = request.numwant.unwrap_or(DEFAULT_NUMWANT).min(MAX_NUMWANT);
+        let peers = if request.event == AnnounceEvent::Stopped {
+            Vec::new()
+        } else {
+            swarm
+                .peers
+                .iter()
+                .filter(|(peer_id, _)| **peer_id != request.peer_id)
+                .map(|(peer_id, entry)| SwarmPeer {
+                    peer_id: *peer_id,
+                    addr: entry.addr,
+                })
+                .choose_multiple(&mut rand::rng(), numwant)
+        };
+
+        let stats = swarm.stats();
+        Ok(AnnounceReply {
+            interval: self.config.interval,
+            min_interval,
+            complete: stats.complete,
+            incomplete: stats.incomplete,
+            peers,
+        })
+    }
+
+    /// Drops peers that have not announced within the peer timeout, then swarms left without peers.
+    pub fn prune(&mut self, now: Instant) {
+        let peer_timeout = self.config.peer_timeout;
+        for swarm in self.swarms.values_mut() {
+            swarm
+                .peers
+                .retain(|_, peer| now.duration_since(peer.last_announce) < peer_timeout);
+        }
+        let before = self.swarms.len();
+        self.swarms.retain(|_, swarm| !swarm.peers.is_empty());
+        if self.swarms.len() < before {
+            tracing::debug!(removed = before - self.swarms.len(), "Pruned empty swarms");
+        }
+    }
+
+    /// Returns the statistics of the requested torrents.
+    ///
+    /// An empty request scrapes every torrent the tracker knows if full scrape is enabled, and
+    /// nothing otherwise. Torrents outside the whitelist are left out, and allowed torrents without
+    /// peers are reported with zero counts.
+    pub fn scrape(&self, info_hashes: &[[u8; 20]]) -> HashMap<[u8; 20], ScrapeStats> {
+        if info_hashes.is_empty() {
+            if !self.config.full_scrape {
+                return HashMap::new();
+            }
+            return self
+                .swarms
+                .iter()
+                .map(|(info_hash, swarm)| (*info_hash, swarm.stats()))
+                .collect();
+        }
+        info_hashes
+            .iter()
+            .filter(|info_hash| self.is_allowed(info_hash))
+            .map(|info_hash| {
+                let stats = self
+                    .swarms
+                    .get(info_hash)
+                    .map(Swarm::stats)
+                    .unwrap_or_default();
+                (*info_hash, stats)
+            })
+            .collect()
+    }
+}
+
+/// An embedded tracker serving one shared `TrackerState` over HTTP and UDP.
+///
+/// # Example
+/// ```no_run
+/// use rs_torrent_client::tracker::server::{ServerConfig, TrackerServer};
+///
+/// # async fn run() -> anyhow::Result<()> {
+/// let server = TrackerServer::new(ServerConfig::default());
+/// let http = server.bind_http("127.0.0.1:0".parse()?).await?;
+/// println!("announce URL: http://{}/announce", http);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TrackerServer {
+    state: Arc<Mutex<TrackerState>>,
+    pruning: Arc<Once>,
+}
+
+impl TrackerServer {
+    /// Creates a tracker with no swarms.
+    pub fn new(config: ServerConfig) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(TrackerState::new(config))),
+            pruning: Arc::new(Once::new()),
+        }
+    }
+
+    /// Returns the shared swarm state.
+    pub fn state(&self) -> Arc<Mutex<TrackerState>> {
+        self.state.clone()
+    }
+
+    /// Starts serving HTTP announces and scrapes on `addr` in a background task.
+    ///
+    /// # Returns
+    /// * `Result<SocketAddr>` - The bound address, useful when binding to port 0
+    pub async fn bind_http(&self, addr: SocketAddr) -> Result<SocketAddr> {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        let local = listener.local_addr()?;
+        self.start_pruning();
+        tokio::spawn(http::serve(listener, self.state.clone()));
+        tracing::info!(%local, "HTTP tracker listening");
+        Ok(local)
+    }
+
+    /// Starts serving the UDP tracker protocol on `addr` in a background task.
+    ///
+    /// # Returns
+    /// * `Result<SocketAddr>` - The bound address, useful when binding to port 0
+    pub async fn bind_udp(&self, addr: SocketAddr) -> Result<SocketAddr> {
+        let socket = tokio::net::UdpSocket::bind(addr).await?;
+        let local = socket.local_addr()?;
+        self.start_pruning();
+        tokio::spawn(udp::serve(socket, self.state.clone()));
+        tracing::info!(%local, "UDP tracker listening");
+        Ok(local)
+    }
+
+    /// Starts the periodic sweep of expired peers and empty swarms, once per tracker.
+    fn start_pruning(&self) {
+        self.pruning.call_once(|| {
+            tokio::spawn(prune(Arc::downgrade(&self.state)));
+        });
+    }
+}
+
+/// Prunes the swarms every `PRUNE_INTERVAL`, until the tracker state is dropped.
+async fn prune(state: Weak<Mutex<TrackerState>>) {
+    let mut ticker = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        state.lock().await.prune(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::TorrentError;
+    use crate::torrent::file::TorrentFile;
+    use crate::tracker::{AnnounceParams, Client, Peer, stub};
+
+    struct Running {
+        http: String,
+        udp: String,
+        torrent: TorrentFile,
+    }
+
+    /// Starts a tracker with both front ends on local ports.
+    async fn start(config: ServerConfig) -> Running {
+        let server = TrackerServer::new(config);
+        let local = "127.0.0.1:0".parse().unwrap();
+        let http = format!("http://{}/announce", server.bind_http(local).await.unwrap());
+        let udp = format!("udp://{}", server.bind_udp(local).await.unwrap());
+        let torrent = stub::torrent(&http);
+        Running { http, udp, torrent }
+    }
+
+    fn client(port: u16) -> Client {
+        Client::new(port)
+            .unwrap()
+            .with_udp_retransmission(Duration::from_millis(200), 2)
+    }
+
+    fn params(event: AnnounceEvent, left: i64) -> AnnounceParams {
+        AnnounceParams {
+            event,
+            left: Some(left),
+            ..Default::default()
+        }
+    }
+
+    fn local_peer(port: u16) -> Peer {
+        Peer {
+            ip: "127.0.0.1".parse().unwrap(),
+            port,
+        }
+    }
+
+    fn tracker_failure(error: anyhow::Error) -> String {
+        match error.downcast::<TorrentError>() {
+            Ok(TorrentError::TrackerFailure(reason)) => reason,
+            other => panic!("expected a tracker failure, got {:?}", other),
+        }
+    }
+
+    fn peer_announce(info_hash: u8, peer: u8, event: AnnounceEvent) -> PeerAnnounce {
+        PeerAnnounce {
+            info_hash: [info_hash; 20],
+            peer_id: [peer; 20],
+            addr: SocketAddr::from(([203, 0, 113, peer], 6881)),
+            uploaded: 0,
+            downloaded: 0,
+            left: 100,
+            event,
+            numwant: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn peers_are_shared_between_http_and_udp() {
+        let tracker = start(ServerConfig::default()).await;
+        let (seeder, leecher) = (client(6881), client(6882));
+
+        let response = seeder
+            .announce_to(
+                &tracker.http,
+                &tracker.torrent,
+                &params(AnnounceEvent::Started, 0),
+            )
+            .await
+            .unwrap();
+        assert!(response.peers.is_empty());
+        assert_eq!(response.interval, DEFAULT_INTERVAL.as_secs() as i64);
+        assert_eq!(
+            response.min_interval,
+            Some(DEFAULT_MIN_INTERVAL.as_secs() as i64)
+        );
+
+        let response = leecher
+            .announce_to(
+                &tracker.udp,
+                &tracker.torrent,
+                &params(AnnounceEvent::Started, 3),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.peers, [local_peer(6881)]);
+        assert_eq!((response.complete, response.incomplete), (Some(1), Some(1)));
+
+        let response = leecher
+            .announce_to(
+                &tracker.udp,
+                &tracker.torrent,
+                &params(AnnounceEvent::Completed, 0),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.peers, [local_peer(6881)]);
+
+        for url in [&tracker.http, &tracker.udp] {
+            let stats = seeder
+                .scrape(url, &[tracker.torrent.info_hash])
+                .await
+                .unwrap();
+            assert_eq!(
+                stats[&tracker.torrent.info_hash],
+                ScrapeStats {
+                    complete: 2,
+                    downloaded: 1,
+                    incomplete: 0,
+                }
+            );
+        }
+
+        leecher
+            .announce_to(
+                &tracker.udp,
+                &tracker.torrent,
+                &params(AnnounceEvent::Stopped, 0),
+            )
+            .await
+            .unwrap();
+        let response = seeder
+            .announce_to(
+                &tracker.http,
+                &tracker.torrent,
+                &params(AnnounceEvent::Completed, 0),
+            )
+            .await
+            .unwrap();
+        assert!(response.peers.is_empty());
+        assert_eq!((response.complete, response.incomplete), (Some(1), Some(0)));
+    }
+
+    #[tokio::test]
+    async fn early_periodic_announces_are_refused() {
+        let tracker = start(ServerConfig::default()).await;
+        let client = client(6881);
+        for url in [&tracker.http, &tracker.udp] {
+            client
+                .announce_to(url, &tracker.torrent, &params(AnnounceEvent::Started, 3))
+                .await
+                .unwrap();
+            let error = client
+                .announce_to(url, &tracker.torrent, &params(AnnounceEvent::None, 3))
+                .await
+                .unwrap_err();
+            assert!(tracker_failure(error).contains("Announce interval too short"));
+        }
+    }
+
+    #[tokio::test]
+    async fn whitelist_refuses_other_torrents() {
+        let tracker = start(ServerConfig::default().with_whitelist([[0; 20]])).await;
+        let client = client(6881);
+        for url in [&tracker.http, &tracker.udp] {
+            let error = client
+                .announce_to(url, &tracker.torrent, &params(AnnounceEvent::Started, 3))
+                .await
+                .unwrap_err();
+            assert!(tracker_failure(error).contains("not registered"));
+        }
+        let stats = client
+            .scrape(&tracker.http, &[tracker.torrent.info_hash])
+            .await
+            .unwrap();
+        assert!(stats.is_empty());
+    }
+
+    #[tokio::test]
+    async fn full_scrape_is_opt_in() {
+        let client = client(6881);
+
+        let tracker = start(ServerConfig::default()).await;
+        client
+            .announce_to(
+                &tracker.http,
+                &tracker.torrent,
+                &params(AnnounceEvent::Started, 3),
+            )
+            .await
+            .unwrap();
+        let error = client.scrape(&tracker.http, &[]).await.unwrap_err();
+        assert!(tracker_failure(error).contains("Full scrape is disabled"));
+
+        let tracker = start(ServerConfig::default().with_full_scrape(true)).await;
+        client
+            .announce_to(
+                &tracker.http,
+                &tracker.torrent,
+                &params(AnnounceEvent::Started, 3),
+            )
+            .await
+            .unwrap();
+        let stats = client.scrape(&tracker.http, &[]).await.unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[&tracker.torrent.info_hash].incomplete, 1);
+    }
+
+    #[test]
+    fn prune_drops_expired_peers_and_empty_swarms() {
+        let mut state = TrackerState::new(ServerConfig::default());
+        let now = Instant::now();
+        state
+            .announce(&peer_announce(1, 1, AnnounceEvent::Started), now)
+            .unwrap();
+        let later = now + DEFAULT_INTERVAL;
+        state
+            .announce(&peer_announce(1, 2, AnnounceEvent::Started), later)
+            .unwrap();
+        state
+            .announce(&peer_announce(2, 1, AnnounceEvent::Started), now)
+            .unwrap();
+
+        state.prune(now + DEFAULT_INTERVAL * 2);
+        assert_eq!(state.swarm_count(), 1);
+        assert_eq!(
+            state.scrape(&[[1; 20]])[&[1; 20]],
+            ScrapeStats {
+                complete: 0,
+                downloaded: 0,
+                incomplete: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn swarm_cap_makes_room_by_pruning() {
+        let config = ServerConfig::default().with_max_swarms(1);
+        let mut state = TrackerState::new(config);
+        let now = Instant::now();
+        state
+            .announce(&peer_announce(1, 1, AnnounceEvent::Started), now)
+            .unwrap();
+
+        let refused = state.announce(&peer_announce(2, 1, AnnounceEvent::Started), now);
+        assert!(refused.unwrap_err().contains("too many torrents"));
+        // Known swarms still accept announces
+        state
+            .announce(&peer_announce(1, 2, AnnounceEvent::Started), now)
+            .unwrap();
+
+        // Once the first swarm has expired, its slot is reused
+        let later = now + DEFAULT_INTERVAL * 2;
+        state
+            .announce(&peer_announce(2, 1, AnnounceEvent::Started), later)
+            .unwrap();
+        assert_eq!(state.swarm_count(), 1);
+    }
+
+    #[test]
+    fn full_scrape_state_is_empty_unless_enabled() {
+        let now = Instant::now();
+        let mut state = TrackerState::new(ServerConfig::default());
+        state
+            .announce(&peer_announce(1, 1, AnnounceEvent::Started), now)
+            .unwrap();
+        assert!(state.scrape(&[]).is_empty());
+
+        let mut state = TrackerState::new(ServerConfig::default().with_full_scrape(true));
+        state
+            .announce(&peer_announce(1, 1, AnnounceEvent::Started), now)
+            .unwrap();
+        assert_eq!(state.scrape(&[]).len(), 1);
+    }
+}
@@ -0,0 +1,273 @@
+//! UDP front end of the embedded tracker (BEP 15).
+//!
+//! Handles connect, announce and scrape requests on a single socket. Connection IDs are tied to
+//! the IP address they were issued to (not the port, since clients may use a new socket per
+//! request) and accepted for up to two minutes, as the protocol recommends.
+//!
+//! Connection IDs are not stored: each one is a keyed hash of the client's IP and the current
+//! time bucket, so a flood of connect requests costs the tracker no memory.
+use super::{PeerAnnounce, TrackerState};
+use crate::tracker::AnnounceEvent;
+use crate::tracker::udp::{
+    ACTION_ANNOUNCE, ACTION_CONNECT, ACTION_ERROR, ACTION_SCRAPE, MAX_SCRAPE_HASHES, PROTOCOL_ID,
+};
+use bytes::{Buf, BufMut, BytesMut};
+use sha1::{Digest, Sha1};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+
+/// Length of the time buckets connection IDs are derived from. An ID is accepted in the bucket it
+/// was issued in and the next one, so for between one and two minutes.
+const CONNECTION_ID_BUCKET: Duration = Duration::from_secs(60);
+/// Size of a fixed-length announce request.
+const ANNOUNCE_REQUEST_SIZE: usize = 98;
+
+/// Issues and checks connection IDs without remembering them.
+#[derive(Debug)]
+struct ConnectionIds {
+    secret: [u8; 32],
+    epoch: Instant,
+}
+
+impl ConnectionIds {
+    /// Creates a generator with a fresh random secret, so IDs cannot be forged by clients.
+    fn new(now: Instant) -> Self {
+        Self {
+            secret: rand::random(),
+            epoch: now,
+        }
+    }
+
+    /// Returns the connection ID for `ip` during the current time bucket.
+    fn issue(&self, ip: IpAddr, now: Instant) -> u64 {
+        self.derive(ip, self.bucket(now))
+    }
+
+    /// Returns `true` if `id` was issued to `ip` in the current or the previous time bucket.
+    fn is_valid(&self, id: u64, ip: IpAddr, now: Instant) -> bool {
+        let bucket = self.bucket(now);
+        id == self.derive(ip, bucket) || bucket > 0 && id == self.derive(ip, bucket - 1)
+    }
+
+    fn bucket(&self, now: Instant) -> u64 {
+        now.duration_since(self.epoch).as_secs() / CONNECTION_ID_BUCKET.as_secs()
+    }
+
+    fn derive(&self, ip: IpAddr, bucket: u64) -> u64 {
+        let mut hasher = Sha1::new();
+        hasher.update(self.secret);
+        hasher.update(bucket.to_be_bytes());
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => hasher.update(ip.octets()),
+            IpAddr::V6(ip) => hasher.update(ip.octets()),
+        }
+        let digest = hasher.finalize();
+        u64::from_be_bytes(digest[..8].try_into().expect("SHA-1 digests are 20 bytes"))
+    }
+}
+
+/// Answers requests until the socket fails.
+pub(super) async fn serve(socket: UdpSocket, state: Arc<Mutex<TrackerState>>) {
+    let connections = ConnectionIds::new(Instant::now());
+    let mut buffer = [0u8; 2048];
+    loop {
+        let (len, remote) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::warn!(error = %e, "UDP tracker stopped receiving");
+                return;
+            }
+        };
+        let now = Instant::now();
+        let Some(response) = handle(&buffer[..len], remote, &connections, &state, now).await else {
+            continue;
+        };
+        if let Err(e) = socket.send_to(&response, remote).await {
+            tracing::debug!(%remote, error = %e, "Failed to send UDP tracker response");
+        }
+    }
+}
+
+/// Handles one datagram; malformed packets are dropped without a reply.
+async fn handle(
+    packet: &[u8],
+    remote: SocketAddr,
+    connections: &ConnectionIds,
+    state: &Mutex<TrackerState>,
+    now: Instant,
+) -> Option<BytesMut> {
+    if packet.len() < 16 {
+        return None;
+    }
+    let mut header = &packet[..16];
+    let connection_id = header.get_u64();
+    let action = header.get_u32();
+    let transaction_id = header.get_u32();
+
+    if action == ACTION_CONNECT {
+        if connection_id != PROTOCOL_ID {
+            return None;
+        }
+        let connection_id = connections.issue(remote.ip(), now);
+        let mut response = BytesMut::with_capacity(16);
+        response.put_u32(ACTION_CONNECT);
+        response.put_u32(transaction_id);
+        response.put_u64(connection_id);
+        return Some(response);
+    }
+
+    if !connections.is_valid(connection_id, remote.ip(), now) {
+        return Some(error(transaction_id, "Invalid or expired connection ID"));
+    }
+
+    match action {
+        ACTION_ANNOUNCE => Some(announce(packet, remote, transaction_id, state, now).await),
+        ACTION_SCRAPE => Some(scrape(&packet[16..], transaction_id, state).await),
+        _ => Some(error(transaction_id, "Unknown action")),
+    }
+}
+
+/**
+Handles an announce request.
+
+# Torrent Protocol Context:
+The request carries the info hash, peer ID, transfer counters, event, IP, key, `num_want` and port
+after the 16-byte header. The response lists peers of the same address family as the request,
+6 bytes per IPv4 peer and 18 bytes per IPv6 peer.
+*/
+async fn announce(
+    packet: &[u8],
+    remote: SocketAddr,
+    transaction_id: u32,
+    state: &Mutex<TrackerState>,
+    now: Instant,
+) -> BytesMut {
+    if packet.len() < ANNOUNCE_REQUEST_SIZE {
+        return error(transaction_id, "Truncated announce request");
+    }
+    let mut body = &packet[16..ANNOUNCE_REQUEST_SIZE];
+    let mut info_hash = [0u8; 20];
+    body.copy_to_slice(&mut info_hash);
+    let mut peer_id = [0u8; 20];
+    body.copy_to_slice(&mut peer_id);
+    let downloaded = body.get_i64();
+    let left = body.get_i64();
+    let uploaded = body.get_i64();
+    let event = AnnounceEvent::from_udp_code(body.get_u32());
+    // The IP field is ignored for the same reason as HTTP's `ip` parameter
+    let _ip = body.get_u32();
+    let _key = body.get_u32();
+    let numwant = usize::try_from(body.get_i32()).ok();
+    let port = body.get_u16();
+    if port == 0 {
+        return error(transaction_id, "Invalid port");
+    }
+
+    let ip = match remote.ip() {
+        IpAddr::V6(ip) => ip.to_canonical(),
+        ip => ip,
+    };
+    let request = PeerAnnounce {
+        info_hash,
+        peer_id,
+        addr: SocketAddr::new(ip, port),
+        uploaded,
+        downloaded,
+        left,
+        event,
+        numwant,
+    };
+    let reply = match state.lock().await.announce(&request, now) {
+        Ok(reply) => reply,
+        Err(reason) => return error(transaction_id, &reason),
+    };
+
+    let mut response = BytesMut::with_capacity(20 + reply.peers.len() * 18);
+    response.put_u32(ACTION_ANNOUNCE);
+    response.put_u32(transaction_id);
+    response.put_u32(reply.interval.as_secs() as u32);
+    response.put_u32(reply.incomplete as u32);
+    response.put_u32(reply.complete as u32);
+    for peer in &reply.peers {
+        match (peer.addr, ip.is_ipv4()) {
+            (SocketAddr::V4(addr), true) => {
+                response.put_slice(&addr.ip().octets());
+                response.put_u16(addr.port());
+            }
+            (SocketAddr::V6(addr), false) => {
+                response.put_slice(&addr.ip().octets());
+                response.put_u16(addr.port());
+            }
+            _ => {}
+        }
+    }
+    response
+}
+
+/// Handles a scrape request: seeders, completed and leechers for each info hash, in request order.
+async fn scrape(body: &[u8], transaction_id: u32, state: &Mutex<TrackerState>) -> BytesMut {
+    let info_hashes: Vec<[u8; 20]> = body
+        .chunks_exact(20)
+        .take(MAX_SCRAPE_HASHES)
+        .filter_map(|chunk| <[u8; 20]>::try_from(chunk).ok())
+        .collect();
+    if info_hashes.is_empty() {
+        return error(transaction_id, "Scrape request without info hashes");
+    }
+
+    let stats = state.lock().await.scrape(&info_hashes);
+    let mut response = BytesMut::with_capacity(8 + info_hashes.len() * 12);
+    response.put_u32(ACTION_SCRAPE);
+    response.put_u32(transaction_id);
+    for info_hash in &info_hashes {
+        let stats = stats.get(info_hash).copied().unwrap_or_default();
+        response.put_u32(stats.complete as u32);
+        response.put_u32(stats.downloaded as u32);
+        response.put_u32(stats.incomplete as u32);
+    }
+    response
+}
+
+/// Builds an error response carrying a human-readable message.
+fn error(transaction_id: u32, message: &str) -> BytesMut {
+    let mut response = BytesMut::with_capacity(8 + message.len());
+    response.put_u32(ACTION_ERROR);
+    response.put_u32(transaction_id);
+    response.put_slice(message.as_bytes());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_ids_last_until_the_end_of_the_next_bucket() {
+        let start = Instant::now();
+        let ids = ConnectionIds::new(start);
+        let ip: IpAddr = "203.0.113.1".parse().unwrap();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let id = ids.issue(ip, at(30));
+        assert!(ids.is_valid(id, ip, at(30)));
+        assert!(ids.is_valid(id, ip, at(119)));
+        assert!(!ids.is_valid(id, ip, at(120)));
+        // Issued again in the next bucket, the ID changes
+        assert_ne!(ids.issue(ip, at(60)), id);
+    }
+
+    #[test]
+    fn connection_ids_are_tied_to_the_address_and_secret() {
+        let now = Instant::now();
+        let ids = ConnectionIds::new(now);
+        let ip: IpAddr = "203.0.113.1".parse().unwrap();
+        let id = ids.issue(ip, now);
+
+        assert!(!ids.is_valid(id, "203.0.113.2".parse().unwrap(), now));
+        assert!(ids.is_valid(id, "::ffff:203.0.113.1".parse().unwrap(), now));
+        assert!(!ConnectionIds::new(now).is_valid(id, ip, now));
+    }
+}
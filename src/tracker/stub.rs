@@ -0,0 +1,164 @@
+//! Stub HTTP tracker shared by the tracker tests.
+//!
+//! The stub answers every request on a local port with whatever the test's `respond` closure
+//! builds, and records the requests so tests can check what was sent and when.
+use crate::bencode::{BencodeValue, decoder, encoder};
+use crate::torrent::file::TorrentFile;
+use std::collections::HashMap;
+use std::net::SocketAddrV4;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Instant;
+
+/// A request received by the stub.
+#[derive(Debug, Clone)]
+pub(crate) struct StubRequest {
+    /// The query string, still percent-encoded.
+    pub query: String,
+    /// The `User-Agent` header, if sent.
+    pub user_agent: Option<String>,
+    /// When the request arrived, on the tokio clock.
+    pub at: Instant,
+}
+
+impl StubRequest {
+    /// Returns a query parameter, still percent-encoded.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+    }
+
+    /// Returns the `event` parameter, or `""` for a regular announce.
+    pub fn event(&self) -> &str {
+        self.param("event").unwrap_or_default()
+    }
+}
+
+type Respond = dyn Fn(&StubRequest) -> Option<Vec<u8>> + Send + Sync;
+
+/// A tracker on a local port. A `None` response leaves the request unanswered.
+pub(crate) struct StubTracker {
+    pub url: String,
+    requests: Arc<Mutex<Vec<StubRequest>>>,
+}
+
+impl StubTracker {
+    pub async fn serve(
+        respond: impl Fn(&StubRequest) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond: Arc<Respond> = Arc::new(respond);
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(answer(stream, respond.clone(), recorded.clone()));
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// Returns the requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<StubRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Reads one request from `stream` and answers it.
+async fn answer(
+    mut stream: TcpStream,
+    respond: Arc<Respond>,
+    requests: Arc<Mutex<Vec<StubRequest>>>,
+) {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => head.extend_from_slice(&buffer[..n]),
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let target = head.split_whitespace().nth(1).unwrap_or_default();
+    let request = StubRequest {
+        query: target
+            .split_once('?')
+            .map(|(_, query)| query.to_string())
+            .unwrap_or_default(),
+        user_agent: head.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("user-agent")
+                .then(|| value.trim().to_string())
+        }),
+        at: Instant::now(),
+    };
+    let body = respond(&request);
+    requests.lock().unwrap().push(request);
+
+    let Some(body) = body else {
+        // Hold the connection open so the client has to time out
+        std::future::pending::<()>().await;
+        return;
+    };
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let _ = stream.write_all(header.as_bytes()).await;
+    let _ = stream.write_all(&body).await;
+}
+
+/// Bencodes a tracker response dictionary.
+pub(crate) fn response(entries: Vec<(&str, BencodeValue)>) -> Vec<u8> {
+    let dict: HashMap<Vec<u8>, BencodeValue> = entries
+        .into_iter()
+        .map(|(key, value)| (key.as_bytes().to_vec(), value))
+        .collect();
+    let mut bytes = Vec::new();
+    encoder::encode(&mut bytes, &BencodeValue::Dict(dict)).unwrap();
+    bytes
+}
+
+/// A successful announce response with compact IPv4 peers.
+pub(crate) fn peers_response(interval: i64, peers: &[SocketAddrV4]) -> Vec<u8> {
+    response(vec![
+        ("interval", BencodeValue::Integer(interval)),
+        ("peers", compact_peers(peers)),
+    ])
+}
+
+/// A failed announce response.
+pub(crate) fn failure_response(reason: &str) -> Vec<u8> {
+    response(vec![(
+        "failure reason",
+        BencodeValue::String(reason.as_bytes().to_vec()),
+    )])
+}
+
+/// Compact IPv4 peer string.
+pub(crate) fn compact_peers(peers: &[SocketAddrV4]) -> BencodeValue {
+    BencodeValue::String(
+        peers
+            .iter()
+            .flat_map(|peer| {
+                let mut entry = peer.ip().octets().to_vec();
+                entry.extend_from_slice(&peer.port().to_be_bytes());
+                entry
+            })
+            .collect(),
+    )
+}
+
+/// A single-file torrent announcing to `announce`.
+pub(crate) fn torrent(announce: &str) -> TorrentFile {
+    let raw = format!(
+        "d8:announce{}:{}4:infod6:lengthi3e4:name1:n12:piece lengthi16384e6:pieces20:{}ee",
+        announce.len(),
+        announce,
+        "a".repeat(20)
+    );
+    TorrentFile::parse(decoder::decode(raw.as_bytes()).unwrap()).unwrap()
+}
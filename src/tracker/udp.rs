@@ -0,0 +1,682 @@
+//! UDP tracker protocol (BEP 15).
+//!
+//! This module implements the connect, announce and scrape exchanges of the UDP tracker protocol, including:
+//! - One socket per tracker, kept with its connection ID (an ID may be reused for one minute)
+//! - Random transaction IDs, checked against every response
+//! - Retransmission with exponential backoff, waiting `15 * 2^n` seconds for attempt `n` with up to
+//!   8 retries as the spec describes
+//!
+//! Responses are parsed into the same `AnnounceResponse` used by HTTP trackers.
+use super::{
+    AnnounceRequest, AnnounceResponse, ScrapeStats, parse_compact_peers_v4, parse_compact_peers_v6,
+};
+use crate::torrent::TorrentError;
+use anyhow::Result;
+use bytes::{Buf, BufMut, BytesMut};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+
+/// Magic constant identifying the protocol in connect requests.
+pub(crate) const PROTOCOL_ID: u64 = 0x41727101980;
+
+pub(crate) const ACTION_CONNECT: u32 = 0;
+pub(crate) const ACTION_ANNOUNCE: u32 = 1;
+pub(crate) const ACTION_SCRAPE: u32 = 2;
+pub(crate) const ACTION_ERROR: u32 = 3;
+
+/// How long a connection ID stays valid after it was received.
+const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Base timeout of the retransmission schedule; attempt `n` waits `15 * 2^n` seconds.
+pub const DEFAULT_BASE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Number of retransmissions after the first attempt, as given by BEP 15.
+///
+/// The full schedule runs for over two hours; callers that cannot wait that long should pick a
+/// shorter one with `Client::with_udp_retransmission`.
+pub const DEFAULT_MAX_RETRIES: u32 = 8;
+
+/// Largest datagram we expect from a tracker; leaves room for a few hundred peers.
+const MAX_PACKET_SIZE: usize = 8192;
+
+/// Maximum number of info hashes a single scrape request may carry.
+pub const MAX_SCRAPE_HASHES: usize = 74;
+
+/// Client side of the UDP tracker protocol, shared by every UDP announce and scrape.
+#[derive(Debug)]
+pub struct UdpTrackerClient {
+    trackers: Mutex<HashMap<SocketAddr, Arc<Mutex<TrackerConnection>>>>,
+    base_timeout: Duration,
+    max_retries: u32,
+}
+
+/// Our end of the conversation with one tracker.
+///
+/// Trackers may tie a connection ID to the address and port it was issued to, so the socket is
+/// kept for the life of the client and every request to the tracker goes through it.
+#[derive(Debug)]
+struct TrackerConnection {
+    socket: UdpSocket,
+    /// The current connection ID and when it was received.
+    connection_id: Option<(u64, Instant)>,
+}
+
+impl TrackerConnection {
+    /// Returns the connection ID if it is still within its lifetime, forgetting it otherwise.
+    fn connection_id(&mut self) -> Option<u64> {
+        match self.connection_id {
+            Some((id, received)) if received.elapsed() < CONNECTION_ID_LIFETIME => Some(id),
+            _ => {
+                self.connection_id = None;
+                None
+            }
+        }
+    }
+}
+
+impl Default for UdpTrackerClient {
+    fn default() -> Self {
+        Self::new(DEFAULT_BASE_TIMEOUT, DEFAULT_MAX_RETRIES)
+    }
+}
+
+impl UdpTrackerClient {
+    /// Creates a client with a custom retransmission schedule.
+    pub fn new(base_timeout: Duration, max_retries: u32) -> Self {
+        Self {
+            trackers: Mutex::new(HashMap::new()),
+            base_timeout,
+            max_retries,
+        }
+    }
+
+    /// Sends an announce request to a `udp://` tracker and parses the response.
+    ///
+    /// # Arguments
+    /// * `url` - The tracker URL, e.g. `udp://tracker.example.org:6969/announce`
+    /// * `request` - The announce parameters; `trackerid` and `no_peer_id` have no UDP equivalent
+    ///
+    /// # Returns
+    /// * `Result<AnnounceResponse>` - The interval, swarm counts and peers sent by the tracker
+    #[tracing::instrument(skip(self, request), level = "debug")]
+    pub async fn announce(
+        &self,
+        url: &url::Url,
+        request: &AnnounceRequest,
+    ) -> Result<AnnounceResponse> {
+        let addr = resolve(url).await?;
+        let tracker = self.tracker(addr).await?;
+        let mut tracker = tracker.lock().await;
+
+        let response = self
+            .exchange(
+                &mut tracker,
+                ACTION_ANNOUNCE,
+                |connection_id, transaction_id| {
+                    let mut packet = BytesMut::with_capacity(98);
+                    packet.put_u64(connection_id);
+                    packet.put_u32(ACTION_ANNOUNCE);
+                    packet.put_u32(transaction_id);
+                    packet.put_slice(&request.info_hash);
+                    packet.put_slice(&request.peer_id);
+                    packet.put_i64(request.downloaded);
+                    packet.put_i64(request.left);
+                    packet.put_i64(request.uploaded);
+                    packet.put_u32(request.event.udp_code());
+                    // IP address: 0 lets the tracker use the packet's source; IPv6 trackers ignore it
+                    let ip = match request.ipv4 {
+                        Some(ipv4) if !addr.is_ipv6() => u32::from(ipv4),
+                        _ => 0,
+                    };
+                    packet.put_u32(ip);
+                    packet.put_u32(request.key);
+                    // -1 asks the tracker for its default number of peers
+                    packet.put_i32(
+                        request
+                            .numwant
+                            .map_or(-1, |n| n.min(i32::MAX as u32) as i32),
+                    );
+                    packet.put_u16(request.port);
+                    packet
+                },
+            )
+            .await?;
+
+        parse_announce(&response, addr.is_ipv6())
+    }
+
+    /// Sends a scrape request for up to `MAX_SCRAPE_HASHES` info hashes.
+    ///
+    /// # Returns
+    /// * `Result<Vec<ScrapeStats>>` - One entry per info hash, in request order
+    #[tracing::instrument(skip(self, info_hashes), level = "debug")]
+    pub async fn scrape(
+        &self,
+        url: &url::Url,
+        info_hashes: &[[u8; 20]],
+    ) -> Result<Vec<ScrapeStats>> {
+        if info_hashes.len() > MAX_SCRAPE_HASHES {
+            return Err(TorrentError::InvalidFormat(format!(
+                "UDP scrape supports at most {} info hashes",
+                MAX_SCRAPE_HASHES
+            ))
+            .into());
+        }
+        let addr = resolve(url).await?;
+        let tracker = self.tracker(addr).await?;
+        let mut tracker = tracker.lock().await;
+
+        let response = self
+            .exchange(
+                &mut tracker,
+                ACTION_SCRAPE,
+                |connection_id, transaction_id| {
+                    let mut packet = BytesMut::with_capacity(16 + 20 * info_hashes.len());
+                    packet.put_u64(connection_id);
+                    packet.put_u32(ACTION_SCRAPE);
+                    packet.put_u32(transaction_id);
+                    for info_hash in info_hashes {
+                        packet.put_slice(info_hash);
+                    }
+                    packet
+                },
+            )
+            .await?;
+
+        let mut body = response.as_slice();
+        let mut stats = Vec::with_capacity(info_hashes.len());
+        while body.remaining() >= 12 && stats.len() < info_hashes.len() {
+            let complete = body.get_u32() as i64;
+            let downloaded = body.get_u32() as i64;
+            let incomplete = body.get_u32() as i64;
+            stats.push(ScrapeStats {
+                complete,
+                downloaded,
+                incomplete,
+            });
+        }
+        if stats.len() != info_hashes.len() {
+            return Err(TorrentError::InvalidTrackerResponse(
+                "Truncated UDP scrape response".to_string(),
+            )
+            .into());
+        }
+        Ok(stats)
+    }
+
+    /// Returns the connection to the tracker at `addr`, binding its socket on first use.
+    ///
+    /// Requests to the same tracker take turns on its connection, so responses are never read by
+    /// the wrong request.
+    async fn tracker(&self, addr: SocketAddr) -> Result<Arc<Mutex<TrackerConnection>>> {
+        let mut trackers = self.trackers.lock().await;
+        if let Some(tracker) = trackers.get(&addr) {
+            return Ok(tracker.clone());
+        }
+        let tracker = Arc::new(Mutex::new(TrackerConnection {
+            socket: bind_for(addr).await?,
+            connection_id: None,
+        }));
+        trackers.insert(addr, tracker.clone());
+        Ok(tracker)
+    }
+
+    /// Runs one request/response exchange, connecting first if there is no valid connection ID.
+    ///
+    /// The `build` closure receives the connection ID and a fresh transaction ID and returns the request packet.
+    /// Returns the response body after the 8-byte action/transaction header.
+    async fn exchange<F>(
+        &self,
+        tracker: &mut TrackerConnection,
+        action: u32,
+        build: F,
+    ) -> Result<Vec<u8>>
+    where
+        F: Fn(u64, u32) -> BytesMut,
+    {
+        let mut attempt = 0;
+        while attempt <= self.max_retries {
+            let connection_id = match tracker.connection_id() {
+                Some(id) => id,
+                None => match self.connect(tracker, &mut attempt).await? {
+                    Some(id) => id,
+                    None => break,
+                },
+            };
+
+            let transaction_id = rand::random::<u32>();
+            let packet = build(connection_id, transaction_id);
+            tracker.socket.send(&packet).await?;
+
+            match self
+                .receive(&tracker.socket, transaction_id, self.timeout_for(attempt))
+                .await?
+            {
+                Some((ACTION_ERROR, body)) => {
+                    return Err(TorrentError::TrackerFailure(
+                        String::from_utf8_lossy(&body).into_owned(),
+                    )
+                    .into());
+                }
+                Some((received, body)) if received == action => return Ok(body),
+                Some(_) => {
+                    return Err(TorrentError::InvalidTrackerResponse(
+                        "Unexpected action in UDP tracker response".to_string(),
+                    )
+                    .into());
+                }
+                None => {
+                    // The connection ID may have expired on the tracker side; reconnect next time
+                    tracing::debug!(attempt, "UDP tracker request timed out");
+                    tracker.connection_id = None;
+                    attempt += 1;
+                }
+            }
+        }
+        let addr = tracker.socket.peer_addr()?;
+        Err(TorrentError::TrackerFailure(format!("UDP tracker {} did not respond", addr)).into())
+    }
+
+    /// Performs the connect exchange, retrying with backoff. Returns `None` once retries are exhausted.
+    async fn connect(
+        &self,
+        tracker: &mut TrackerConnection,
+        attempt: &mut u32,
+    ) -> Result<Option<u64>> {
+        while *attempt <= self.max_retries {
+            let transaction_id = rand::random::<u32>();
+            let mut packet = BytesMut::with_capacity(16);
+            packet.put_u64(PROTOCOL_ID);
+            packet.put_u32(ACTION_CONNECT);
+            packet.put_u32(transaction_id);
+            tracker.socket.send(&packet).await?;
+
+            match self
+                .receive(&tracker.socket, transaction_id, self.timeout_for(*attempt))
+                .await?
+            {
+                Some((ACTION_CONNECT, body)) if body.len() >= 8 => {
+                    let connection_id = (&body[..8]).get_u64();
+                    tracker.connection_id = Some((connection_id, Instant::now()));
+                    return Ok(Some(connection_id));
+                }
+                Some((ACTION_ERROR, body)) => {
+                    return Err(TorrentError::TrackerFailure(
+                        String::from_utf8_lossy(&body).into_owned(),
+                    )
+                    .into());
+                }
+                Some(_) => {
+                    return Err(TorrentError::InvalidTrackerResponse(
+                        "Invalid UDP connect response".to_string(),
+                    )
+                    .into());
+                }
+                None => {
+                    tracing::debug!(attempt = *attempt, "UDP tracker connect timed out");
+                    *attempt += 1;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits for a response with a matching transaction ID, ignoring stray datagrams.
+    ///
+    /// Returns `None` on timeout, otherwise the action and the body after the header.
+    async fn receive(
+        &self,
+        socket: &UdpSocket,
+        transaction_id: u32,
+        timeout: Duration,
+    ) -> Result<Option<(u32, Vec<u8>)>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+        loop {
+            let len = match tokio::time::timeout_at(deadline, socket.recv(&mut buffer)).await {
+                Ok(result) => result?,
+                Err(_) => return Ok(None),
+            };
+            if len < 8 {
+                continue;
+            }
+            let mut header = &buffer[..8];
+            let action = header.get_u32();
+            if header.get_u32() != transaction_id {
+                continue;
+            }
+            return Ok(Some((action, buffer[8..len].to_vec())));
+        }
+    }
+
+    /// Timeout for attempt `n`: `base * 2^n`.
+    fn timeout_for(&self, attempt: u32) -> Duration {
+        self.base_timeout * 2u32.saturating_pow(attempt)
+    }
+}
+
+/// Parses the body of an announce response (after the action/transaction header).
+///
+/// Peers are 6 bytes each for IPv4 trackers and 18 bytes each for IPv6 trackers.
+fn parse_announce(body: &[u8], ipv6: bool) -> Result<AnnounceResponse> {
+    if body.len() < 12 {
+        return Err(TorrentError::InvalidTrackerResponse(
+            "Truncated UDP announce response".to_string(),
+        )
+        .into());
+    }
+    let mut header = &body[..12];
+    let interval = header.get_u32() as i64;
+    let incomplete = header.get_u32() as i64;
+    let complete = header.get_u32() as i64;
+
+    let peers = if ipv6 {
+        parse_compact_peers_v6(&body[12..])
+    } else {
+        parse_compact_peers_v4(&body[12..])
+    };
+
+    Ok(AnnounceResponse {
+        interval,
+        complete: Some(complete),
+        incomplete: Some(incomplete),
+        peers,
+        ..Default::default()
+    })
+}
+
+/// Resolves the host and port of a `udp://` tracker URL.
+async fn resolve(url: &url::Url) -> Result<SocketAddr> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| TorrentError::InvalidFormat(format!("Tracker URL has no host: {}", url)))?;
+    let port = url
+        .port()
+        .ok_or_else(|| TorrentError::InvalidFormat(format!("Tracker URL has no port: {}", url)))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| {
+            TorrentError::InvalidFormat(format!("Could not resolve tracker: {}", url)).into()
+        })
+}
+
+/// Binds an ephemeral socket of the same address family as the tracker and connects it.
+async fn bind_for(addr: SocketAddr) -> Result<UdpSocket> {
+    let local: SocketAddr = if addr.is_ipv6() {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(addr).await?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::{AnnounceEvent, Peer};
+    use std::net::IpAddr;
+
+    const INFO_HASH: [u8; 20] = [7; 20];
+
+    /// Binds a stub tracker on localhost and returns it with its URL.
+    async fn stub() -> (UdpSocket, url::Url) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+        (socket, url::Url::parse(&url).unwrap())
+    }
+
+    /// Receives the next request sent to the stub.
+    async fn recv(socket: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+        let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+        let (len, from) =
+            tokio::time::timeout(Duration::from_secs(5), socket.recv_from(&mut buffer))
+                .await
+                .expect("client sent nothing")
+                .unwrap();
+        buffer.truncate(len);
+        (buffer, from)
+    }
+
+    /// Answers `request` with `action`, echoing its transaction ID.
+    async fn reply(socket: &UdpSocket, to: SocketAddr, request: &[u8], action: u32, body: &[u8]) {
+        let mut packet = BytesMut::new();
+        packet.put_u32(action);
+        packet.put_slice(&request[12..16]);
+        packet.put_slice(body);
+        socket.send_to(&packet, to).await.unwrap();
+    }
+
+    /// Receives a connect request and hands out `connection_id`.
+    async fn accept_connect(socket: &UdpSocket, connection_id: u64) -> SocketAddr {
+        let (request, from) = recv(socket).await;
+        assert_eq!(request.len(), 16);
+        assert_eq!((&request[..8]).get_u64(), PROTOCOL_ID);
+        assert_eq!((&request[8..12]).get_u32(), ACTION_CONNECT);
+        reply(
+            socket,
+            from,
+            &request,
+            ACTION_CONNECT,
+            &connection_id.to_be_bytes(),
+        )
+        .await;
+        from
+    }
+
+    /// Receives an announce request, checks its connection ID and answers with one peer.
+    async fn accept_announce(socket: &UdpSocket, connection_id: u64) -> SocketAddr {
+        let (request, from) = recv(socket).await;
+        assert_eq!(request.len(), 98);
+        assert_eq!((&request[..8]).get_u64(), connection_id);
+        assert_eq!((&request[8..12]).get_u32(), ACTION_ANNOUNCE);
+        assert_eq!(&request[16..36], &INFO_HASH);
+        assert_eq!((&request[96..98]).get_u16(), 6881);
+        let mut body = BytesMut::new();
+        body.put_u32(1800);
+        body.put_u32(3);
+        body.put_u32(5);
+        body.put_slice(&[10, 0, 0, 1, 0x1a, 0xe1]);
+        reply(socket, from, &request, ACTION_ANNOUNCE, &body).await;
+        from
+    }
+
+    fn client() -> UdpTrackerClient {
+        UdpTrackerClient::new(Duration::from_millis(100), 2)
+    }
+
+    fn request() -> AnnounceRequest {
+        AnnounceRequest {
+            info_hash: INFO_HASH,
+            peer_id: [1; 20],
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            compact: true,
+            left: 1000,
+            event: AnnounceEvent::Started,
+            numwant: None,
+            key: 9,
+            trackerid: None,
+            no_peer_id: false,
+            ipv4: None,
+            ipv6: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_then_announce() {
+        let (socket, url) = stub().await;
+        let tracker = tokio::spawn(async move {
+            accept_connect(&socket, 42).await;
+            accept_announce(&socket, 42).await;
+        });
+
+        let response = client().announce(&url, &request()).await.unwrap();
+        tracker.await.unwrap();
+        assert_eq!(response.interval, 1800);
+        assert_eq!(response.incomplete, Some(3));
+        assert_eq!(response.complete, Some(5));
+        assert_eq!(
+            response.peers,
+            vec![Peer {
+                ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                port: 6881,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_id_is_reused_from_the_same_socket() {
+        let (socket, url) = stub().await;
+        let tracker = tokio::spawn(async move {
+            let connected = accept_connect(&socket, 42).await;
+            assert_eq!(accept_announce(&socket, 42).await, connected);
+            assert_eq!(accept_announce(&socket, 42).await, connected);
+        });
+
+        let client = client();
+        client.announce(&url, &request()).await.unwrap();
+        client.announce(&url, &request()).await.unwrap();
+        tracker.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn scrape_returns_stats_in_request_order() {
+        let (socket, url) = stub().await;
+        let tracker = tokio::spawn(async move {
+            accept_connect(&socket, 42).await;
+            let (request, from) = recv(&socket).await;
+            assert_eq!((&request[..8]).get_u64(), 42);
+            assert_eq!((&request[8..12]).get_u32(), ACTION_SCRAPE);
+            assert_eq!(&request[16..], [[1u8; 20], [2u8; 20]].concat());
+            let mut body = BytesMut::new();
+            for stats in [[5u32, 10, 3], [0, 1, 2]] {
+                for value in stats {
+                    body.put_u32(value);
+                }
+            }
+            reply(&socket, from, &request, ACTION_SCRAPE, &body).await;
+        });
+
+        let stats = client().scrape(&url, &[[1; 20], [2; 20]]).await.unwrap();
+        tracker.await.unwrap();
+        assert_eq!(
+            stats,
+            vec![
+                ScrapeStats {
+                    complete: 5,
+                    downloaded: 10,
+                    incomplete: 3,
+                },
+                ScrapeStats {
+                    complete: 0,
+                    downloaded: 1,
+                    incomplete: 2,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lost_requests_are_retransmitted() {
+        let (socket, url) = stub().await;
+        let tracker = tokio::spawn(async move {
+            // Drop the first connect request
+            let (first, _) = recv(&socket).await;
+            assert_eq!((&first[8..12]).get_u32(), ACTION_CONNECT);
+            accept_connect(&socket, 42).await;
+            accept_announce(&socket, 42).await;
+        });
+
+        client().announce(&url, &request()).await.unwrap();
+        tracker.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_retry() {
+        let (socket, url) = stub().await;
+        let client = UdpTrackerClient::new(Duration::from_millis(20), 1);
+
+        let error = client.announce(&url, &request()).await.unwrap_err();
+        assert!(error.to_string().contains("did not respond"), "{}", error);
+        // The first attempt and one retransmission
+        recv(&socket).await;
+        recv(&socket).await;
+        let mut buffer = [0u8; 16];
+        assert!(socket.try_recv_from(&mut buffer).is_err());
+    }
+
+    #[tokio::test]
+    async fn error_action_is_reported() {
+        let (socket, url) = stub().await;
+        let tracker = tokio::spawn(async move {
+            accept_connect(&socket, 42).await;
+            let (request, from) = recv(&socket).await;
+            reply(
+                &socket,
+                from,
+                &request,
+                ACTION_ERROR,
+                b"unregistered torrent",
+            )
+            .await;
+        });
+
+        let error = client().announce(&url, &request()).await.unwrap_err();
+        tracker.await.unwrap();
+        assert!(matches!(
+            error.downcast_ref::<TorrentError>(),
+            Some(TorrentError::TrackerFailure(message)) if message == "unregistered torrent"
+        ));
+    }
+
+    #[tokio::test]
+    async fn expired_connection_id_is_renewed() {
+        let (socket, url) = stub().await;
+        let addr = socket.local_addr().unwrap();
+        let tracker = tokio::spawn(async move {
+            accept_connect(&socket, 42).await;
+            accept_announce(&socket, 42).await;
+            accept_connect(&socket, 43).await;
+            accept_announce(&socket, 43).await;
+        });
+
+        let client = client();
+        client.announce(&url, &request()).await.unwrap();
+        {
+            let trackers = client.trackers.lock().await;
+            let mut connection = trackers[&addr].lock().await;
+            let received = Instant::now() - CONNECTION_ID_LIFETIME - Duration::from_secs(1);
+            connection.connection_id = Some((42, received));
+        }
+        client.announce(&url, &request()).await.unwrap();
+        tracker.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconnects_when_the_tracker_forgot_the_connection_id() {
+        let (socket, url) = stub().await;
+        let tracker = tokio::spawn(async move {
+            accept_connect(&socket, 42).await;
+            accept_announce(&socket, 42).await;
+            // The tracker has since dropped ID 42 and ignores requests carrying it
+            let (stale, _) = recv(&socket).await;
+            assert_eq!((&stale[..8]).get_u64(), 42);
+            accept_connect(&socket, 43).await;
+            accept_announce(&socket, 43).await;
+        });
+
+        let client = client();
+        client.announce(&url, &request()).await.unwrap();
+        client.announce(&url, &request()).await.unwrap();
+        tracker.await.unwrap();
+    }
+}